@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use tokio::sync::RwLock;
+
+use crate::auth::{HubSet, ServiceAccount, DEFAULT_PROFILE};
+use crate::cache::{CacheLayer, CacheScope};
+use crate::calendar::CalendarClient;
+use crate::classroom::ClassroomClient;
+use crate::config::CacheConfig;
+use crate::crypto::Cipher;
+use crate::drive::DriveClient;
+use crate::error::AppError;
+use crate::scopes::ToolGroup;
+use crate::search::SearchIndex;
+#[cfg(feature = "semantic-search")]
+use crate::semantic::SemanticIndex;
+use crate::session::ManagedAuth;
+use crate::store::{store_path, Store};
+use crate::usage::{ApiUsage, Usage};
+
+/// The API clients for one authenticated Google account: a sign-in profile or
+/// a Workspace user impersonated through a service account.
+#[derive(Debug)]
+pub struct Account {
+    pub profile: String,
+    pub classroom: ClassroomClient,
+    pub drive: DriveClient,
+    pub calendar: CalendarClient,
+    /// The account's local store: the disk cache and a snapshot of fetched data.
+    pub store: Store,
+    /// Full-text index of the store's course content.
+    pub search: SearchIndex,
+    /// Passage embeddings of the store's course content.
+    #[cfg(feature = "semantic-search")]
+    pub semantic: SemanticIndex,
+    /// `None` when serving offline.
+    auth: Option<ManagedAuth>,
+}
+
+impl Account {
+    pub fn new(
+        profile: &str,
+        hubs: HubSet,
+        usage: &Usage,
+        cache_config: &CacheConfig,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<Self, AppError> {
+        let store = Store::open(&store_path(profile)?, cipher)?;
+        let cache = |api: &Arc<ApiUsage>| {
+            let layer = CacheLayer::new(api.clone(), store.clone(), cache_config);
+            if hubs.auth.is_none() {
+                layer.offline()
+            } else {
+                layer
+            }
+        };
+        Ok(Self {
+            profile: profile.to_string(),
+            classroom: ClassroomClient::new(
+                hubs.classroom,
+                usage.classroom.clone(),
+                cache(&usage.classroom),
+                store.clone(),
+            ),
+            drive: DriveClient::new(
+                hubs.drive,
+                usage.drive.clone(),
+                cache(&usage.drive),
+                store.clone(),
+                hubs.auth.clone(),
+            ),
+            calendar: CalendarClient::new(
+                hubs.calendar,
+                usage.calendar.clone(),
+                cache(&usage.calendar),
+                store.clone(),
+                hubs.auth.clone(),
+            ),
+            search: SearchIndex::new(store.clone()),
+            #[cfg(feature = "semantic-search")]
+            semantic: SemanticIndex::new(store.clone()),
+            store,
+            auth: hubs.auth,
+        })
+    }
+
+    /// Drop this account's cached entries in `scope`, returning how many were removed.
+    pub async fn clear_cache(&self, scope: &CacheScope) -> usize {
+        self.classroom.cache().clear(scope).await
+            + self.drive.cache().clear(scope).await
+            + self.calendar.cache().clear(scope).await
+    }
+
+    /// Whether the account serves only locally stored data (`run --offline`).
+    pub fn is_offline(&self) -> bool {
+        self.auth.is_none()
+    }
+
+    /// Fail with [`AppError::AuthExpired`] if this account's credentials stopped
+    /// working, or [`AppError::MissingScope`] unless it granted `group`'s scopes.
+    /// Offline, where no credentials are used, every group is allowed.
+    pub fn require(&self, group: ToolGroup) -> Result<(), AppError> {
+        let Some(auth) = &self.auth else {
+            return Ok(());
+        };
+        auth.check()?;
+        if group.is_granted(&auth.granted_scopes()) {
+            Ok(())
+        } else {
+            Err(auth.missing_scope(group))
+        }
+    }
+
+    /// Names of the tool groups this account has granted (all of them offline).
+    pub fn granted_groups(&self) -> Vec<&'static str> {
+        let granted = self.auth.as_ref().map(ManagedAuth::granted_scopes);
+        ToolGroup::ALL
+            .iter()
+            .filter(|g| granted.as_ref().is_none_or(|granted| g.is_granted(granted)))
+            .map(|g| g.name())
+            .collect()
+    }
+}
+
+/// All configured accounts, keyed by profile name (or email address for
+/// service-account users).
+#[derive(Debug)]
+pub struct AccountRegistry {
+    accounts: RwLock<BTreeMap<String, Arc<Account>>>,
+    default: Option<String>,
+    service_account: Option<ServiceAccount>,
+    usage: Arc<Usage>,
+    cache_config: CacheConfig,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl AccountRegistry {
+    /// Build a registry from the given accounts.
+    ///
+    /// When a tool call doesn't name an account, `default` (from
+    /// `config.json`) is used, else the `default` profile, else the first
+    /// account in alphabetical order. With a delegating service account, more
+    /// accounts are added on demand as tools name users of its domain.
+    pub fn new(
+        accounts: Vec<Account>,
+        default: Option<String>,
+        service_account: Option<ServiceAccount>,
+        usage: Arc<Usage>,
+        cache_config: CacheConfig,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<Self, AppError> {
+        let accounts: BTreeMap<_, _> = accounts
+            .into_iter()
+            .map(|a| (a.profile.clone(), Arc::new(a)))
+            .collect();
+        if accounts.is_empty() && service_account.is_none() {
+            return Err(AppError::NotAuthenticated);
+        }
+
+        let default = default.or_else(|| {
+            if accounts.contains_key(DEFAULT_PROFILE) {
+                Some(DEFAULT_PROFILE.to_string())
+            } else {
+                accounts.keys().next().cloned()
+            }
+        });
+
+        Ok(Self {
+            accounts: RwLock::new(accounts),
+            default,
+            service_account,
+            usage,
+            cache_config,
+            cipher,
+        })
+    }
+
+    /// Look up an account by name, falling back to the default account.
+    ///
+    /// Unknown email addresses in the service account's delegated domain get
+    /// an account built on the spot.
+    pub async fn get(&self, name: Option<&str>) -> Result<Arc<Account>, AppError> {
+        let name = match name.or(self.default.as_deref()) {
+            Some(name) => name,
+            None => {
+                return Err(AppError::InvalidInput(
+                    "no default account configured — pass `account`".into(),
+                ));
+            }
+        };
+        if let Some(account) = self.accounts.read().await.get(name) {
+            return Ok(account.clone());
+        }
+
+        match &self.service_account {
+            Some(sa) if sa.can_impersonate(name) => {
+                let name = name.to_ascii_lowercase();
+                if let Some(account) = self.accounts.read().await.get(&name) {
+                    return Ok(account.clone());
+                }
+                // Fetching the impersonation token can take a while; build the
+                // account unlocked so calls for other accounts aren't held up.
+                let hubs = sa.hubs_for(&name).await?;
+                let account = Arc::new(Account::new(
+                    &name,
+                    hubs,
+                    &self.usage,
+                    &self.cache_config,
+                    self.cipher.clone(),
+                )?);
+                // Another call may have built the same account meanwhile.
+                let mut accounts = self.accounts.write().await;
+                Ok(accounts.entry(name).or_insert(account).clone())
+            }
+            _ => Err(AppError::InvalidInput(format!(
+                "unknown account '{name}' — configured accounts: {}",
+                self.accounts
+                    .read()
+                    .await
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+
+    /// Request counts, throttling and cache statistics shared by all accounts.
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    /// List configured accounts with the email address each is signed in as.
+    pub async fn describe(&self) -> Value {
+        let accounts: Vec<_> = self.accounts.read().await.values().cloned().collect();
+        let mut out = Vec::new();
+        for account in accounts {
+            let (email, display_name, error) = match account.require(ToolGroup::Drive) {
+                Err(e) => (Value::Null, Value::Null, Some(e.to_string())),
+                _ => match account.drive.current_user(false).await {
+                    // Offline results are wrapped as `{asOf, data}`.
+                    Ok(user) if account.is_offline() => (
+                        user["data"]["email"].clone(),
+                        user["data"]["displayName"].clone(),
+                        None,
+                    ),
+                    Ok(user) => (user["email"].clone(), user["displayName"].clone(), None),
+                    Err(e) => (Value::Null, Value::Null, Some(e.to_string())),
+                },
+            };
+            out.push(json!({
+                "account": account.profile,
+                "default": self.default.as_deref() == Some(account.profile.as_str()),
+                "email": email,
+                "displayName": display_name,
+                "grantedToolGroups": account.granted_groups(),
+                "auth": account
+                    .auth
+                    .as_ref()
+                    .map_or_else(|| json!({ "status": "offline" }), ManagedAuth::describe),
+                "error": error,
+            }));
+        }
+        Value::Array(out)
+    }
+}
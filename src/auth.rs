@@ -1,18 +1,35 @@
+use std::collections::BTreeSet;
 use std::future::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
 
+use chrono::{DateTime, Duration, Utc};
 use google_calendar3::CalendarHub;
+use google_classroom1::common::NoToken;
 use google_classroom1::Classroom;
 use google_drive3::DriveHub;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
 use yup_oauth2::authenticator_delegate::InstalledFlowDelegate;
-use yup_oauth2::{InstalledFlowAuthenticator, InstalledFlowReturnMethod, read_application_secret};
+use yup_oauth2::{
+    read_application_secret, read_service_account_key, InstalledFlowAuthenticator,
+    InstalledFlowReturnMethod, ServiceAccountAuthenticator, ServiceAccountKey,
+};
 
+use crate::config::{config_dir, Config};
+use crate::crypto::{self, Cipher};
 use crate::error::AppError;
+use crate::scopes::{scopes_for, ToolGroup, DRIVE_READONLY};
+use crate::session::{AuthHealth, AuthSource, ManagedAuth};
+use crate::token_store::{read_stored_tokens, FileTokenStorage};
 
 /// Custom delegate that prevents interactive browser auth in server mode.
 /// Logs to stderr instead of printing to stdout (which would corrupt MCP transport).
-struct ServerFlowDelegate;
+struct ServerFlowDelegate {
+    profile: String,
+}
 
 impl InstalledFlowDelegate for ServerFlowDelegate {
     fn present_user_url<'a>(
@@ -22,52 +39,254 @@ impl InstalledFlowDelegate for ServerFlowDelegate {
     ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>> {
         Box::pin(async {
             tracing::error!(
-                "Token refresh failed — re-authenticate: personal-google-mcp auth"
+                "Token refresh failed — re-authenticate: personal-google-mcp auth --profile {}",
+                self.profile
             );
             Err("interactive auth not available in server mode".into())
         })
     }
 }
 
-const OAUTH_REDIRECT_PORT: u16 = 8085;
+/// Default local port Google redirects to after consent (`auth --redirect-port`).
+pub const OAUTH_REDIRECT_PORT: u16 = 8085;
 
-pub const SCOPES: &[&str] = &[
-    "https://www.googleapis.com/auth/classroom.courses.readonly",
-    "https://www.googleapis.com/auth/classroom.announcements.readonly",
-    "https://www.googleapis.com/auth/classroom.coursework.me.readonly",
-    "https://www.googleapis.com/auth/classroom.rosters.readonly",
-    "https://www.googleapis.com/auth/classroom.courseworkmaterials.readonly",
-    "https://www.googleapis.com/auth/classroom.topics.readonly",
-    "https://www.googleapis.com/auth/drive.readonly",
-    "https://www.googleapis.com/auth/calendar.readonly",
-];
+const TOKENINFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
 
-pub type ClassroomHub =
-    Classroom<hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>>;
+/// Refresh tokens issued to apps in "Testing" publishing status expire after 7 days.
+const UNVERIFIED_REFRESH_TOKEN_DAYS: i64 = 7;
 
-pub type DriveHubType =
-    DriveHub<hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>>;
+type HttpsConnector =
+    hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>;
 
-pub type CalendarHubType =
-    CalendarHub<hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>>;
+pub type Authenticator = yup_oauth2::authenticator::Authenticator<HttpsConnector>;
 
-fn config_dir() -> Result<PathBuf, AppError> {
-    let dir = dirs::config_dir()
-        .ok_or_else(|| AppError::CredentialRead("cannot determine config directory".into()))?
-        .join("personal-google-mcp");
-    Ok(dir)
-}
+pub type ClassroomHub = Classroom<HttpsConnector>;
+
+pub type DriveHubType = DriveHub<HttpsConnector>;
+
+pub type CalendarHubType = CalendarHub<HttpsConnector>;
 
 fn credentials_path() -> Result<PathBuf, AppError> {
     Ok(config_dir()?.join("credentials.json"))
 }
 
-fn tokens_path() -> Result<PathBuf, AppError> {
-    Ok(config_dir()?.join("tokens.json"))
+/// Name of the profile whose tokens live directly in the config directory.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Check that a profile name is safe to use as a directory name.
+pub fn validate_profile_name(profile: &str) -> Result<(), AppError> {
+    if !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(AppError::InvalidInput(format!(
+            "invalid profile name '{profile}' — use letters, digits, '-' or '_'"
+        )))
+    }
+}
+
+/// Directory holding a profile's tokens and disk cache.
+///
+/// The default profile keeps the original layout (`tokens.json` and `cache/`
+/// directly in the config directory); named profiles live under `profiles/{name}/`.
+pub fn profile_dir(profile: &str) -> Result<PathBuf, AppError> {
+    validate_profile_name(profile)?;
+    if profile == DEFAULT_PROFILE {
+        config_dir()
+    } else {
+        Ok(config_dir()?.join("profiles").join(profile))
+    }
+}
+
+/// Directory holding an account's disk cache and other local state.
+///
+/// Accounts are either sign-in profiles (see [`profile_dir`]) or Workspace
+/// users impersonated through a service account, which are named by their
+/// email address and kept under `delegated/{email}/`.
+pub fn account_dir(account: &str) -> Result<PathBuf, AppError> {
+    if validate_profile_name(account).is_ok() {
+        return profile_dir(account);
+    }
+    validate_email(account)?;
+    Ok(config_dir()?
+        .join("delegated")
+        .join(account.to_ascii_lowercase()))
+}
+
+fn validate_email(email: &str) -> Result<(), AppError> {
+    let valid = email.split_once('@').is_some_and(|(user, domain)| {
+        !user.is_empty()
+            && domain.contains('.')
+            && !email.contains("..")
+            && email
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "@._+-".contains(c))
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::InvalidInput(format!(
+            "'{email}' is neither a profile name nor an email address"
+        )))
+    }
+}
+
+pub fn tokens_path(profile: &str) -> Result<PathBuf, AppError> {
+    Ok(profile_dir(profile)?.join("tokens.json"))
+}
+
+/// List all profiles that have saved tokens, default profile first.
+pub fn list_profiles() -> Result<Vec<String>, AppError> {
+    let mut profiles = Vec::new();
+    if tokens_path(DEFAULT_PROFILE)?.exists() {
+        profiles.push(DEFAULT_PROFILE.to_string());
+    }
+
+    let profiles_dir = config_dir()?.join("profiles");
+    let entries = match std::fs::read_dir(&profiles_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(profiles),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut named = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if name != DEFAULT_PROFILE
+            && validate_profile_name(&name).is_ok()
+            && entry.path().join("tokens.json").exists()
+        {
+            named.push(name);
+        }
+    }
+    named.sort();
+    profiles.extend(named);
+    Ok(profiles)
+}
+
+/// Delegate for machines without a local browser (e.g. over SSH).
+///
+/// Prints the consent URL, then reads back either the full URL the browser was
+/// redirected to (the page itself fails to load, which is expected) or just the
+/// `code` parameter from it.
+struct HeadlessFlowDelegate {
+    redirect_uri: String,
+}
+
+impl InstalledFlowDelegate for HeadlessFlowDelegate {
+    fn redirect_uri(&self) -> Option<&str> {
+        Some(&self.redirect_uri)
+    }
+
+    fn present_user_url<'a>(
+        &'a self,
+        url: &'a str,
+        _need_code: bool,
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>> {
+        Box::pin(async move {
+            use tokio::io::AsyncBufReadExt;
+
+            println!("Open this URL in a browser on any machine and approve access:\n\n{url}\n");
+            println!(
+                "The browser will then be redirected to {} — that page won't load.\n\
+                 Copy the full URL from the address bar (or just its `code` value) and paste it here:",
+                self.redirect_uri
+            );
+
+            let mut input = String::new();
+            tokio::io::BufReader::new(tokio::io::stdin())
+                .read_line(&mut input)
+                .await
+                .map_err(|e| format!("couldn't read from stdin: {e}"))?;
+            extract_auth_code(&input)
+        })
+    }
+}
+
+/// Pull the authorization code out of a pasted redirect URL, or accept a bare code.
+fn extract_auth_code(input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("no authorization code entered".into());
+    }
+
+    let Some((_, query)) = input.split_once('?') else {
+        return Ok(input.to_string());
+    };
+    let query = query.split('#').next().unwrap_or_default();
+
+    let mut code = None;
+    for pair in query.split('&') {
+        match pair.split_once('=') {
+            Some(("code", value)) => code = Some(percent_decode(value)),
+            Some(("error", value)) => {
+                return Err(format!(
+                    "authorization was denied: {}",
+                    percent_decode(value)
+                ));
+            }
+            _ => {}
+        }
+    }
+    code.filter(|c| !c.is_empty())
+        .ok_or_else(|| "pasted URL has no `code` parameter".into())
+}
+
+/// Decode `%XX` escapes and `+` in a URL query value.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// How the consent flow gets the authorization code back from Google.
+pub struct AuthFlowOptions {
+    /// Print the consent URL and read the redirect URL/code from stdin instead
+    /// of listening for the browser redirect locally.
+    pub headless: bool,
+    /// Local port used in the `http://localhost:{port}` redirect URI.
+    pub redirect_port: u16,
+    /// Extra tool groups to grant on top of those enabled in `config.json`.
+    pub add_scopes: Vec<ToolGroup>,
 }
 
 /// Run the interactive OAuth2 flow: opens a browser, waits for consent, saves tokens.
-pub async fn run_auth_flow() -> Result<(), AppError> {
+///
+/// Requests the scopes of every enabled tool group plus `options.add_scopes`,
+/// keeping any scopes the profile was already granted. If the saved tokens
+/// already cover all of them, no browser flow is needed.
+pub async fn run_auth_flow(
+    profile: &str,
+    options: &AuthFlowOptions,
+    config: &Config,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<(), AppError> {
     let creds_path = credentials_path()?;
     if !creds_path.exists() {
         return Err(AppError::CredentialRead(format!(
@@ -82,31 +301,89 @@ pub async fn run_auth_flow() -> Result<(), AppError> {
         .await
         .map_err(|e| AppError::CredentialRead(format!("failed to parse credentials.json: {e}")))?;
 
-    let tokens = tokens_path()?;
+    let tokens = tokens_path(profile)?;
     // Ensure parent directory exists
     if let Some(parent) = tokens.parent() {
         std::fs::create_dir_all(parent)?;
     }
 
-    let auth = InstalledFlowAuthenticator::builder(
-        secret,
-        InstalledFlowReturnMethod::HTTPPortRedirect(OAUTH_REDIRECT_PORT),
-    )
-    .persist_tokens_to_disk(&tokens)
-    .build()
-    .await
-    .map_err(|e| AppError::OAuth2(e.to_string()))?;
+    let mut groups = config.tool_groups.clone();
+    groups.extend(&options.add_scopes);
+    let mut scopes: BTreeSet<String> = scopes_for(&groups).into_iter().map(String::from).collect();
+    if tokens.exists() {
+        scopes.extend(stored_scopes(&tokens, cipher.as_deref())?);
+    }
+    let scopes: Vec<String> = scopes.into_iter().collect();
+    let refresh_before = stored_refresh_tokens(&tokens, cipher.as_deref());
 
-    tracing::info!(
-        "Opening browser for Google sign-in (redirect on port {OAUTH_REDIRECT_PORT})..."
-    );
+    let port = options.redirect_port;
+    let builder = if options.headless {
+        // Interactive mode hands the pasted code straight to the token exchange,
+        // using the delegate's redirect URI so it matches the consent request.
+        InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::Interactive)
+            .flow_delegate(Box::new(HeadlessFlowDelegate {
+                redirect_uri: format!("http://localhost:{port}"),
+            }))
+    } else {
+        InstalledFlowAuthenticator::builder(
+            secret,
+            InstalledFlowReturnMethod::HTTPPortRedirect(port),
+        )
+    };
+
+    let auth = builder
+        .with_storage(Box::new(FileTokenStorage::open(
+            tokens.clone(),
+            cipher.clone(),
+        )?))
+        // Always show the account chooser so a second profile doesn't silently
+        // reuse whichever Google account the browser is signed in to.
+        .force_account_selection(true)
+        .build()
+        .await
+        .map_err(|e| AppError::OAuth2(e.to_string()))?;
+
+    if options.headless {
+        tracing::info!("Starting headless Google sign-in for profile '{profile}'...");
+    } else {
+        tracing::info!(
+            "Opening browser for Google sign-in to profile '{profile}' \
+             (redirect on port {port})..."
+        );
+    }
 
     // Requesting a token triggers the browser flow if no cached token exists
     let token = auth
-        .token(SCOPES)
+        .token(&scopes)
         .await
         .map_err(|e| AppError::OAuth2(e.to_string()))?;
 
+    let refresh_after = stored_refresh_tokens(&tokens, cipher.as_deref());
+    record_auth_info(
+        &auth_info_path(profile)?,
+        &scopes,
+        &refresh_before,
+        &refresh_after,
+    )?;
+
+    // Users can untick individual scopes on the consent screen; warn about
+    // any group that didn't end up fully granted.
+    if let Some(access_token) = token.token() {
+        match fetch_token_info(access_token).await {
+            Ok(info) => {
+                let granted: BTreeSet<String> =
+                    info.scope.split_whitespace().map(String::from).collect();
+                for group in groups.iter().filter(|g| !g.is_granted(&granted)) {
+                    tracing::warn!(
+                        "not all scopes for the '{}' tool group were granted — its tools will fail",
+                        group.name()
+                    );
+                }
+            }
+            Err(e) => tracing::debug!("could not verify granted scopes: {e}"),
+        }
+    }
+
     tracing::info!("Authentication successful!");
     tracing::info!("Tokens saved to {}", tokens.display());
     tracing::debug!("Token expires: {:?}", token.expiration_time());
@@ -114,14 +391,231 @@ pub async fn run_auth_flow() -> Result<(), AppError> {
     Ok(())
 }
 
-/// Build Classroom and Drive API hubs from previously saved tokens.
-pub async fn build_hubs() -> Result<(ClassroomHub, DriveHubType, CalendarHubType), AppError> {
+/// The API hubs for a single authenticated profile.
+pub struct HubSet {
+    pub classroom: ClassroomHub,
+    pub drive: DriveHubType,
+    pub calendar: CalendarHubType,
+    /// The token provider shared by the hubs, which tracks auth health
+    /// (`None` for offline hubs).
+    pub auth: Option<ManagedAuth>,
+}
+
+impl HubSet {
+    fn new(auth: ManagedAuth) -> Result<Self, AppError> {
+        Ok(Self {
+            classroom: Classroom::new(https_client()?, auth.clone()),
+            drive: DriveHub::new(https_client()?, auth.clone()),
+            calendar: CalendarHub::new(https_client()?, auth.clone()),
+            auth: Some(auth),
+        })
+    }
+
+    /// Hubs without credentials, for `run --offline`, where every tool is
+    /// served from the local store and Google is never called.
+    pub fn offline() -> Result<Self, AppError> {
+        Ok(Self {
+            classroom: Classroom::new(https_client()?, NoToken),
+            drive: DriveHub::new(https_client()?, NoToken),
+            calendar: CalendarHub::new(https_client()?, NoToken),
+            auth: None,
+        })
+    }
+}
+
+/// Union of the scopes of every token saved in a token file.
+fn stored_scopes(tokens: &Path, cipher: Option<&Cipher>) -> Result<BTreeSet<String>, AppError> {
+    Ok(read_stored_tokens(tokens, cipher)?
+        .into_iter()
+        .flat_map(|t| t.scopes)
+        .collect())
+}
+
+/// Scopes to request a token for: those of the enabled groups that the
+/// profile has actually granted. Groups enabled after sign-in are left out so
+/// they don't trigger a consent flow; their tools report the missing scope.
+fn enabled_granted_scopes(tool_groups: &[ToolGroup], granted: &BTreeSet<String>) -> Vec<String> {
+    scopes_for(tool_groups)
+        .into_iter()
+        .filter(|s| granted.contains(*s))
+        .map(String::from)
+        .collect()
+}
+
+/// Build a hub set for every profile with saved tokens, plus every user the
+/// configured service account impersonates at startup.
+///
+/// An account that fails to validate is skipped with an error log so one
+/// expired account doesn't take the others down; if no account can be built the
+/// first error is returned.
+pub async fn build_hubs(
+    config: &Config,
+    service_account: Option<&ServiceAccount>,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<Vec<(String, HubSet)>, AppError> {
+    let profiles = if credentials_path()?.exists() {
+        list_profiles()?
+    } else {
+        Vec::new()
+    };
+    let delegated = service_account
+        .map(|sa| sa.startup_accounts())
+        .unwrap_or_default();
+    if profiles.is_empty() && delegated.is_empty() {
+        return Err(AppError::NotAuthenticated);
+    }
+
+    let mut hubs = Vec::new();
+    let mut first_err = None;
+    for profile in profiles {
+        match build_profile_hubs(&profile, config, cipher.clone()).await {
+            Ok(set) => hubs.push((profile, set)),
+            Err(e) => {
+                tracing::error!("skipping profile '{profile}': {e}");
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(sa) = service_account {
+        for account in delegated {
+            match sa.hubs_for(&account).await {
+                Ok(set) => hubs.push((account, set)),
+                Err(e) => {
+                    tracing::error!("skipping service account user '{account}': {e}");
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+    }
+
+    match (hubs.is_empty(), first_err) {
+        (true, Some(e)) => Err(e),
+        _ => Ok(hubs),
+    }
+}
+
+/// A service account that builds hubs without interactive consent, optionally
+/// impersonating Workspace users through domain-wide delegation.
+///
+/// Delegation must be set up by a Workspace admin (Security → API controls →
+/// Domain-wide delegation) for the service account's client ID and the scopes
+/// of every enabled tool group.
+pub struct ServiceAccount {
+    key: ServiceAccountKey,
+    subject: Option<String>,
+    users: Vec<String>,
+    domain: Option<String>,
+    scopes: Vec<&'static str>,
+}
+
+impl std::fmt::Debug for ServiceAccount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServiceAccount")
+            .field("client_email", &self.key.client_email)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServiceAccount {
+    /// Load the service account configured in `config.json`, if any.
+    pub async fn load(config: &Config) -> Result<Option<Self>, AppError> {
+        let Some(sa) = &config.service_account else {
+            return Ok(None);
+        };
+        let key_path = config_dir()?.join(&sa.key_file);
+        let key = read_service_account_key(&key_path).await.map_err(|e| {
+            AppError::CredentialRead(format!(
+                "failed to read service account key {}: {e}",
+                key_path.display()
+            ))
+        })?;
+        for user in sa.subject.iter().chain(&sa.users) {
+            validate_email(user)?;
+        }
+        tracing::info!("using service account {}", key.client_email);
+
+        Ok(Some(Self {
+            key,
+            subject: sa.subject.clone(),
+            users: sa.users.clone(),
+            domain: sa.domain.as_ref().map(|d| d.to_ascii_lowercase()),
+            scopes: scopes_for(&config.tool_groups),
+        }))
+    }
+
+    /// Accounts to set up at startup: the default subject (or the service
+    /// account itself) and every listed user.
+    fn startup_accounts(&self) -> Vec<String> {
+        let mut accounts = vec![self
+            .subject
+            .clone()
+            .unwrap_or_else(|| self.key.client_email.clone())];
+        accounts.extend(self.users.iter().cloned());
+        let mut seen = BTreeSet::new();
+        accounts.retain(|account| seen.insert(account.to_ascii_lowercase()));
+        accounts
+    }
+
+    /// Whether `account` is a user of the delegated domain who can be
+    /// impersonated on demand.
+    pub fn can_impersonate(&self, account: &str) -> bool {
+        let Some(domain) = &self.domain else {
+            return false;
+        };
+        validate_email(account).is_ok()
+            && account
+                .rsplit_once('@')
+                .is_some_and(|(_, d)| d.eq_ignore_ascii_case(domain))
+    }
+
+    /// Build hubs acting as `account`: either a user to impersonate, or the
+    /// service account's own email to act as itself.
+    pub async fn hubs_for(&self, account: &str) -> Result<HubSet, AppError> {
+        let builder = ServiceAccountAuthenticator::builder(self.key.clone());
+        let builder = if account == self.key.client_email {
+            builder
+        } else {
+            builder.subject(account)
+        };
+        let auth = builder
+            .build()
+            .await
+            .map_err(|e| AppError::OAuth2(e.to_string()))?;
+
+        let granted = self.scopes.iter().map(|s| s.to_string()).collect();
+        let scopes = self.scopes.iter().map(|s| s.to_string()).collect();
+        let auth =
+            ManagedAuth::start(account, AuthSource::ServiceAccount, auth, scopes, granted).await;
+
+        // Fail fast when the service account can't act as this account at all
+        // (scopes it isn't delegated fail per call, with a missing-scope error).
+        if let AuthHealth::Failed { reason, .. } = auth.health() {
+            return Err(AppError::OAuth2(format!(
+                "service account {} could not get a token for {account} — check \
+                 domain-wide delegation for the enabled scopes: {reason}",
+                self.key.client_email
+            )));
+        }
+
+        tracing::info!("Google API hubs ready for {account} (service account)");
+        HubSet::new(auth)
+    }
+}
+
+/// Build a non-interactive authenticator over a profile's saved tokens.
+///
+/// If the stored refresh token no longer works, token requests fail with an
+/// error instead of starting a browser flow.
+async fn server_authenticator(
+    profile: &str,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<Authenticator, AppError> {
     let creds_path = credentials_path()?;
     if !creds_path.exists() {
         return Err(AppError::NotAuthenticated);
     }
 
-    let tokens = tokens_path()?;
+    let tokens = tokens_path(profile)?;
     if !tokens.exists() {
         return Err(AppError::NotAuthenticated);
     }
@@ -134,43 +628,466 @@ pub async fn build_hubs() -> Result<(ClassroomHub, DriveHubType, CalendarHubType
     // returns Err, the error propagates instead of blocking on wait_for_auth_code().
     // With HTTPPortRedirect, the delegate's return is discarded (`let _ =`) and
     // the server blocks forever waiting for a browser redirect that never comes.
-    let auth = InstalledFlowAuthenticator::builder(
-        secret,
-        InstalledFlowReturnMethod::Interactive,
+    InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::Interactive)
+        .with_storage(Box::new(FileTokenStorage::open(tokens, cipher)?))
+        .flow_delegate(Box::new(ServerFlowDelegate {
+            profile: profile.to_string(),
+        }))
+        .build()
+        .await
+        .map_err(|e| AppError::OAuth2(e.to_string()))
+}
+
+/// Build an HTTPS client using the platform's native root certificates.
+fn https_client<B>() -> Result<hyper_util::client::legacy::Client<HttpsConnector, B>, AppError>
+where
+    B: hyper::body::Body + Send,
+    B::Data: Send,
+{
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))?
+        .https_only()
+        .enable_http2()
+        .build();
+    Ok(
+        hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+            .build(connector),
     )
-    .persist_tokens_to_disk(&tokens)
-    .flow_delegate(Box::new(ServerFlowDelegate))
-    .build()
-    .await
-    .map_err(|e| AppError::OAuth2(e.to_string()))?;
+}
+
+/// Load a profile's saved tokens: a non-interactive authenticator, the scopes
+/// to request (enabled groups the profile granted) and every granted scope.
+pub async fn profile_auth(
+    profile: &str,
+    tool_groups: &[ToolGroup],
+    cipher: Option<Arc<Cipher>>,
+) -> Result<(Authenticator, Vec<String>, BTreeSet<String>), AppError> {
+    let auth = server_authenticator(profile, cipher.clone()).await?;
+
+    let granted_scopes = stored_scopes(&tokens_path(profile)?, cipher.as_deref())?;
+    let scopes = enabled_granted_scopes(tool_groups, &granted_scopes);
+    if scopes.is_empty() {
+        return Err(AppError::OAuth2(format!(
+            "profile '{profile}' has granted none of the enabled tool groups — run \
+             `personal-google-mcp auth --profile {profile}`"
+        )));
+    }
+    Ok((auth, scopes, granted_scopes))
+}
+
+/// Build Classroom, Drive and Calendar API hubs from a profile's saved tokens.
+pub async fn build_profile_hubs(
+    profile: &str,
+    config: &Config,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<HubSet, AppError> {
+    let (auth, scopes, granted_scopes) =
+        profile_auth(profile, &config.tool_groups, cipher.clone()).await?;
 
     // Validate token at startup — catches expired/revoked tokens before any
     // MCP tool call. With an unverified Google app, refresh tokens expire after
-    // 7 days, so this will fail fast with a clear re-auth message.
-    match auth.token(SCOPES).await {
-        Ok(_) => tracing::info!("OAuth token validated successfully"),
-        Err(e) => {
-            return Err(AppError::OAuth2(format!(
-                "token refresh failed — re-authenticate with `personal-google-mcp auth`: {e}"
-            )));
+    // 7 days. A profile that fails is still served: its tools report the
+    // re-auth command, and it recovers once `auth` rewrites tokens.json.
+    let source = AuthSource::Profile {
+        tool_groups: config.tool_groups.clone(),
+        cipher,
+    };
+    let auth = ManagedAuth::start(profile, source, auth, scopes, granted_scopes).await;
+    match auth.health() {
+        AuthHealth::Healthy => {
+            tracing::info!("OAuth token validated successfully for profile '{profile}'")
+        }
+        AuthHealth::Failed { .. } => {
+            tracing::warn!("profile '{profile}' needs re-authentication; waiting for new tokens")
+        }
+    }
+
+    let hubs = HubSet::new(auth)?;
+    tracing::info!("Google API hubs ready for profile '{profile}'");
+    Ok(hubs)
+}
+
+/// Metadata recorded when a profile completes the consent flow.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthInfo {
+    /// When the refresh token was issued.
+    authorized_at: DateTime<Utc>,
+    /// Scopes requested during consent.
+    scopes: Vec<String>,
+}
+
+fn auth_info_path(profile: &str) -> Result<PathBuf, AppError> {
+    Ok(profile_dir(profile)?.join("auth-info.json"))
+}
+
+/// Refresh tokens saved in a token file (empty if it doesn't exist yet).
+fn stored_refresh_tokens(tokens: &Path, cipher: Option<&Cipher>) -> BTreeSet<String> {
+    read_stored_tokens(tokens, cipher)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|t| t.token.refresh_token)
+        .collect()
+}
+
+/// Write `auth-info.json` if `auth` stored a refresh token it didn't have
+/// before, i.e. a consent flow actually ran. Reusing or refreshing the saved
+/// tokens keeps the original `authorizedAt`, which `auth status` ages against
+/// the unverified-app limit. Returns whether the file was written.
+fn record_auth_info(
+    path: &Path,
+    scopes: &[String],
+    refresh_before: &BTreeSet<String>,
+    refresh_after: &BTreeSet<String>,
+) -> Result<bool, AppError> {
+    if refresh_after.is_subset(refresh_before) {
+        return Ok(false);
+    }
+    let info = AuthInfo {
+        authorized_at: Utc::now(),
+        scopes: scopes.to_vec(),
+    };
+    crypto::write_private(path, serde_json::to_string_pretty(&info)?.as_bytes())?;
+    Ok(true)
+}
+
+fn read_auth_info(path: &Path) -> Option<AuthInfo> {
+    let data = std::fs::read(path).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Response of Google's `tokeninfo` endpoint for an access token.
+#[derive(Debug, Deserialize)]
+struct GoogleTokenInfo {
+    #[serde(default)]
+    scope: String,
+    email: Option<String>,
+}
+
+async fn fetch_token_info(access_token: &str) -> Result<GoogleTokenInfo, AppError> {
+    let client = https_client::<Full<Bytes>>()?;
+    let uri = format!("{TOKENINFO_URL}?access_token={access_token}");
+    let req = hyper::Request::get(uri)
+        .body(Full::default())
+        .map_err(|e| AppError::OAuth2(e.to_string()))?;
+    let resp = client
+        .request(req)
+        .await
+        .map_err(|e| AppError::OAuth2(format!("tokeninfo request failed: {e}")))?;
+    let status = resp.status();
+    let body = resp
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| AppError::OAuth2(format!("failed to read tokeninfo response: {e}")))?
+        .to_bytes();
+    if !status.is_success() {
+        return Err(AppError::OAuth2(format!(
+            "tokeninfo returned {status}: {}",
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Print a diagnostic report for a profile: account, granted vs. required
+/// scopes, access-token expiry and refresh-token age.
+pub async fn auth_status(
+    profile: &str,
+    config: &Config,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<(), AppError> {
+    let tokens = tokens_path(profile)?;
+    println!("Profile:      {profile}");
+    println!("Credentials:  {}", describe_path(&credentials_path()?));
+    println!("Tokens:       {}", describe_path(&tokens));
+    if !tokens.exists() {
+        println!("\nNot signed in — run `personal-google-mcp auth --profile {profile}`.");
+        return Ok(());
+    }
+
+    let stored = read_stored_tokens(&tokens, cipher.as_deref())?;
+    println!(
+        "Encryption:   {}",
+        if cipher.is_some() {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    let has_refresh = stored.iter().any(|t| t.token.refresh_token.is_some());
+    println!(
+        "Refresh token: {}",
+        if has_refresh { "present" } else { "missing" }
+    );
+    for entry in &stored {
+        if let Some(expires_at) = entry.token.expires_at {
+            let expires_at = DateTime::from_timestamp(expires_at.unix_timestamp(), 0);
+            if let Some(expires_at) = expires_at {
+                let remaining = expires_at - Utc::now();
+                let state = if remaining.num_seconds() > 0 {
+                    format!("in {} min", remaining.num_minutes())
+                } else {
+                    "expired (refreshed on next use)".to_string()
+                };
+                println!(
+                    "Access token: expires {} ({state}, {} scopes)",
+                    expires_at.to_rfc3339(),
+                    entry.scopes.len()
+                );
+            }
+        }
+    }
+
+    match read_auth_info(&auth_info_path(profile)?) {
+        Some(info) => {
+            let age = Utc::now() - info.authorized_at;
+            println!(
+                "Authorized:   {} ({} days ago)",
+                info.authorized_at.to_rfc3339(),
+                age.num_days()
+            );
+            let remaining = Duration::days(UNVERIFIED_REFRESH_TOKEN_DAYS) - age;
+            if remaining.num_seconds() > 0 {
+                println!(
+                    "              unverified (Testing) apps lose this refresh token in {} days {} h",
+                    remaining.num_days(),
+                    remaining.num_hours() % 24
+                );
+            } else {
+                println!(
+                    "              older than the {UNVERIFIED_REFRESH_TOKEN_DAYS}-day limit for \
+                     unverified (Testing) apps — expect refresh failures unless the app is published"
+                );
+            }
         }
+        None => println!("Authorized:   unknown (signed in before this was recorded)"),
     }
 
-    let build_client = || -> Result<_, AppError> {
-        let connector = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .map_err(|e| AppError::Io(std::io::Error::other(e)))?
-            .https_only()
-            .enable_http2()
-            .build();
-        Ok(hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
-            .build(connector))
+    let stored_scopes: BTreeSet<String> = stored.into_iter().flat_map(|t| t.scopes).collect();
+    let auth = server_authenticator(profile, cipher).await?;
+    let access = match auth
+        .token(&enabled_granted_scopes(&config.tool_groups, &stored_scopes))
+        .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            println!("\nToken refresh FAILED: {e}");
+            println!("Re-authenticate with `personal-google-mcp auth --profile {profile}`.");
+            return Ok(());
+        }
+    };
+    let Some(access_token) = access.token() else {
+        println!("\nNo access token returned.");
+        return Ok(());
     };
 
-    let classroom_hub = Classroom::new(build_client()?, auth.clone());
-    let drive_hub = DriveHub::new(build_client()?, auth.clone());
-    let calendar_hub = CalendarHub::new(build_client()?, auth);
+    let info = fetch_token_info(access_token).await?;
+    if let Some(email) = &info.email {
+        println!("Account:      {email}");
+    } else {
+        let drive = DriveHub::new(https_client()?, auth.clone());
+        if let Ok((_resp, about)) = drive
+            .about()
+            .get()
+            .param("fields", "user(emailAddress)")
+            .add_scope(DRIVE_READONLY)
+            .doit()
+            .await
+        {
+            let email = about.user.and_then(|u| u.email_address);
+            println!("Account:      {}", email.as_deref().unwrap_or("unknown"));
+        }
+    }
+
+    let granted: BTreeSet<String> = info.scope.split_whitespace().map(String::from).collect();
+    println!("\nTool groups:");
+    for &group in ToolGroup::ALL {
+        let enabled = config.tool_groups.contains(&group);
+        let state = match (enabled, group.is_granted(&granted)) {
+            (true, true) => "enabled, granted",
+            (true, false) => "enabled, MISSING SCOPES",
+            (false, true) => "disabled, granted",
+            (false, false) => "disabled",
+        };
+        println!("  {:<14} {state}", group.name());
+        if enabled {
+            for scope in group.scopes().iter().filter(|s| !granted.contains(**s)) {
+                println!("                 missing {scope}");
+            }
+        }
+    }
+    if config.tool_groups.iter().any(|g| !g.is_granted(&granted)) {
+        println!(
+            "\nGrant missing scopes with `personal-google-mcp auth --profile {profile} \
+             --add-scope <group>`."
+        );
+    }
+    Ok(())
+}
+
+fn describe_path(path: &Path) -> String {
+    let state = if path.exists() { "" } else { " (missing)" };
+    format!("{}{state}", path.display())
+}
+
+/// Force a token refresh for a profile and report the new expiry.
+pub async fn auth_refresh(
+    profile: &str,
+    config: &Config,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<(), AppError> {
+    let auth = server_authenticator(profile, cipher.clone()).await?;
+    let granted = stored_scopes(&tokens_path(profile)?, cipher.as_deref())?;
+    let scopes = enabled_granted_scopes(&config.tool_groups, &granted);
+    let token = auth.force_refreshed_token(&scopes).await.map_err(|e| {
+        AppError::OAuth2(format!(
+            "token refresh failed — re-authenticate with \
+             `personal-google-mcp auth --profile {profile}`: {e}"
+        ))
+    })?;
+    tracing::info!("Token refreshed for profile '{profile}'");
+    if let Some(expires_at) = token.expiration_time() {
+        tracing::info!("New access token expires at {expires_at}");
+    }
+    Ok(())
+}
+
+/// Revoke a profile's grant at Google and delete its saved tokens.
+///
+/// Local tokens are deleted even when Google rejects the revocation (for
+/// example because the token already expired), so the profile ends up signed
+/// out either way.
+pub async fn auth_revoke(profile: &str, cipher: Option<Arc<Cipher>>) -> Result<(), AppError> {
+    let tokens = tokens_path(profile)?;
+    if !tokens.exists() {
+        return Err(AppError::InvalidInput(format!(
+            "profile '{profile}' has no saved tokens at {}",
+            tokens.display()
+        )));
+    }
+
+    let stored = read_stored_tokens(&tokens, cipher.as_deref())?;
+    // Revoking the refresh token invalidates every access token derived from it.
+    let token = stored
+        .iter()
+        .find_map(|t| t.token.refresh_token.clone())
+        .or_else(|| stored.iter().find_map(|t| t.token.access_token.clone()));
+
+    match token {
+        Some(token) => match revoke_token(&token).await {
+            Ok(()) => tracing::info!("Revoked Google access for profile '{profile}'"),
+            Err(e) => tracing::warn!("revocation at Google failed, deleting tokens anyway: {e}"),
+        },
+        None => tracing::warn!("no token to revoke at Google, deleting local tokens"),
+    }
+
+    std::fs::remove_file(&tokens)?;
+    if let Ok(info) = auth_info_path(profile) {
+        let _ = std::fs::remove_file(info);
+    }
+    tracing::info!("Deleted {}", tokens.display());
+    Ok(())
+}
+
+async fn revoke_token(token: &str) -> Result<(), AppError> {
+    let client = https_client::<Full<Bytes>>()?;
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("token", token)
+        .finish();
+    let req = hyper::Request::post(REVOKE_URL)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Full::new(Bytes::from(body)))
+        .map_err(|e| AppError::OAuth2(e.to_string()))?;
+    let resp = client
+        .request(req)
+        .await
+        .map_err(|e| AppError::OAuth2(format!("revoke request failed: {e}")))?;
+    let status = resp.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        let body = resp
+            .into_body()
+            .collect()
+            .await
+            .map(|b| b.to_bytes())
+            .unwrap_or_default();
+        Err(AppError::OAuth2(format!(
+            "revoke returned {status}: {}",
+            String::from_utf8_lossy(&body)
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_names() {
+        assert!(validate_profile_name("school").is_ok());
+        assert!(validate_profile_name("work-2_a").is_ok());
+        assert!(validate_profile_name("").is_err());
+        assert!(validate_profile_name("../etc").is_err());
+        assert!(validate_profile_name("a b").is_err());
+    }
+
+    #[test]
+    fn auth_code_from_redirect_url() {
+        let url = "http://localhost:8085/?code=4/0AbC-dEf%2Fg&scope=https://www.googleapis.com/auth/drive.readonly";
+        assert_eq!(extract_auth_code(url).unwrap(), "4/0AbC-dEf/g");
+    }
 
-    tracing::info!("Google API hubs ready");
-    Ok((classroom_hub, drive_hub, calendar_hub))
+    #[test]
+    fn auth_code_bare() {
+        assert_eq!(extract_auth_code("  4/0AbCdEf\n").unwrap(), "4/0AbCdEf");
+        assert!(extract_auth_code("   ").is_err());
+    }
+
+    #[test]
+    fn auth_code_denied() {
+        let url = "http://localhost:8085/?error=access_denied";
+        assert!(extract_auth_code(url)
+            .unwrap_err()
+            .contains("access_denied"));
+    }
+
+    #[test]
+    fn delegated_accounts_use_email_dirs() {
+        let dir = account_dir("Alice@School.edu").unwrap();
+        assert!(dir.ends_with("delegated/alice@school.edu"));
+        assert!(account_dir("alice@../etc").is_err());
+        assert!(account_dir("not an email").is_err());
+    }
+
+    #[test]
+    fn named_profiles_live_under_profiles_dir() {
+        let default = profile_dir(DEFAULT_PROFILE).unwrap();
+        let school = profile_dir("school").unwrap();
+        assert_eq!(school, default.join("profiles").join("school"));
+    }
+
+    #[test]
+    fn reauth_without_new_refresh_token_keeps_timestamp() {
+        let dir = std::env::temp_dir().join(format!("auth-info-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth-info.json");
+        let scopes = vec!["scope".to_string()];
+        let old: BTreeSet<String> = ["refresh-1".to_string()].into();
+
+        assert!(record_auth_info(&path, &scopes, &BTreeSet::new(), &old).unwrap());
+        let first = read_auth_info(&path).unwrap().authorized_at;
+
+        // Reusing or refreshing the saved tokens leaves the refresh token as is.
+        assert!(!record_auth_info(&path, &scopes, &old, &old).unwrap());
+        assert_eq!(read_auth_info(&path).unwrap().authorized_at, first);
+
+        // A fresh consent stores a new refresh token.
+        let new: BTreeSet<String> = ["refresh-2".to_string()].into();
+        assert!(record_auth_info(&path, &scopes, &old, &new).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,724 @@
+//! Caching shared by the API clients.
+//!
+//! Every client keeps a [`CacheLayer`]: an in-memory tier plus a disk tier in
+//! the account's [`Store`], so data survives restarts.
+//! Each [`CacheKind`] has its own freshness window; entries a little past it
+//! are served immediately while a background task refreshes them
+//! (stale-while-revalidate), and concurrent misses for the same key share a
+//! single request to Google.
+//!
+//! Disk entries record when they were fetched and, for data that comes from
+//! a single response (everything but course details, assignments and Drive
+//! files), the ETag Google sent.
+//! Past the configured max age they are only served when refetching fails
+//! (Google unreachable, or access to the course lost), and each account's
+//! disk tier is kept under a size cap by evicting the least recently used
+//! entries. The `cache` CLI subcommand lists, shows and purges the disk tier.
+//!
+//! In offline mode (`run --offline`) Google is never called: every entry on
+//! disk is served regardless of age, along with when it was fetched.
+
+use std::collections::{BTreeSet, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use google_classroom1::common;
+use hyper::header::ETAG;
+use moka::future::Cache;
+use moka::Expiry;
+use serde_json::{json, Value};
+
+use crate::config::CacheConfig;
+use crate::crypto::Cipher;
+use crate::error::AppError;
+use crate::store::{store_path, CachedRow, Store};
+use crate::usage::ApiUsage;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The kinds of data the clients cache, each with its own lifetime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Courses,
+    CourseDetails,
+    Assignments,
+    Materials,
+    Topics,
+    Calendars,
+    Events,
+    Event,
+    DriveFile,
+    DriveUser,
+}
+
+impl CacheKind {
+    const ALL: &'static [CacheKind] = &[
+        Self::Courses,
+        Self::CourseDetails,
+        Self::Assignments,
+        Self::Materials,
+        Self::Topics,
+        Self::Calendars,
+        Self::Events,
+        Self::Event,
+        Self::DriveFile,
+        Self::DriveUser,
+    ];
+
+    /// Split a cache key into its kind and id.
+    fn parse_key(key: &str) -> Option<(Self, &str)> {
+        Self::ALL.iter().find_map(|&kind| {
+            let rest = key.strip_prefix(kind.name())?;
+            if rest.is_empty() {
+                Some((kind, rest))
+            } else {
+                rest.strip_prefix('_').map(|id| (kind, id))
+            }
+        })
+    }
+
+    /// Prefix of the cache key (and disk file name).
+    fn name(self) -> &'static str {
+        match self {
+            Self::Courses => "courses",
+            Self::CourseDetails => "course_details",
+            Self::Assignments => "assignments",
+            Self::Materials => "materials",
+            Self::Topics => "topics",
+            Self::Calendars => "calendar_list",
+            Self::Events => "events",
+            Self::Event => "event",
+            Self::DriveFile => "drive_file",
+            Self::DriveUser => "drive_user",
+        }
+    }
+
+    /// How long an entry is served without asking Google.
+    fn ttl(self) -> Duration {
+        match self {
+            Self::Courses => 6 * HOUR,
+            Self::CourseDetails => 15 * MINUTE,
+            Self::Assignments => 5 * MINUTE,
+            Self::Materials => HOUR,
+            Self::Topics => 6 * HOUR,
+            Self::Calendars => HOUR,
+            Self::Events | Self::Event => 5 * MINUTE,
+            Self::DriveFile => 30 * MINUTE,
+            Self::DriveUser => DAY,
+        }
+    }
+
+    /// How long past its TTL an entry is still served while it's refreshed in
+    /// the background.
+    fn stale_window(self) -> Duration {
+        match self {
+            Self::Courses | Self::Calendars | Self::DriveFile => DAY,
+            Self::CourseDetails | Self::Assignments | Self::Events | Self::Event => 30 * MINUTE,
+            Self::Materials | Self::Topics | Self::DriveUser => 7 * DAY,
+        }
+    }
+}
+
+/// Which entries `cache_clear` drops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheScope {
+    All,
+    /// One course's data, or all Classroom data (including the course list).
+    Course(Option<String>),
+    /// One Drive file, or every cached file.
+    DriveFile(Option<String>),
+    /// One calendar's events, or all Calendar data (including the calendar list).
+    Calendar(Option<String>),
+}
+
+impl CacheScope {
+    fn matches(&self, kind: CacheKind, id: &str) -> bool {
+        use CacheKind::*;
+        match self {
+            Self::All => true,
+            Self::Course(None) => {
+                matches!(
+                    kind,
+                    Courses | CourseDetails | Assignments | Materials | Topics
+                )
+            }
+            Self::Course(Some(course)) => {
+                matches!(kind, CourseDetails | Assignments | Materials | Topics) && id == course
+            }
+            Self::DriveFile(file) => {
+                kind == DriveFile && file.as_ref().is_none_or(|file| file == id)
+            }
+            Self::Calendar(None) => matches!(kind, Calendars | Events | Event),
+            Self::Calendar(Some(calendar)) => {
+                matches!(kind, Events | Event)
+                    && id.split_once(':').is_some_and(|(cal, _)| cal == calendar)
+            }
+        }
+    }
+}
+
+/// A freshly fetched value and the ETag of the response it came from.
+#[derive(Debug)]
+pub struct Fetched {
+    pub value: Value,
+    pub etag: Option<String>,
+}
+
+impl Fetched {
+    /// Keep `value` along with the `ETag` header of `response`.
+    pub fn with_etag(value: Value, response: &common::Response) -> Self {
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        Self { value, etag }
+    }
+}
+
+impl From<Value> for Fetched {
+    fn from(value: Value) -> Self {
+        Self { value, etag: None }
+    }
+}
+
+/// A cached value.
+#[derive(Debug, Clone)]
+struct Entry {
+    kind: CacheKind,
+    fetched_at: DateTime<Utc>,
+    etag: Option<String>,
+    value: Value,
+}
+
+impl Entry {
+    fn new(kind: CacheKind, fetched: Fetched) -> Self {
+        Self {
+            kind,
+            fetched_at: Utc::now(),
+            etag: fetched.etag,
+            value: fetched.value,
+        }
+    }
+
+    fn age(&self) -> Duration {
+        (Utc::now() - self.fetched_at).to_std().unwrap_or_default()
+    }
+}
+
+/// How long entries are served: fresh until their kind's TTL, then stale
+/// while revalidating until the end of its stale window, both capped by the
+/// configured max age.
+#[derive(Debug, Clone, Copy)]
+struct Lifetimes {
+    max_age: Duration,
+}
+
+impl Lifetimes {
+    fn fresh(&self, kind: CacheKind) -> Duration {
+        kind.ttl().min(self.max_age)
+    }
+
+    fn usable(&self, kind: CacheKind) -> Duration {
+        (kind.ttl() + kind.stale_window()).min(self.max_age)
+    }
+}
+
+/// Drops entries from memory once they are past their stale window.
+struct EntryExpiry(Lifetimes);
+
+impl EntryExpiry {
+    fn remaining(&self, entry: &Entry) -> Option<Duration> {
+        Some(self.0.usable(entry.kind).saturating_sub(entry.age()))
+    }
+}
+
+impl Expiry<String, Entry> for EntryExpiry {
+    fn expire_after_create(&self, _key: &String, entry: &Entry, _at: Instant) -> Option<Duration> {
+        self.remaining(entry)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        entry: &Entry,
+        _at: Instant,
+        _remaining: Option<Duration>,
+    ) -> Option<Duration> {
+        self.remaining(entry)
+    }
+}
+
+/// The `cache_entries` table of the account's [`Store`]. Storage errors are
+/// logged and treated as misses, so a broken store never fails a tool call.
+#[derive(Clone)]
+struct DiskTier {
+    store: Store,
+    /// Total size above which the least recently used entries are evicted.
+    max_bytes: u64,
+}
+
+impl DiskTier {
+    /// Read an entry and mark it as recently used.
+    fn read(&self, kind: CacheKind, key: &str) -> Option<Entry> {
+        match self.store.cache_get(key) {
+            Ok(Some(row)) => {
+                tracing::debug!("disk cache hit: {key}");
+                Some(Entry {
+                    kind,
+                    fetched_at: row.fetched_at,
+                    etag: row.etag,
+                    value: row.value,
+                })
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("cannot read disk cache for {key}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Write an entry, evicting old ones if the cache has outgrown its cap.
+    /// SQLite and encryption run on a blocking thread, off the async workers.
+    async fn write(&self, key: &str, entry: &Entry) {
+        let row = CachedRow {
+            fetched_at: entry.fetched_at,
+            etag: entry.etag.clone(),
+            value: entry.value.clone(),
+        };
+        let (store, max_bytes, owned_key) = (self.store.clone(), self.max_bytes, key.to_string());
+        let result =
+            tokio::task::spawn_blocking(move || store.cache_put(&owned_key, &row, max_bytes)).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("failed to write disk cache for {key}: {e}"),
+            Err(e) => tracing::warn!("disk cache write for {key} did not finish: {e}"),
+        }
+    }
+}
+
+/// Memory and disk caching for one API client.
+#[derive(Clone)]
+pub struct CacheLayer {
+    memory: Cache<String, Entry>,
+    disk: DiskTier,
+    lifetimes: Lifetimes,
+    usage: Arc<ApiUsage>,
+    /// Keys with a background refresh in flight.
+    revalidating: Arc<Mutex<HashSet<String>>>,
+    /// Serve only what the disk tier holds, never calling `fetch`.
+    offline: bool,
+}
+
+impl CacheLayer {
+    /// Create a cache whose disk tier lives in `store` (one per account).
+    pub fn new(usage: Arc<ApiUsage>, store: Store, config: &CacheConfig) -> Self {
+        let lifetimes = Lifetimes {
+            max_age: config.max_age(),
+        };
+        Self {
+            memory: Cache::builder()
+                .max_capacity(1000)
+                .expire_after(EntryExpiry(lifetimes))
+                .build(),
+            disk: DiskTier {
+                store,
+                max_bytes: config.max_disk_bytes(),
+            },
+            lifetimes,
+            usage,
+            revalidating: Arc::default(),
+            offline: false,
+        }
+    }
+
+    /// Switch to offline mode (`run --offline`): entries of any age are
+    /// served from disk, wrapped as `{asOf, data}` with their fetch time,
+    /// and data never fetched is an [`AppError::NotCached`].
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Return the cached value for `kind`/`id` (empty for kinds with a single
+    /// entry), calling `fetch` on a miss or when `refresh` is set.
+    ///
+    /// Every entry is also written to disk, so it survives restarts. Stale
+    /// entries are returned at once and refreshed in the background. If
+    /// fetching fails, an expired disk copy is served rather than nothing
+    /// (but not when the caller asked for a refresh).
+    pub async fn get<F, Fut, T>(
+        &self,
+        kind: CacheKind,
+        id: &str,
+        refresh: bool,
+        fetch: F,
+    ) -> Result<Value, AppError>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, AppError>> + Send + 'static,
+        T: Into<Fetched> + Send + 'static,
+    {
+        let key = if id.is_empty() {
+            kind.name().to_string()
+        } else {
+            format!("{}_{id}", kind.name())
+        };
+        if self.offline {
+            let entry = self.disk.read(kind, &key).ok_or(AppError::NotCached(key))?;
+            self.usage.cache_hit();
+            return Ok(json!({ "asOf": entry.fetched_at, "data": entry.value }));
+        }
+
+        let started = Utc::now();
+        let mut cached = None;
+        if !refresh {
+            cached = self.memory.get(&key).await;
+        }
+        if cached.is_none() && !refresh {
+            cached = self.disk.read(kind, &key);
+            if let Some(entry) = &cached {
+                self.memory.insert(key.clone(), entry.clone()).await;
+            }
+        }
+
+        if let Some(entry) = &cached {
+            let age = entry.age();
+            if age < self.lifetimes.fresh(kind) {
+                tracing::debug!("cache hit: {key}");
+                self.usage.cache_hit();
+                return Ok(entry.value.clone());
+            }
+            if age < self.lifetimes.usable(kind) {
+                tracing::debug!("stale cache hit, revalidating: {key}");
+                self.usage.cache_hit();
+                self.revalidate(kind, key.clone(), fetch);
+                return Ok(entry.value.clone());
+            }
+        }
+
+        // A call for the same key may have fetched it since the lookup above;
+        // serve that. Otherwise only an entry being refreshed or past use is
+        // dropped, so that `try_get_with` fetches anew.
+        match self.memory.get(&key).await {
+            Some(entry) if entry.fetched_at >= started => {
+                tracing::debug!("cache hit after concurrent fetch: {key}");
+                self.usage.cache_hit();
+                return Ok(entry.value);
+            }
+            Some(entry) if refresh || entry.age() >= self.lifetimes.usable(kind) => {
+                self.memory.invalidate(&key).await;
+            }
+            _ => {}
+        }
+
+        if refresh {
+            tracing::debug!("cache bypassed: {key}");
+        } else {
+            tracing::debug!("cache miss: {key}");
+        }
+        self.usage.cache_miss();
+        // Concurrent misses for the same key wait on this one fetch.
+        let disk = self.disk.clone();
+        let result = self
+            .memory
+            .try_get_with(key.clone(), async {
+                let entry = Entry::new(kind, fetch().await?.into());
+                disk.write(&key, &entry).await;
+                Ok::<_, AppError>(entry)
+            })
+            .await;
+
+        match (result, cached) {
+            (Ok(entry), _) => Ok(entry.value),
+            (Err(e), Some(entry)) => {
+                tracing::warn!(
+                    "serving cache for {key} from {} after fetch failed: {e}",
+                    entry.fetched_at.to_rfc3339()
+                );
+                Ok(entry.value)
+            }
+            (Err(e), _) => Err(AppError::from_shared(e)),
+        }
+    }
+
+    /// Drop the entries in `scope` from memory and disk, returning how many
+    /// distinct keys were removed.
+    pub async fn clear(&self, scope: &CacheScope) -> usize {
+        let in_scope =
+            |key: &str| CacheKind::parse_key(key).is_some_and(|(kind, id)| scope.matches(kind, id));
+        let mut cleared = BTreeSet::new();
+        let keys: Vec<Arc<String>> = self
+            .memory
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| in_scope(key))
+            .collect();
+        for key in keys {
+            self.memory.invalidate(key.as_str()).await;
+            cleared.insert(key.to_string());
+        }
+        let entries = self.disk.store.cache_entries().unwrap_or_else(|e| {
+            tracing::warn!("cannot list disk cache: {e}");
+            Vec::new()
+        });
+        for entry in entries {
+            if in_scope(&entry.key) && self.disk.store.cache_delete(&entry.key).unwrap_or(false) {
+                cleared.insert(entry.key);
+            }
+        }
+        cleared.len()
+    }
+
+    /// Refresh an entry in the background, unless a refresh is already running.
+    fn revalidate<F, Fut, T>(&self, kind: CacheKind, key: String, fetch: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, AppError>> + Send + 'static,
+        T: Into<Fetched> + Send + 'static,
+    {
+        let mut revalidating = self.revalidating.lock().unwrap_or_else(|e| e.into_inner());
+        if !revalidating.insert(key.clone()) {
+            return;
+        }
+        drop(revalidating);
+
+        let cache = self.clone();
+        tokio::spawn(async move {
+            match fetch().await {
+                Ok(fetched) => {
+                    let entry = Entry::new(kind, fetched.into());
+                    cache.disk.write(&key, &entry).await;
+                    cache.memory.insert(key.clone(), entry).await;
+                }
+                Err(e) => tracing::warn!("background refresh of {key} failed: {e}"),
+            }
+            cache
+                .revalidating
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&key);
+        });
+    }
+}
+
+/// Format an age as its two largest units, e.g. `3h 12m`.
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+fn open_store(account: &str, cipher: Option<Arc<Cipher>>) -> Result<Store, AppError> {
+    Store::open(&store_path(account)?, cipher)
+}
+
+/// Print the disk cache entries of an account with their age, last use and size.
+pub fn list_entries(account: &str, cipher: Option<Arc<Cipher>>) -> Result<(), AppError> {
+    let path = store_path(account)?;
+    let entries = Store::open(&path, cipher)?.cache_entries()?;
+    println!("Cache: {}", path.display());
+    if entries.is_empty() {
+        println!("\nNo cached entries.");
+        return Ok(());
+    }
+    println!(
+        "\n{:<40} {:>10} {:>10} {:>11}",
+        "KEY", "AGE", "LAST USED", "SIZE"
+    );
+    let since = |time: DateTime<Utc>| (Utc::now() - time).to_std().unwrap_or_default();
+    let mut total = 0;
+    for entry in entries {
+        println!(
+            "{:<40} {:>10} {:>10} {:>8} KB",
+            entry.key,
+            format_age(since(entry.fetched_at)),
+            format_age(since(entry.last_used)),
+            entry.size.div_ceil(1024)
+        );
+        total += entry.size;
+    }
+    println!("\nTotal: {} KB", total.div_ceil(1024));
+    Ok(())
+}
+
+/// Print one disk cache entry (decrypted if needed).
+pub fn show_entry(account: &str, key: &str, cipher: Option<Arc<Cipher>>) -> Result<(), AppError> {
+    let row = open_store(account, cipher)?
+        .cache_peek(key)?
+        .ok_or_else(|| AppError::InvalidInput(format!("no cache entry '{key}'")))?;
+    println!("Key:     {key}");
+    println!(
+        "Fetched: {} ({} ago)",
+        row.fetched_at.to_rfc3339(),
+        format_age((Utc::now() - row.fetched_at).to_std().unwrap_or_default())
+    );
+    println!("ETag:    {}", row.etag.as_deref().unwrap_or("none"));
+    println!("{}", serde_json::to_string_pretty(&row.value)?);
+    Ok(())
+}
+
+/// Delete the given disk cache entries, or every entry with `all`.
+pub fn purge_entries(
+    account: &str,
+    keys: &[String],
+    all: bool,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<(), AppError> {
+    if keys.is_empty() && !all {
+        return Err(AppError::InvalidInput(
+            "name the keys to purge (see `cache list`) or pass --all".into(),
+        ));
+    }
+    let store = open_store(account, cipher)?;
+    let mut removed = 0;
+    for entry in store.cache_entries()? {
+        if (all || keys.contains(&entry.key)) && store.cache_delete(&entry.key)? {
+            removed += 1;
+        }
+    }
+    println!("Removed {removed} cache entries.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::config::RateLimit;
+
+    fn layer(store: Store) -> CacheLayer {
+        let usage = ApiUsage::new(
+            "test",
+            RateLimit {
+                requests_per_second: 0.0,
+                burst: 1,
+            },
+        );
+        CacheLayer::new(Arc::new(usage), store, &CacheConfig::default())
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_fetch_once() {
+        let cache = layer(Store::open_in_memory(None));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetch = || {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(json!(["course"]))
+            }
+        };
+        let (a, b) = tokio::join!(
+            cache.get(CacheKind::Courses, "", false, fetch()),
+            cache.get(CacheKind::Courses, "", false, fetch()),
+        );
+        assert_eq!(a.unwrap(), json!(["course"]));
+        assert_eq!(b.unwrap(), json!(["course"]));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A fresh entry is served without fetching.
+        cache
+            .get(CacheKind::Courses, "", false, fetch())
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A refresh bypasses it.
+        cache
+            .get(CacheKind::Courses, "", true, fetch())
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn entries_survive_restarts_and_serve_offline() {
+        let store = Store::open_in_memory(None);
+        let cache = layer(store.clone());
+        cache
+            .get(CacheKind::Topics, "42", false, || async {
+                Ok(json!(["topic"]))
+            })
+            .await
+            .unwrap();
+
+        let fail = || || async { Err::<Value, _>(AppError::Transient("down".into())) };
+        let restarted = layer(store.clone());
+        let topics = restarted.get(CacheKind::Topics, "42", false, fail()).await;
+        assert_eq!(topics.unwrap(), json!(["topic"]));
+
+        let offline = layer(store).offline();
+        let topics = offline
+            .get(CacheKind::Topics, "42", true, fail())
+            .await
+            .unwrap();
+        assert_eq!(topics["data"], json!(["topic"]));
+        assert!(topics["asOf"].is_string());
+        let courses = offline.get(CacheKind::Courses, "", false, fail()).await;
+        assert!(matches!(courses, Err(AppError::NotCached(key)) if key == "courses"));
+    }
+
+    #[test]
+    fn scopes_match_keys() {
+        let matches = |scope: CacheScope, key: &str| {
+            let (kind, id) = CacheKind::parse_key(key).unwrap();
+            scope.matches(kind, id)
+        };
+        assert_eq!(
+            CacheKind::parse_key("events_primary:7"),
+            Some((CacheKind::Events, "primary:7"))
+        );
+        assert_eq!(
+            CacheKind::parse_key("courses"),
+            Some((CacheKind::Courses, ""))
+        );
+        assert_eq!(CacheKind::parse_key("coursework"), None);
+
+        assert!(matches(
+            CacheScope::Course(Some("42".into())),
+            "materials_42"
+        ));
+        assert!(!matches(
+            CacheScope::Course(Some("42".into())),
+            "materials_421"
+        ));
+        assert!(!matches(CacheScope::Course(Some("42".into())), "courses"));
+        assert!(matches(CacheScope::Course(None), "courses"));
+        assert!(matches(
+            CacheScope::Calendar(Some("primary".into())),
+            "event_primary:abc"
+        ));
+        assert!(!matches(
+            CacheScope::Calendar(Some("primary".into())),
+            "calendar_list"
+        ));
+        assert!(!matches(CacheScope::DriveFile(None), "topics_42"));
+        assert!(matches(CacheScope::All, "drive_user"));
+    }
+
+    #[test]
+    fn ages_are_compact() {
+        assert_eq!(format_age(Duration::from_secs(42)), "42s");
+        assert_eq!(
+            format_age(Duration::from_secs(3 * 3600 + 12 * 60 + 5)),
+            "3h 12m"
+        );
+        assert_eq!(format_age(Duration::from_secs(2 * 86400 + 3600)), "2d 1h");
+    }
+}
@@ -1,17 +1,221 @@
-use std::time::Duration;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
 
-use chrono::{Days, Utc};
-use moka::future::Cache;
+use chrono::{DateTime, Days, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use google_calendar3::api::{
+    Calendar, ConferenceData, ConferenceSolutionKey, CreateConferenceRequest, Event, EventAttendee,
+    EventDateTime, EventReminder, EventReminders, FreeBusyRequest, FreeBusyRequestItem,
+};
 use serde_json::{json, Value};
+use tokio::task::JoinSet;
 
 use crate::auth::CalendarHubType;
+use crate::cache::{CacheKind, CacheLayer, CacheScope, Fetched};
+use crate::dates::{local, parse_day, parse_time};
 use crate::error::AppError;
+use crate::retry::{self, RetryPolicy};
+use crate::scopes::{
+    ToolGroup, CALENDAR_APP_CREATED, CALENDAR_EVENTS as CALENDAR_WRITE_SCOPE,
+    CALENDAR_READONLY as CALENDAR_SCOPE,
+};
+use crate::session::ManagedAuth;
+use crate::store::{RecordKind, Store};
+use crate::usage::ApiUsage;
 
-const CALENDAR_SCOPE: &str = "https://www.googleapis.com/auth/calendar.readonly";
+/// Which events `list_events` returns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventQuery {
+    /// Start of the window; defaults to now.
+    pub time_min: Option<DateTime<Utc>>,
+    /// End of the window; defaults to `days_ahead` days after its start.
+    pub time_max: Option<DateTime<Utc>>,
+    pub days_ahead: u32,
+    /// Free text matched against the events' summary, description, location,
+    /// attendees and so on.
+    pub q: Option<String>,
+    pub max_results: u32,
+    pub page_token: Option<String>,
+    /// Include cancelled events.
+    pub show_deleted: bool,
+}
+
+impl Default for EventQuery {
+    fn default() -> Self {
+        Self {
+            time_min: None,
+            time_max: None,
+            days_ahead: 7,
+            q: None,
+            max_results: 100,
+            page_token: None,
+            show_deleted: false,
+        }
+    }
+}
 
+impl EventQuery {
+    /// The most events Google returns per page.
+    pub const MAX_RESULTS: u32 = 2500;
+
+    fn window(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let start = self.time_min.unwrap_or(now);
+        let end = self.time_max.unwrap_or_else(|| {
+            start
+                .checked_add_days(Days::new(self.days_ahead as u64))
+                .unwrap_or(start)
+        });
+        (start, end)
+    }
+
+    /// The cache id; it starts with the calendar ID so that `cache_clear`
+    /// can drop one calendar's events. A window relative to now is keyed by
+    /// its length rather than its bounds.
+    fn cache_id(&self, calendar_id: &str) -> String {
+        let bound = |time: Option<DateTime<Utc>>| time.map(|t| t.to_rfc3339()).unwrap_or_default();
+        let end = match self.time_max {
+            Some(_) => bound(self.time_max),
+            None => format!("+{}d", self.days_ahead),
+        };
+        format!(
+            "{calendar_id}:{}..{end}:{}:{}:{}:{}",
+            bound(self.time_min),
+            self.max_results,
+            u8::from(self.show_deleted),
+            self.page_token.as_deref().unwrap_or_default(),
+            self.q.as_deref().unwrap_or_default(),
+        )
+    }
+}
+
+/// What the write tools set on an event. Times are read like
+/// `calendar_events` reads them, in `time_zone` or else the calendar's own
+/// time zone; a field left `None` is left as it is.
+#[derive(Debug, Clone, Default)]
+pub struct EventFields {
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub start: Option<String>,
+    /// Defaults to an hour after a timed start, or the day of an all-day one
+    /// (or, when updating, to the event's previous length).
+    pub end: Option<String>,
+    /// Whether `start` and `end` are days rather than times.
+    pub all_day: bool,
+    pub time_zone: Option<String>,
+    /// Email addresses of the guests.
+    pub attendees: Option<Vec<String>>,
+    /// Minutes before the start of each popup reminder; empty for none.
+    pub reminders: Option<Vec<u32>>,
+    /// RRULE, EXRULE, RDATE and EXDATE lines.
+    pub recurrence: Option<Vec<String>>,
+    /// Add a Google Meet link.
+    pub meet_link: bool,
+}
+
+/// When an event starts or ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EventTime {
+    /// All day; an all-day event ends on the day after its last day.
+    Day(NaiveDate),
+    At(DateTime<Utc>),
+}
+
+impl EventTime {
+    fn of(time: &EventDateTime) -> Option<Self> {
+        match (time.date, time.date_time) {
+            (Some(day), _) => Some(Self::Day(day)),
+            (_, Some(at)) => Some(Self::At(at)),
+            _ => None,
+        }
+    }
+
+    fn to_api(self, time_zone: &str) -> EventDateTime {
+        match self {
+            Self::Day(day) => EventDateTime {
+                date: Some(day),
+                ..EventDateTime::default()
+            },
+            // The zone matters for recurring events, which repeat in it.
+            Self::At(at) => EventDateTime {
+                date_time: Some(at),
+                time_zone: Some(time_zone.to_string()),
+                ..EventDateTime::default()
+            },
+        }
+    }
+}
+
+/// Resolve the start and end of `fields` in `tz`. `previous` is the event's
+/// current start and end, whose length is kept when only the start moves.
+fn event_times(
+    fields: &EventFields,
+    tz: &Tz,
+    previous: Option<(EventTime, EventTime)>,
+) -> Result<Option<(EventTime, EventTime)>, AppError> {
+    let now = Utc::now().with_timezone(tz);
+    let read = |text: &str| -> Result<EventTime, AppError> {
+        Ok(if fields.all_day {
+            EventTime::Day(parse_day(text, &now)?)
+        } else {
+            EventTime::At(parse_time(text, &now, false)?)
+        })
+    };
+    let Some(start) = fields.start.as_deref().map(read).transpose()? else {
+        if fields.end.is_some() || fields.all_day {
+            return Err(AppError::InvalidInput(
+                "give the start as well when changing the end or making an event all-day".into(),
+            ));
+        }
+        return Ok(None);
+    };
+    let end = match (fields.end.as_deref(), start, previous) {
+        // The last day given is included, so the event ends the day after.
+        (Some(text), EventTime::Day(_), _) => match read(text)? {
+            EventTime::Day(day) => EventTime::Day(day.succ_opt().unwrap_or(day)),
+            at => at,
+        },
+        (Some(text), _, _) => read(text)?,
+        (None, EventTime::Day(day), Some((EventTime::Day(from), EventTime::Day(to)))) => {
+            EventTime::Day(day + (to - from))
+        }
+        (None, EventTime::At(at), Some((EventTime::At(from), EventTime::At(to)))) => {
+            EventTime::At(at + (to - from))
+        }
+        (None, EventTime::Day(day), _) => EventTime::Day(day.succ_opt().unwrap_or(day)),
+        (None, EventTime::At(at), _) => EventTime::At(at + Duration::hours(1)),
+    };
+    let ordered = match (start, end) {
+        (EventTime::Day(start), EventTime::Day(end)) => end > start,
+        (EventTime::At(start), EventTime::At(end)) => end > start,
+        _ => false,
+    };
+    if !ordered {
+        return Err(AppError::InvalidInput(
+            "the event must end after it starts".into(),
+        ));
+    }
+    Ok(Some((start, end)))
+}
+
+/// A new event ID. Google accepts IDs chosen by the client, which makes
+/// retrying a create safe: a retry after a lost response finds the event.
+fn new_event_id() -> String {
+    const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+    (0..26)
+        .map(|_| ALPHABET[fastrand::usize(..ALPHABET.len())] as char)
+        .collect()
+}
+
+#[derive(Clone)]
 pub struct CalendarClient {
     hub: CalendarHubType,
-    memory_cache: Cache<String, Value>,
+    usage: Arc<ApiUsage>,
+    cache: CacheLayer,
+    /// The account's snapshot, where fetched objects are saved.
+    store: Store,
+    /// For the scopes the account granted (`None` offline).
+    auth: Option<ManagedAuth>,
 }
 
 impl std::fmt::Debug for CalendarClient {
@@ -21,33 +225,48 @@ impl std::fmt::Debug for CalendarClient {
 }
 
 impl CalendarClient {
-    pub fn new(hub: CalendarHubType) -> Self {
-        let memory_cache = Cache::builder()
-            .max_capacity(200)
-            .time_to_live(Duration::from_secs(300))
-            .build();
+    pub fn new(
+        hub: CalendarHubType,
+        usage: Arc<ApiUsage>,
+        cache: CacheLayer,
+        store: Store,
+        auth: Option<ManagedAuth>,
+    ) -> Self {
+        Self {
+            hub,
+            usage,
+            cache,
+            store,
+            auth,
+        }
+    }
 
-        Self { hub, memory_cache }
+    pub fn cache(&self) -> &CacheLayer {
+        &self.cache
     }
 
     /// List all calendars the authenticated user has access to.
-    pub async fn list_calendars(&self) -> Result<Value, AppError> {
-        let key = "calendar_list".to_string();
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
-        tracing::debug!("memory cache miss: {key}");
-
-        let (_resp, list) = self
-            .hub
-            .calendar_list()
-            .list()
-            .clear_scopes()
-            .add_scope(CALENDAR_SCOPE)
-            .doit()
+    pub async fn list_calendars(&self, refresh: bool) -> Result<Value, AppError> {
+        let this = self.clone();
+        self.cache
+            .get(CacheKind::Calendars, "", refresh, move || async move {
+                this.fetch_calendars().await
+            })
             .await
-            .map_err(|e| AppError::GoogleApi(e.to_string()))?;
+    }
+
+    async fn fetch_calendars(&self) -> Result<Fetched, AppError> {
+        let (resp, list) = retry::call(&self.usage, "calendarList.list", |mut probe| async move {
+            self.hub
+                .calendar_list()
+                .list()
+                .clear_scopes()
+                .add_scope(CALENDAR_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
 
         let calendars: Vec<Value> = list
             .items
@@ -67,42 +286,60 @@ impl CalendarClient {
             .collect();
 
         let value = serde_json::to_value(&calendars).map_err(AppError::Json)?;
-        self.memory_cache.insert(key, value.clone()).await;
-        Ok(value)
+        Ok(Fetched::with_etag(value, &resp))
     }
 
-    /// List upcoming events for a calendar.
+    /// List the events on a calendar in a time window, a page at a time.
     pub async fn list_events(
         &self,
         calendar_id: &str,
-        days_ahead: u32,
+        query: &EventQuery,
+        refresh: bool,
     ) -> Result<Value, AppError> {
-        let key = format!("events:{calendar_id}:{days_ahead}");
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
-        tracing::debug!("memory cache miss: {key}");
-
-        let now = Utc::now();
-        let until = now
-            .checked_add_days(Days::new(days_ahead as u64))
-            .unwrap_or(now);
-
-        let (_resp, list) = self
-            .hub
-            .events()
-            .list(calendar_id)
-            .time_min(now)
-            .time_max(until)
-            .single_events(true)
-            .order_by("startTime")
-            .max_results(100)
-            .clear_scopes()
-            .add_scope(CALENDAR_SCOPE)
-            .doit()
+        let this = self.clone();
+        let id = calendar_id.to_string();
+        let request = query.clone();
+        self.cache
+            .get(
+                CacheKind::Events,
+                &query.cache_id(calendar_id),
+                refresh,
+                move || async move { this.fetch_events(&id, &request).await },
+            )
             .await
-            .map_err(|e| AppError::GoogleApi(e.to_string()))?;
+    }
+
+    async fn fetch_events(
+        &self,
+        calendar_id: &str,
+        query: &EventQuery,
+    ) -> Result<Fetched, AppError> {
+        let (time_min, time_max) = query.window(Utc::now());
+
+        let (resp, list) = retry::call(&self.usage, "events.list", |mut probe| async move {
+            let mut call = self
+                .hub
+                .events()
+                .list(calendar_id)
+                .time_min(time_min)
+                .time_max(time_max)
+                .single_events(true)
+                .order_by("startTime")
+                .max_results(query.max_results as i32)
+                .show_deleted(query.show_deleted);
+            if let Some(q) = &query.q {
+                call = call.q(q);
+            }
+            if let Some(token) = &query.page_token {
+                call = call.page_token(token);
+            }
+            call.clear_scopes()
+                .add_scope(CALENDAR_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
 
         let events: Vec<Value> = list
             .items
@@ -111,6 +348,7 @@ impl CalendarClient {
             .map(|evt| {
                 json!({
                     "id": evt.id,
+                    "iCalUID": evt.i_cal_uid,
                     "summary": evt.summary,
                     "description": evt.description,
                     "location": evt.location,
@@ -137,37 +375,756 @@ impl CalendarClient {
                 })
             })
             .collect();
+        self.store
+            .persist_records(RecordKind::Event, calendar_id, events.clone())
+            .await;
+
+        let value = json!({
+            "timeMin": time_min.to_rfc3339(),
+            "timeMax": time_max.to_rfc3339(),
+            "events": events,
+            "nextPageToken": list.next_page_token,
+        });
+        Ok(Fetched::with_etag(value, &resp))
+    }
+
+    /// Events from several calendars, or from every calendar when
+    /// `calendar_ids` is `None`, merged into one list ordered by start time
+    /// (see [`merge_events`]). Calendars are fetched concurrently; one that
+    /// fails is reported under `failures` rather than failing the agenda.
+    pub async fn agenda(
+        &self,
+        calendar_ids: Option<&[String]>,
+        query: &EventQuery,
+        refresh: bool,
+    ) -> Result<Value, AppError> {
+        let listed = self.data(self.list_calendars(refresh).await?);
+        let listed = listed.as_array().cloned().unwrap_or_default();
+        let mut calendars: Vec<Value> = match calendar_ids {
+            None => listed,
+            Some(ids) => ids
+                .iter()
+                .map(|id| {
+                    listed
+                        .iter()
+                        .find(|cal| cal["id"] == id.as_str())
+                        .cloned()
+                        .unwrap_or_else(|| json!({ "id": id }))
+                })
+                .collect(),
+        };
+        // All-day events are placed at midnight in the user's time zone.
+        let tz = self.zone("primary", false).await?;
+        // The primary calendar's copy of a shared event is the one kept.
+        calendars.sort_by_key(|cal| cal["primary"] != true);
+
+        let mut tasks = JoinSet::new();
+        for (index, calendar) in calendars.iter().enumerate() {
+            let (this, query) = (self.clone(), query.clone());
+            let id = calendar["id"].as_str().unwrap_or_default().to_string();
+            tasks.spawn(async move { (index, this.list_events(&id, &query, refresh).await) });
+        }
+        let mut results: Vec<Option<Result<Value, AppError>>> =
+            calendars.iter().map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok((index, result)) = joined {
+                results[index] = Some(result);
+            }
+        }
+
+        let mut window = None;
+        let mut listings = Vec::new();
+        let mut included = Vec::new();
+        let mut failures = Vec::new();
+        let mut first_error = None;
+        for (calendar, result) in calendars.into_iter().zip(results) {
+            let name = calendar_name(&calendar);
+            let listing = match result {
+                Some(Ok(listing)) => listing,
+                Some(Err(e)) => {
+                    failures.push(format!("{name}: {e}"));
+                    first_error.get_or_insert(e);
+                    continue;
+                }
+                None => {
+                    failures.push(format!("{name}: the request was aborted"));
+                    continue;
+                }
+            };
+            let as_of = listing.get("asOf").cloned();
+            let listing = self.data(listing);
+            window.get_or_insert_with(|| (listing["timeMin"].clone(), listing["timeMax"].clone()));
+            let events = listing["events"].as_array().cloned().unwrap_or_default();
+            included.push(json!({
+                "id": calendar["id"],
+                "name": name,
+                "color": calendar["backgroundColor"],
+                "events": events.len(),
+                // Only the first page of each calendar is merged.
+                "more": listing["nextPageToken"].is_string(),
+                "asOf": as_of,
+            }));
+            listings.push((calendar, events));
+        }
+        if included.is_empty() {
+            if let Some(e) = first_error {
+                return Err(e);
+            }
+        }
+
+        let (time_min, time_max) = window.unwrap_or_default();
+        Ok(json!({
+            "timeMin": time_min,
+            "timeMax": time_max,
+            "events": merge_events(listings, &tz),
+            "calendars": included,
+            "failures": failures,
+        }))
+    }
+
+    /// The time zone to read a calendar's times in, from the (cached)
+    /// calendar list: the calendar's own, else the primary calendar's, else
+    /// UTC.
+    pub async fn zone(&self, calendar_id: &str, refresh: bool) -> Result<Tz, AppError> {
+        let calendars = match self.list_calendars(refresh).await {
+            Ok(calendars) => self.data(calendars),
+            // Offline, and the calendar list was never fetched.
+            Err(AppError::NotCached(_)) => Value::Null,
+            Err(e) => return Err(e),
+        };
+        let calendars = calendars.as_array().map(Vec::as_slice).unwrap_or_default();
+        let primary = calendars.iter().find(|cal| cal["primary"] == true);
+        let own = match calendar_id {
+            "primary" => primary,
+            id => calendars.iter().find(|cal| cal["id"] == id),
+        };
+        let zone = |calendar: Option<&Value>| {
+            calendar
+                .and_then(|cal| cal["timeZone"].as_str())
+                .and_then(|zone| zone.parse::<Tz>().ok())
+        };
+        Ok(zone(own).or_else(|| zone(primary)).unwrap_or(Tz::UTC))
+    }
+
+    /// When the given calendars are busy between `time_min` and `time_max`,
+    /// as sorted, non-overlapping periods, along with the calendars Google
+    /// couldn't report on. Always asks Google: free time must be current.
+    pub async fn busy(
+        &self,
+        calendar_ids: &[String],
+        time_min: DateTime<Utc>,
+        time_max: DateTime<Utc>,
+    ) -> Result<(Vec<Period>, Vec<String>), AppError> {
+        let request = FreeBusyRequest {
+            time_min: Some(time_min),
+            time_max: Some(time_max),
+            items: Some(
+                calendar_ids
+                    .iter()
+                    .map(|id| FreeBusyRequestItem {
+                        id: Some(id.clone()),
+                    })
+                    .collect(),
+            ),
+            ..FreeBusyRequest::default()
+        };
+        let request = &request;
+        let (_resp, response) =
+            retry::call(&self.usage, "freebusy.query", |mut probe| async move {
+                self.hub
+                    .freebusy()
+                    .query(request.clone())
+                    .clear_scopes()
+                    .add_scope(CALENDAR_SCOPE)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            })
+            .await?;
+
+        let mut periods = Vec::new();
+        let mut failures = Vec::new();
+        for (id, calendar) in response.calendars.unwrap_or_default() {
+            if let Some(errors) = calendar.errors.filter(|errors| !errors.is_empty()) {
+                let reasons: Vec<String> = errors.into_iter().filter_map(|e| e.reason).collect();
+                failures.push(format!("{id}: {}", reasons.join(", ")));
+                continue;
+            }
+            periods.extend(
+                calendar
+                    .busy
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|period| Some((period.start?, period.end?))),
+            );
+        }
+        failures.sort();
+        Ok((merge_periods(periods), failures))
+    }
+
+    /// Create an event and return it.
+    pub async fn create_event(
+        &self,
+        calendar_id: &str,
+        fields: &EventFields,
+        notify: bool,
+    ) -> Result<Value, AppError> {
+        self.require_online()?;
+        let tz = self.event_time_zone(calendar_id, fields).await?;
+        let times = event_times(fields, &tz, None)?
+            .ok_or_else(|| AppError::InvalidInput("a new event needs a start".into()))?;
+        let id = new_event_id();
+        let mut event = api_event(fields, Some(times), &tz, &id);
+        event.id = Some(id);
+        self.insert_event(calendar_id, event, notify).await
+    }
+
+    /// Insert `event` as it is, giving it an ID if it has none, and return it.
+    pub async fn insert_event(
+        &self,
+        calendar_id: &str,
+        mut event: Event,
+        notify: bool,
+    ) -> Result<Value, AppError> {
+        let id = event.id.get_or_insert_with(new_event_id).clone();
+        let event = &event;
+        let result = retry::call(&self.usage, "events.insert", |mut probe| async move {
+            self.hub
+                .events()
+                .insert(event.clone(), calendar_id)
+                .conference_data_version(1)
+                .send_updates(send_updates(notify))
+                .clear_scopes()
+                .add_scope(CALENDAR_WRITE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await;
+        let created = match result {
+            Ok((_resp, created)) => created,
+            // An earlier attempt created it, but its response was lost.
+            Err(e) if e.status.is_some_and(|s| s.as_u16() == 409) => {
+                self.event_for_write(calendar_id, &id).await?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.written(calendar_id, &created).await
+    }
+
+    /// Change the given fields of an event and return it.
+    pub async fn update_event(
+        &self,
+        calendar_id: &str,
+        event_id: &str,
+        fields: &EventFields,
+        notify: bool,
+    ) -> Result<Value, AppError> {
+        self.require_online()?;
+        let tz = self.event_time_zone(calendar_id, fields).await?;
+        let previous = match &fields.start {
+            Some(_) => {
+                let event = self.event_for_write(calendar_id, event_id).await?;
+                event
+                    .start
+                    .as_ref()
+                    .and_then(EventTime::of)
+                    .zip(event.end.as_ref().and_then(EventTime::of))
+            }
+            None => None,
+        };
+        let times = event_times(fields, &tz, previous)?;
+        let event = api_event(fields, times, &tz, event_id);
+        self.patch_event(calendar_id, event_id, event, notify).await
+    }
 
-        let value = serde_json::to_value(&events).map_err(AppError::Json)?;
-        self.memory_cache.insert(key, value.clone()).await;
+    /// Set the fields present in `event` on an event and return it.
+    pub async fn patch_event(
+        &self,
+        calendar_id: &str,
+        event_id: &str,
+        event: Event,
+        notify: bool,
+    ) -> Result<Value, AppError> {
+        let event = &event;
+        let (_resp, updated) = retry::call(&self.usage, "events.patch", |mut probe| async move {
+            self.hub
+                .events()
+                .patch(event.clone(), calendar_id, event_id)
+                .conference_data_version(1)
+                .send_updates(send_updates(notify))
+                .clear_scopes()
+                .add_scope(CALENDAR_WRITE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
+        self.written(calendar_id, &updated).await
+    }
+
+    /// Delete an event; for a recurring event, every instance.
+    pub async fn delete_event(
+        &self,
+        calendar_id: &str,
+        event_id: &str,
+        notify: bool,
+    ) -> Result<Value, AppError> {
+        self.require_online()?;
+        let mut attempts = 0;
+        let result = retry::call(&self.usage, "events.delete", |mut probe| {
+            attempts += 1;
+            async move {
+                self.hub
+                    .events()
+                    .delete(calendar_id, event_id)
+                    .send_updates(send_updates(notify))
+                    .clear_scopes()
+                    .add_scope(CALENDAR_WRITE_SCOPE)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            }
+        })
+        .await;
+        if let Err(e) = result {
+            // Gone on a retry: an earlier attempt deleted it, but its
+            // response was lost.
+            let gone = e.status.is_some_and(|s| matches!(s.as_u16(), 404 | 410));
+            if !(gone && attempts > 1) {
+                return Err(e.into());
+            }
+        }
+        self.cache
+            .clear(&CacheScope::Calendar(Some(calendar_id.to_string())))
+            .await;
+        Ok(json!({ "deleted": true, "calendarId": calendar_id, "eventId": event_id }))
+    }
+
+    /// Create a secondary calendar and return its ID.
+    pub async fn create_calendar(&self, summary: &str) -> Result<String, AppError> {
+        let calendar = Calendar {
+            summary: Some(summary.to_string()),
+            ..Calendar::default()
+        };
+        let calendar = &calendar;
+        // Sent once: a retry after a lost response would create a second
+        // calendar, leaving one of them orphaned.
+        let once = RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        };
+        let (_resp, created) = retry::call_with(
+            &once,
+            &self.usage,
+            "calendars.insert",
+            |mut probe| async move {
+                self.hub
+                    .calendars()
+                    .insert(calendar.clone())
+                    .clear_scopes()
+                    .add_scope(CALENDAR_APP_CREATED)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            },
+        )
+        .await?;
+        self.cache.clear(&CacheScope::Calendar(None)).await;
+        created
+            .id
+            .ok_or_else(|| AppError::InvalidResponse("the new calendar has no ID".into()))
+    }
+
+    /// Every event of a calendar, recurring ones unexpanded, bypassing the
+    /// cache.
+    pub async fn all_events(&self, calendar_id: &str) -> Result<Vec<Event>, AppError> {
+        let mut events = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let page = token.as_deref();
+            let (_resp, list) = retry::call(&self.usage, "events.list", |mut probe| async move {
+                let mut call = self.hub.events().list(calendar_id).max_results(2500);
+                if let Some(page) = page {
+                    call = call.page_token(page);
+                }
+                call.clear_scopes()
+                    .add_scope(CALENDAR_WRITE_SCOPE)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            })
+            .await?;
+            events.extend(list.items.unwrap_or_default());
+            match list.next_page_token {
+                Some(next) => token = Some(next),
+                None => return Ok(events),
+            }
+        }
+    }
+
+    /// An event as it is now, fetched with the write scope.
+    async fn event_for_write(&self, calendar_id: &str, event_id: &str) -> Result<Event, AppError> {
+        let (_resp, event) = retry::call(&self.usage, "events.get", |mut probe| async move {
+            self.hub
+                .events()
+                .get(calendar_id, event_id)
+                .clear_scopes()
+                .add_scope(CALENDAR_WRITE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
+        Ok(event)
+    }
+
+    /// The zone to read an event's times in: the one asked for, or the
+    /// calendar's own.
+    async fn event_time_zone(
+        &self,
+        calendar_id: &str,
+        fields: &EventFields,
+    ) -> Result<Tz, AppError> {
+        match &fields.time_zone {
+            Some(zone) => zone
+                .parse()
+                .map_err(|_| AppError::InvalidInput(format!("unknown time zone '{zone}'"))),
+            None if fields.start.is_none() => Ok(Tz::UTC),
+            None => {
+                let granted = self.auth.as_ref().map(ManagedAuth::granted_scopes);
+                if can_list_calendars(granted.as_ref()) {
+                    return self.zone(calendar_id, false).await;
+                }
+                // Without the `calendar` group, ask the calendar itself.
+                let (_resp, list) =
+                    retry::call(&self.usage, "events.list", |mut probe| async move {
+                        self.hub
+                            .events()
+                            .list(calendar_id)
+                            .max_results(1)
+                            .clear_scopes()
+                            .add_scope(CALENDAR_WRITE_SCOPE)
+                            .delegate(&mut probe)
+                            .doit()
+                            .await
+                    })
+                    .await?;
+                Ok(list
+                    .time_zone
+                    .and_then(|zone| zone.parse().ok())
+                    .unwrap_or(Tz::UTC))
+            }
+        }
+    }
+
+    /// Save a created or updated event and drop the calendar's cached events.
+    async fn written(&self, calendar_id: &str, event: &Event) -> Result<Value, AppError> {
+        let value = serde_json::to_value(event)?;
+        if let Some(id) = &event.id {
+            self.store
+                .persist_record(RecordKind::Event, calendar_id, id, value.clone())
+                .await;
+        }
+        self.cache
+            .clear(&CacheScope::Calendar(Some(calendar_id.to_string())))
+            .await;
         Ok(value)
     }
 
+    /// Events are written at Google, so writes fail offline rather than
+    /// sending requests without credentials.
+    fn require_online(&self) -> Result<(), AppError> {
+        if self.cache.is_offline() {
+            return Err(AppError::FailedPrecondition(
+                "events are changed at Google, which isn't possible offline".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The value of a cache response, without the `{asOf, data}` wrapper
+    /// it has offline.
+    fn data(&self, value: Value) -> Value {
+        if self.cache.is_offline() {
+            value.get("data").cloned().unwrap_or(value)
+        } else {
+            value
+        }
+    }
+
     /// Get full details for a single event.
     pub async fn get_event(
         &self,
         calendar_id: &str,
         event_id: &str,
+        refresh: bool,
     ) -> Result<Value, AppError> {
-        let key = format!("event:{calendar_id}:{event_id}");
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
-        tracing::debug!("memory cache miss: {key}");
-
-        let (_resp, event) = self
-            .hub
-            .events()
-            .get(calendar_id, event_id)
-            .clear_scopes()
-            .add_scope(CALENDAR_SCOPE)
-            .doit()
+        let this = self.clone();
+        let (calendar, event) = (calendar_id.to_string(), event_id.to_string());
+        self.cache
+            .get(
+                CacheKind::Event,
+                &format!("{calendar_id}:{event_id}"),
+                refresh,
+                move || async move { this.fetch_event(&calendar, &event).await },
+            )
             .await
-            .map_err(|e| AppError::GoogleApi(e.to_string()))?;
+    }
+
+    async fn fetch_event(&self, calendar_id: &str, event_id: &str) -> Result<Fetched, AppError> {
+        let (resp, event) = retry::call(&self.usage, "events.get", |mut probe| async move {
+            self.hub
+                .events()
+                .get(calendar_id, event_id)
+                .clear_scopes()
+                .add_scope(CALENDAR_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
 
         let value = serde_json::to_value(&event).map_err(AppError::Json)?;
-        self.memory_cache.insert(key, value.clone()).await;
-        Ok(value)
+        self.store
+            .persist_records(RecordKind::Event, calendar_id, vec![value.clone()])
+            .await;
+        Ok(Fetched::with_etag(value, &resp))
+    }
+}
+
+/// The API's form of `fields`, for an insert or a patch.
+fn api_event(
+    fields: &EventFields,
+    times: Option<(EventTime, EventTime)>,
+    tz: &Tz,
+    id: &str,
+) -> Event {
+    let (start, end) = times.unzip();
+    Event {
+        summary: fields.summary.clone(),
+        description: fields.description.clone(),
+        location: fields.location.clone(),
+        start: start.map(|time| time.to_api(tz.name())),
+        end: end.map(|time| time.to_api(tz.name())),
+        attendees: fields.attendees.as_ref().map(|emails| {
+            emails
+                .iter()
+                .map(|email| EventAttendee {
+                    email: Some(email.clone()),
+                    ..EventAttendee::default()
+                })
+                .collect()
+        }),
+        reminders: fields.reminders.as_ref().map(|minutes| EventReminders {
+            use_default: Some(false),
+            overrides: Some(
+                minutes
+                    .iter()
+                    .map(|&minutes| EventReminder {
+                        method: Some("popup".into()),
+                        minutes: Some(minutes as i32),
+                    })
+                    .collect(),
+            ),
+        }),
+        recurrence: fields.recurrence.clone(),
+        conference_data: fields.meet_link.then(|| ConferenceData {
+            create_request: Some(CreateConferenceRequest {
+                request_id: Some(format!("{id}-meet")),
+                conference_solution_key: Some(ConferenceSolutionKey {
+                    type_: Some("hangoutsMeet".into()),
+                }),
+                ..CreateConferenceRequest::default()
+            }),
+            ..ConferenceData::default()
+        }),
+        ..Event::default()
+    }
+}
+
+fn send_updates(notify: bool) -> &'static str {
+    if notify {
+        "all"
+    } else {
+        "none"
+    }
+}
+
+/// A span of time, start first.
+pub type Period = (DateTime<Utc>, DateTime<Utc>);
+
+/// Sort periods and join those that overlap or touch.
+pub fn merge_periods(mut periods: Vec<Period>) -> Vec<Period> {
+    periods.sort();
+    let mut merged: Vec<Period> = Vec::with_capacity(periods.len());
+    for (start, end) in periods {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn calendar_name(calendar: &Value) -> String {
+    calendar["summary"]
+        .as_str()
+        .or(calendar["id"].as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Whether the calendar list can be read with the `granted` scopes (`None`
+/// offline, where the cached list is used).
+fn can_list_calendars(granted: Option<&BTreeSet<String>>) -> bool {
+    granted.is_none_or(|granted| ToolGroup::Calendar.is_granted(granted))
+}
+
+/// When an event starts; all-day events start at midnight in `tz`.
+fn event_start(event: &Value, tz: &Tz) -> Option<DateTime<Utc>> {
+    let start = &event["start"];
+    if let Some(time) = start["dateTime"].as_str() {
+        return DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|t| t.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(start["date"].as_str()?, "%Y-%m-%d").ok()?;
+    Some(local(tz, date.and_time(NaiveTime::MIN)))
+}
+
+/// Merge the events of several calendars, in start order, tagging each with
+/// its calendar. An event on more than one calendar (the same `iCalUID` and
+/// start, since the instances of a recurring event share their `iCalUID`)
+/// is kept from the first calendar, with the others listed under `alsoOn`.
+fn merge_events(listings: Vec<(Value, Vec<Value>)>, tz: &Tz) -> Vec<Value> {
+    let mut merged: Vec<Value> = Vec::new();
+    let mut seen: HashMap<(String, Option<DateTime<Utc>>), usize> = HashMap::new();
+    for (calendar, events) in listings {
+        let name = calendar_name(&calendar);
+        for mut event in events {
+            let start = event_start(&event, tz);
+            let uid = event["iCalUID"].as_str().map(String::from);
+            if let Some(&index) = uid.as_ref().and_then(|uid| seen.get(&(uid.clone(), start))) {
+                let also_on = &mut merged[index]["alsoOn"];
+                match also_on.as_array_mut() {
+                    Some(names) => names.push(json!(name)),
+                    None => *also_on = json!([name]),
+                }
+                continue;
+            }
+            if let Some(uid) = uid {
+                seen.insert((uid, start), merged.len());
+            }
+            event["calendarId"] = calendar["id"].clone();
+            event["calendarName"] = json!(name);
+            event["calendarColor"] = calendar["backgroundColor"].clone();
+            merged.push(event);
+        }
+    }
+    // Events without a start (cancelled ones) go last.
+    merged.sort_by_key(|event| (event_start(event, tz).is_none(), event_start(event, tz)));
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_only_accounts_skip_the_calendar_list() {
+        let granted = |groups: &[ToolGroup]| -> BTreeSet<String> {
+            groups
+                .iter()
+                .flat_map(|group| group.scopes())
+                .map(|scope| scope.to_string())
+                .collect()
+        };
+        assert!(can_list_calendars(None));
+        assert!(can_list_calendars(Some(&granted(&[
+            ToolGroup::Calendar,
+            ToolGroup::CalendarWrite
+        ]))));
+        assert!(!can_list_calendars(Some(&granted(&[
+            ToolGroup::Classroom,
+            ToolGroup::CalendarWrite
+        ]))));
+    }
+
+    #[test]
+    fn merges_calendars_in_start_order() {
+        let school = json!({"id": "school", "summary": "School", "backgroundColor": "#16a765"});
+        let family = json!({"id": "family", "summary": "Family", "backgroundColor": "#f83a22"});
+        let event = |id: &str, uid: &str, start: &str| json!({"id": id, "iCalUID": uid, "start": {"dateTime": start}});
+        let listings = vec![
+            (
+                school,
+                vec![
+                    event("a", "exam@x", "2026-03-02T09:00:00Z"),
+                    event("b1", "club@x", "2026-03-02T15:00:00Z"),
+                    event("b2", "club@x", "2026-03-03T15:00:00Z"),
+                ],
+            ),
+            (
+                family,
+                vec![
+                    event("c", "dinner@y", "2026-03-02T18:00:00+01:00"),
+                    // The school's 09:00 UTC exam, as seen from another time zone.
+                    event("a2", "exam@x", "2026-03-02T10:00:00+01:00"),
+                    // Starts at 11:00 UTC the day before, in Auckland.
+                    json!({"id": "d", "start": {"date": "2026-03-03"}}),
+                ],
+            ),
+        ];
+        let merged = merge_events(listings, &chrono_tz::Pacific::Auckland);
+
+        let ids: Vec<&str> = merged.iter().map(|e| e["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["a", "d", "b1", "c", "b2"]);
+        assert_eq!(merged[0]["calendarName"], "School");
+        assert_eq!(merged[0]["alsoOn"], json!(["Family"]));
+        assert_eq!(merged[3]["calendarColor"], "#f83a22");
+        assert!(merged[2].get("alsoOn").is_none());
+    }
+
+    #[test]
+    fn event_times_fill_in_the_end() {
+        let tz = chrono_tz::Europe::Berlin;
+        let fields = |start: &str, end: Option<&str>, all_day| EventFields {
+            start: Some(start.into()),
+            end: end.map(String::from),
+            all_day,
+            ..EventFields::default()
+        };
+        let at = |text: &str| EventTime::At(text.parse().unwrap());
+        let day = |text: &str| EventTime::Day(text.parse().unwrap());
+
+        let times = event_times(&fields("2026-03-02 14:00", None, false), &tz, None).unwrap();
+        assert_eq!(
+            times,
+            Some((at("2026-03-02T13:00:00Z"), at("2026-03-02T14:00:00Z")))
+        );
+        let times = event_times(&fields("2026-03-02", Some("2026-03-04"), true), &tz, None);
+        assert_eq!(times.unwrap(), Some((day("2026-03-02"), day("2026-03-05"))));
+        // Moving a 30-minute event keeps its length.
+        let previous = (at("2026-03-01T08:00:00Z"), at("2026-03-01T08:30:00Z"));
+        let times = event_times(
+            &fields("2026-03-02T10:00:00Z", None, false),
+            &tz,
+            Some(previous),
+        );
+        assert_eq!(
+            times.unwrap(),
+            Some((at("2026-03-02T10:00:00Z"), at("2026-03-02T10:30:00Z")))
+        );
+        assert!(event_times(
+            &fields("2026-03-02 14:00", Some("2026-03-02 13:00"), false),
+            &tz,
+            None
+        )
+        .is_err());
+        assert_eq!(
+            event_times(&EventFields::default(), &tz, None).unwrap(),
+            None
+        );
     }
 }
@@ -1,16 +1,24 @@
-use std::path::PathBuf;
-use std::time::Duration;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::Arc;
 
-use moka::future::Cache;
+use serde::Serialize;
 use serde_json::{json, Value};
 
 use crate::auth::ClassroomHub;
+use crate::cache::{CacheKind, CacheLayer, Fetched};
 use crate::error::AppError;
+use crate::retry;
+use crate::store::{RecordKind, Store};
+use crate::usage::ApiUsage;
 
+#[derive(Clone)]
 pub struct ClassroomClient {
     hub: ClassroomHub,
-    memory_cache: Cache<String, Value>,
-    cache_dir: PathBuf,
+    usage: Arc<ApiUsage>,
+    cache: CacheLayer,
+    /// The account's snapshot, where fetched objects are saved.
+    store: Store,
 }
 
 impl std::fmt::Debug for ClassroomClient {
@@ -20,110 +28,95 @@ impl std::fmt::Debug for ClassroomClient {
 }
 
 impl ClassroomClient {
-    pub fn new(hub: ClassroomHub) -> Self {
-        let memory_cache = Cache::builder()
-            .max_capacity(1000)
-            .time_to_live(Duration::from_secs(300))
-            .build();
-
-        let cache_dir = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("personal-google-mcp")
-            .join("cache");
-
-        if let Err(e) = std::fs::create_dir_all(&cache_dir) {
-            tracing::warn!("failed to create disk cache directory: {e}");
-        }
-
+    pub fn new(hub: ClassroomHub, usage: Arc<ApiUsage>, cache: CacheLayer, store: Store) -> Self {
         Self {
             hub,
-            memory_cache,
-            cache_dir,
+            usage,
+            cache,
+            store,
         }
     }
 
-    /// Read a value from the disk cache.
-    fn read_disk_cache(&self, key: &str) -> Option<Value> {
-        let path = self.cache_dir.join(format!("{key}.json"));
-        match std::fs::read_to_string(&path) {
-            Ok(data) => match serde_json::from_str(&data) {
-                Ok(val) => {
-                    tracing::debug!("disk cache hit: {key}");
-                    Some(val)
-                }
-                Err(e) => {
-                    tracing::warn!("disk cache corrupted for {key}: {e}");
-                    None
-                }
-            },
-            Err(_) => None,
-        }
-    }
-
-    /// Write a value to the disk cache.
-    fn write_disk_cache(&self, key: &str, value: &Value) {
-        let path = self.cache_dir.join(format!("{key}.json"));
-        match serde_json::to_string_pretty(value) {
-            Ok(data) => {
-                if let Err(e) = std::fs::write(&path, data) {
-                    tracing::warn!("failed to write disk cache for {key}: {e}");
-                }
-            }
-            Err(e) => {
-                tracing::warn!("failed to serialize for disk cache {key}: {e}");
-            }
-        }
+    pub fn cache(&self) -> &CacheLayer {
+        &self.cache
     }
 
     /// List all courses the authenticated user can see.
-    pub async fn list_courses(&self) -> Result<Value, AppError> {
-        let key = "courses".to_string();
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
-        tracing::debug!("memory cache miss: {key}");
-
-        let (_resp, list) = self
-            .hub
-            .courses()
-            .list()
-            .page_size(100)
-            .doit()
+    pub async fn list_courses(&self, refresh: bool) -> Result<Value, AppError> {
+        let this = self.clone();
+        self.cache
+            .get(CacheKind::Courses, "", refresh, move || async move {
+                this.fetch_courses().await
+            })
             .await
-            .map_err(|e| AppError::GoogleApi(e.to_string()))?;
+    }
+
+    async fn fetch_courses(&self) -> Result<Fetched, AppError> {
+        let (resp, list) = retry::call(&self.usage, "courses.list", |mut probe| async move {
+            self.hub
+                .courses()
+                .list()
+                .page_size(100)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
 
         let courses = list.courses.unwrap_or_default();
         let value = serde_json::to_value(&courses).map_err(AppError::Json)?;
-        self.memory_cache.insert(key, value.clone()).await;
-        Ok(value)
+        if let Value::Array(items) = &value {
+            self.store
+                .persist_records(RecordKind::Course, "", items.clone())
+                .await;
+        }
+        Ok(Fetched::with_etag(value, &resp))
     }
 
     /// Get course details plus its most recent announcements.
-    pub async fn get_course_details(&self, course_id: &str) -> Result<Value, AppError> {
-        let key = format!("course_details:{course_id}");
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
-        tracing::debug!("memory cache miss: {key}");
-
-        let (_resp, course) = self
-            .hub
-            .courses()
-            .get(course_id)
-            .doit()
-            .await
-            .map_err(|e| AppError::GoogleApi(e.to_string()))?;
-
-        let announcements = match self
-            .hub
-            .courses()
-            .announcements_list(course_id)
-            .page_size(20)
-            .doit()
+    pub async fn get_course_details(
+        &self,
+        course_id: &str,
+        refresh: bool,
+    ) -> Result<Value, AppError> {
+        let this = self.clone();
+        let id = course_id.to_string();
+        self.cache
+            .get(
+                CacheKind::CourseDetails,
+                course_id,
+                refresh,
+                move || async move { this.fetch_course_details(&id).await },
+            )
             .await
-        {
+    }
+
+    async fn fetch_course_details(&self, course_id: &str) -> Result<Value, AppError> {
+        let (_resp, course) = retry::call(&self.usage, "courses.get", |mut probe| async move {
+            self.hub
+                .courses()
+                .get(course_id)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
+
+        let result = retry::call(
+            &self.usage,
+            "courses.announcements.list",
+            |mut probe| async move {
+                self.hub
+                    .courses()
+                    .announcements_list(course_id)
+                    .page_size(20)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            },
+        )
+        .await;
+        let announcements = match result {
             Ok((_resp, list)) => {
                 serde_json::to_value(list.announcements.unwrap_or_default()).unwrap_or(json!([]))
             }
@@ -137,35 +130,57 @@ impl ClassroomClient {
             "course": serde_json::to_value(&course).map_err(AppError::Json)?,
             "announcements": announcements,
         });
-        self.memory_cache.insert(key, value.clone()).await;
+        self.store
+            .persist_records(RecordKind::Course, "", vec![value["course"].clone()])
+            .await;
+        if let Value::Array(items) = &value["announcements"] {
+            self.store
+                .persist_records(RecordKind::Announcement, course_id, items.clone())
+                .await;
+        }
         Ok(value)
     }
 
     /// Get coursework for a course plus student submissions for the first 5 assignments.
-    pub async fn get_assignments(&self, course_id: &str) -> Result<Value, AppError> {
-        let key = format!("assignments:{course_id}");
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
-        tracing::debug!("memory cache miss: {key}");
-
-        let (_resp, course) = self
-            .hub
-            .courses()
-            .get(course_id)
-            .doit()
-            .await
-            .map_err(|e| AppError::GoogleApi(e.to_string()))?;
-
-        let course_work_list = match self
-            .hub
-            .courses()
-            .course_work_list(course_id)
-            .page_size(50)
-            .doit()
+    pub async fn get_assignments(&self, course_id: &str, refresh: bool) -> Result<Value, AppError> {
+        let this = self.clone();
+        let id = course_id.to_string();
+        self.cache
+            .get(
+                CacheKind::Assignments,
+                course_id,
+                refresh,
+                move || async move { this.fetch_assignments(&id).await },
+            )
             .await
-        {
+    }
+
+    async fn fetch_assignments(&self, course_id: &str) -> Result<Value, AppError> {
+        let (_resp, course) = retry::call(&self.usage, "courses.get", |mut probe| async move {
+            self.hub
+                .courses()
+                .get(course_id)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
+
+        let result = retry::call(
+            &self.usage,
+            "courses.courseWork.list",
+            |mut probe| async move {
+                self.hub
+                    .courses()
+                    .course_work_list(course_id)
+                    .page_size(50)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            },
+        )
+        .await;
+        let course_work_list = match result {
             Ok((_resp, list)) => list.course_work.unwrap_or_default(),
             Err(e) => {
                 tracing::warn!("failed to fetch coursework for {course_id}: {e}");
@@ -180,12 +195,19 @@ impl ClassroomClient {
                 None => continue,
             };
 
-            let submissions = match self
-                .hub
-                .courses()
-                .course_work_student_submissions_list(course_id, cw_id)
-                .doit()
-                .await
+            let submissions = match retry::call(
+                &self.usage,
+                "courses.courseWork.studentSubmissions.list",
+                |mut probe| async move {
+                    self.hub
+                        .courses()
+                        .course_work_student_submissions_list(course_id, cw_id)
+                        .delegate(&mut probe)
+                        .doit()
+                        .await
+                },
+            )
+            .await
             {
                 Ok((_resp, list)) => {
                     serde_json::to_value(list.student_submissions.unwrap_or_default())
@@ -217,99 +239,342 @@ impl ClassroomClient {
             "course": serde_json::to_value(&course).map_err(AppError::Json)?,
             "assignments": assignments,
         });
-        self.memory_cache.insert(key, value.clone()).await;
+        self.record_assignments(course_id, &value).await;
         Ok(value)
     }
 
+    /// Save the coursework and submissions of an `assignments` result.
+    async fn record_assignments(&self, course_id: &str, value: &Value) {
+        let assignments = value["assignments"].as_array().into_iter().flatten();
+        let course_work: Vec<Value> = assignments
+            .clone()
+            .map(|a| a["courseWork"].clone())
+            .collect();
+        let submissions: Vec<Value> = assignments
+            .filter_map(|a| a["submissions"].as_array())
+            .flatten()
+            .cloned()
+            .collect();
+        self.store
+            .persist_records(RecordKind::CourseWork, course_id, course_work)
+            .await;
+        self.store
+            .persist_records(RecordKind::Submission, course_id, submissions)
+            .await;
+    }
+
     /// Get course work materials (posted resources) for a course.
     /// Results are persisted to disk so they survive restarts and remain
     /// available even after losing access to the course.
-    pub async fn get_course_materials(&self, course_id: &str) -> Result<Value, AppError> {
-        let key = format!("materials_{course_id}");
-
-        // 1. Memory cache
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
-
-        // 2. Disk cache (persistent)
-        if let Some(cached) = self.read_disk_cache(&key) {
-            self.memory_cache.insert(key, cached.clone()).await;
-            return Ok(cached);
-        }
-
-        tracing::debug!("cache miss (memory + disk): {key}");
-
-        // 3. Fetch from API
-        let materials = match self
-            .hub
-            .courses()
-            .course_work_materials_list(course_id)
-            .page_size(50)
-            .doit()
+    pub async fn get_course_materials(
+        &self,
+        course_id: &str,
+        refresh: bool,
+    ) -> Result<Value, AppError> {
+        let this = self.clone();
+        let id = course_id.to_string();
+        self.cache
+            .get(
+                CacheKind::Materials,
+                course_id,
+                refresh,
+                move || async move { this.fetch_course_materials(&id).await },
+            )
             .await
-        {
-            Ok((_resp, list)) => list.course_work_material.unwrap_or_default(),
+    }
+
+    async fn fetch_course_materials(&self, course_id: &str) -> Result<Fetched, AppError> {
+        let result = retry::call(
+            &self.usage,
+            "courses.courseWorkMaterials.list",
+            |mut probe| async move {
+                self.hub
+                    .courses()
+                    .course_work_materials_list(course_id)
+                    .page_size(50)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            },
+        )
+        .await;
+        let (resp, materials) = match result {
+            Ok((resp, list)) => (resp, list.course_work_material.unwrap_or_default()),
             Err(e) => {
-                return Err(AppError::GoogleApi(format!(
-                    "failed to fetch course materials for {course_id}: {e}"
-                )));
+                return Err(AppError::from(e)
+                    .context(format!("failed to fetch course materials for {course_id}")));
             }
         };
 
         let value = serde_json::to_value(&materials).map_err(AppError::Json)?;
+        if let Value::Array(items) = &value {
+            self.store
+                .persist_records(RecordKind::Material, course_id, items.clone())
+                .await;
+        }
 
-        // Save to both caches
-        self.memory_cache.insert(key.clone(), value.clone()).await;
-        self.write_disk_cache(&key, &value);
-
-        Ok(value)
+        Ok(Fetched::with_etag(value, &resp))
     }
 
     /// Get topics (modules/sections) for a course.
     /// Results are persisted to disk so they survive restarts and remain
     /// available even after losing access to the course.
-    pub async fn get_course_topics(&self, course_id: &str) -> Result<Value, AppError> {
-        let key = format!("topics_{course_id}");
+    pub async fn get_course_topics(
+        &self,
+        course_id: &str,
+        refresh: bool,
+    ) -> Result<Value, AppError> {
+        let this = self.clone();
+        let id = course_id.to_string();
+        self.cache
+            .get(CacheKind::Topics, course_id, refresh, move || async move {
+                this.fetch_course_topics(&id).await
+            })
+            .await
+    }
 
-        // 1. Memory cache
-        if let Some(cached) = self.memory_cache.get(&key).await {
-            tracing::debug!("memory cache hit: {key}");
-            return Ok(cached);
-        }
+    async fn fetch_course_topics(&self, course_id: &str) -> Result<Fetched, AppError> {
+        let (resp, topics) =
+            match retry::call(&self.usage, "courses.topics.list", |mut probe| async move {
+                self.hub
+                    .courses()
+                    .topics_list(course_id)
+                    .page_size(100)
+                    .delegate(&mut probe)
+                    .doit()
+                    .await
+            })
+            .await
+            {
+                Ok((resp, list)) => (resp, list.topic.unwrap_or_default()),
+                Err(e) => {
+                    return Err(AppError::from(e)
+                        .context(format!("failed to fetch topics for {course_id}")));
+                }
+            };
 
-        // 2. Disk cache (persistent)
-        if let Some(cached) = self.read_disk_cache(&key) {
-            self.memory_cache.insert(key, cached.clone()).await;
-            return Ok(cached);
+        let value = serde_json::to_value(&topics).map_err(AppError::Json)?;
+        if let Value::Array(items) = &value {
+            self.store
+                .persist_records(RecordKind::Topic, course_id, items.clone())
+                .await;
         }
 
-        tracing::debug!("cache miss (memory + disk): {key}");
+        Ok(Fetched::with_etag(value, &resp))
+    }
 
-        // 3. Fetch from API
-        let topics = match self
-            .hub
-            .courses()
-            .topics_list(course_id)
-            .page_size(100)
-            .doit()
-            .await
-        {
-            Ok((_resp, list)) => list.topic.unwrap_or_default(),
-            Err(e) => {
-                return Err(AppError::GoogleApi(format!(
-                    "failed to fetch topics for {course_id}: {e}"
-                )));
-            }
-        };
+    // Complete listings, across all pages and bypassing the cache, for `sync`.
 
-        let value = serde_json::to_value(&topics).map_err(AppError::Json)?;
+    /// Every course the user can see.
+    pub async fn all_courses(&self) -> Result<Vec<Value>, AppError> {
+        all_pages(|token| async move {
+            let token = token.as_deref();
+            let (_resp, list) = retry::call(&self.usage, "courses.list", |mut probe| async move {
+                let mut call = self.hub.courses().list().page_size(100);
+                if let Some(token) = token {
+                    call = call.page_token(token);
+                }
+                call.delegate(&mut probe).doit().await
+            })
+            .await?;
+            Ok((list.courses, list.next_page_token))
+        })
+        .await
+    }
 
-        // Save to both caches
-        self.memory_cache.insert(key.clone(), value.clone()).await;
-        self.write_disk_cache(&key, &value);
+    /// Every topic of a course.
+    pub async fn all_topics(&self, course_id: &str) -> Result<Vec<Value>, AppError> {
+        all_pages(|token| async move {
+            let token = token.as_deref();
+            let (_resp, list) =
+                retry::call(&self.usage, "courses.topics.list", |mut probe| async move {
+                    let mut call = self.hub.courses().topics_list(course_id).page_size(100);
+                    if let Some(token) = token {
+                        call = call.page_token(token);
+                    }
+                    call.delegate(&mut probe).doit().await
+                })
+                .await?;
+            Ok((list.topic, list.next_page_token))
+        })
+        .await
+    }
 
-        Ok(value)
+    /// Every coursework item of a course.
+    pub async fn all_course_work(&self, course_id: &str) -> Result<Vec<Value>, AppError> {
+        all_pages(|token| async move {
+            let token = token.as_deref();
+            let (_resp, list) = retry::call(
+                &self.usage,
+                "courses.courseWork.list",
+                |mut probe| async move {
+                    let mut call = self
+                        .hub
+                        .courses()
+                        .course_work_list(course_id)
+                        .page_size(100);
+                    if let Some(token) = token {
+                        call = call.page_token(token);
+                    }
+                    call.delegate(&mut probe).doit().await
+                },
+            )
+            .await?;
+            Ok((list.course_work, list.next_page_token))
+        })
+        .await
+    }
+
+    /// Every material posted in a course.
+    pub async fn all_materials(&self, course_id: &str) -> Result<Vec<Value>, AppError> {
+        all_pages(|token| async move {
+            let token = token.as_deref();
+            let (_resp, list) = retry::call(
+                &self.usage,
+                "courses.courseWorkMaterials.list",
+                |mut probe| async move {
+                    let mut call = self
+                        .hub
+                        .courses()
+                        .course_work_materials_list(course_id)
+                        .page_size(100);
+                    if let Some(token) = token {
+                        call = call.page_token(token);
+                    }
+                    call.delegate(&mut probe).doit().await
+                },
+            )
+            .await?;
+            Ok((list.course_work_material, list.next_page_token))
+        })
+        .await
+    }
+
+    /// Every announcement of a course.
+    pub async fn all_announcements(&self, course_id: &str) -> Result<Vec<Value>, AppError> {
+        all_pages(|token| async move {
+            let token = token.as_deref();
+            let (_resp, list) = retry::call(
+                &self.usage,
+                "courses.announcements.list",
+                |mut probe| async move {
+                    let mut call = self
+                        .hub
+                        .courses()
+                        .announcements_list(course_id)
+                        .page_size(100);
+                    if let Some(token) = token {
+                        call = call.page_token(token);
+                    }
+                    call.delegate(&mut probe).doit().await
+                },
+            )
+            .await?;
+            Ok((list.announcements, list.next_page_token))
+        })
+        .await
+    }
+
+    /// Every submission to every coursework item of a course.
+    pub async fn all_submissions(&self, course_id: &str) -> Result<Vec<Value>, AppError> {
+        all_pages(|token| async move {
+            let token = token.as_deref();
+            let (_resp, list) = retry::call(
+                &self.usage,
+                "courses.courseWork.studentSubmissions.list",
+                |mut probe| async move {
+                    // `-` lists the submissions of all coursework at once.
+                    let mut call = self
+                        .hub
+                        .courses()
+                        .course_work_student_submissions_list(course_id, "-")
+                        .page_size(100);
+                    if let Some(token) = token {
+                        call = call.page_token(token);
+                    }
+                    call.delegate(&mut probe).doit().await
+                },
+            )
+            .await?;
+            Ok((list.student_submissions, list.next_page_token))
+        })
+        .await
+    }
+}
+
+/// Collect the items of every page of a list call. `page` fetches the page
+/// at a token (`None` for the first) and returns its items and the next token.
+async fn all_pages<T, F, Fut>(mut page: F) -> Result<Vec<Value>, AppError>
+where
+    T: Serialize,
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<(Option<Vec<T>>, Option<String>), AppError>>,
+{
+    let mut items = Vec::new();
+    let mut token = None;
+    loop {
+        let (page_items, next) = page(token.take()).await?;
+        for item in page_items.unwrap_or_default() {
+            items.push(serde_json::to_value(item)?);
+        }
+        match next {
+            Some(next) if !next.is_empty() => token = Some(next),
+            _ => return Ok(items),
+        }
+    }
+}
+
+/// An announcement's first non-empty line, as a title for it.
+pub fn announcement_title(announcement: &Value) -> String {
+    announcement["text"]
+        .as_str()
+        .and_then(|text| text.lines().find(|l| !l.trim().is_empty()))
+        .map(|line| line.trim().chars().take(80).collect())
+        .unwrap_or_default()
+}
+
+/// Collect the IDs of the Drive files attached to a Classroom object: the
+/// `driveFile` of materials and of submission attachments.
+pub fn drive_file_ids(value: &Value, ids: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                if key == "driveFile" {
+                    if let Some(id) = field["id"].as_str() {
+                        ids.insert(id.to_string());
+                    }
+                }
+                drive_file_ids(field, ids);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                drive_file_ids(item, ids);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_attached_drive_files() {
+        let material = json!({
+            "id": "m1",
+            "materials": [
+                { "driveFile": { "driveFile": { "id": "doc1", "title": "Notes" }, "shareMode": "VIEW" } },
+                { "link": { "url": "https://example.com" } },
+            ],
+        });
+        let submission = json!({
+            "assignmentSubmission": { "attachments": [{ "driveFile": { "id": "doc2" } }] },
+        });
+        let mut ids = BTreeSet::new();
+        drive_file_ids(&material, &mut ids);
+        drive_file_ids(&submission, &mut ids);
+        assert_eq!(ids.into_iter().collect::<Vec<_>>(), ["doc1", "doc2"]);
     }
 }
@@ -0,0 +1,256 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::error::AppError;
+use crate::scopes::ToolGroup;
+
+/// Directory holding credentials, tokens, config and caches.
+pub fn config_dir() -> Result<PathBuf, AppError> {
+    let dir = dirs::config_dir()
+        .ok_or_else(|| AppError::CredentialRead("cannot determine config directory".into()))?
+        .join("personal-google-mcp");
+    Ok(dir)
+}
+
+/// Server settings from `config.json` in the config directory.
+///
+/// Every field is optional; a missing file means all defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// Tool groups to expose; their scopes are requested at sign-in.
+    pub tool_groups: Vec<ToolGroup>,
+    /// Account used when a tool call doesn't name one.
+    pub default_account: Option<String>,
+    /// Authenticate as Workspace users through a service account instead of
+    /// (or alongside) interactive sign-in.
+    pub service_account: Option<ServiceAccountConfig>,
+    /// Client-side request rate limits, per Google API.
+    pub rate_limits: RateLimits,
+    /// Cache freshness and disk usage limits.
+    pub cache: CacheConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tool_groups: ToolGroup::DEFAULT.to_vec(),
+            default_account: None,
+            service_account: None,
+            rate_limits: RateLimits::default(),
+            cache: CacheConfig::default(),
+        }
+    }
+}
+
+/// Rate limits for each Google API, shared by all accounts.
+///
+/// An API's settings override its defaults field by field, so setting only
+/// `requestsPerSecond` keeps that API's default burst.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RateLimitOverrides")]
+pub struct RateLimits {
+    pub classroom: RateLimit,
+    pub drive: RateLimit,
+    pub calendar: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            classroom: RateLimit::new(10.0, 20),
+            drive: RateLimit::new(10.0, 20),
+            calendar: RateLimit::new(5.0, 10),
+        }
+    }
+}
+
+/// `rateLimits` as written in `config.json`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct RateLimitOverrides {
+    classroom: RateLimitOverride,
+    drive: RateLimitOverride,
+    calendar: RateLimitOverride,
+}
+
+impl TryFrom<RateLimitOverrides> for RateLimits {
+    type Error = String;
+
+    fn try_from(overrides: RateLimitOverrides) -> Result<Self, String> {
+        let defaults = Self::default();
+        Ok(Self {
+            classroom: overrides.classroom.apply("classroom", defaults.classroom)?,
+            drive: overrides.drive.apply("drive", defaults.drive)?,
+            calendar: overrides.calendar.apply("calendar", defaults.calendar)?,
+        })
+    }
+}
+
+/// A token bucket: a sustained request rate plus a burst allowance.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Sustained requests per second; 0 disables limiting.
+    pub requests_per_second: f64,
+    /// Requests that may be sent back to back before the rate applies.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// The slowest rate accepted besides 0: one request every 100 seconds.
+    /// Slower rates would make the limiter's waits overflow.
+    const MIN_RATE: f64 = 0.01;
+
+    const fn new(requests_per_second: f64, burst: u32) -> Self {
+        Self {
+            requests_per_second,
+            burst,
+        }
+    }
+}
+
+/// The fields of a [`RateLimit`] that `config.json` sets.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimitOverride {
+    requests_per_second: Option<f64>,
+    burst: Option<u32>,
+}
+
+impl RateLimitOverride {
+    fn apply(self, api: &str, default: RateLimit) -> Result<RateLimit, String> {
+        let requests_per_second = self
+            .requests_per_second
+            .unwrap_or(default.requests_per_second);
+        if requests_per_second != 0.0 && requests_per_second < RateLimit::MIN_RATE {
+            return Err(format!(
+                "{api} requestsPerSecond must be 0 (no limit) or at least {}",
+                RateLimit::MIN_RATE
+            ));
+        }
+        Ok(RateLimit {
+            requests_per_second,
+            burst: self.burst.unwrap_or(default.burst),
+        })
+    }
+}
+
+/// Limits on cached data.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CacheConfig {
+    /// Entries older than this are refetched, and only served if that fails
+    /// (Google unreachable or access to the course lost).
+    pub max_age_hours: u64,
+    /// Size cap for each account's disk cache; the least recently used
+    /// entries are evicted beyond it.
+    pub max_disk_mb: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_age_hours: 7 * 24,
+            max_disk_mb: 100,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn max_age(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.max_age_hours.saturating_mul(3600))
+    }
+
+    pub fn max_disk_bytes(&self) -> u64 {
+        self.max_disk_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// Service account with (optional) domain-wide delegation.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccountConfig {
+    /// JSON key file; relative paths are resolved against the config directory.
+    pub key_file: PathBuf,
+    /// User to impersonate by default. Without one, the service account acts as itself.
+    pub subject: Option<String>,
+    /// Additional users to set up accounts for at startup.
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Workspace domain whose users may be impersonated on demand by passing
+    /// their email address as a tool's `account` parameter.
+    pub domain: Option<String>,
+}
+
+impl Config {
+    pub fn path() -> Result<PathBuf, AppError> {
+        Ok(config_dir()?.join("config.json"))
+    }
+
+    /// Load `config.json`, falling back to defaults if it doesn't exist.
+    pub fn load() -> Result<Self, AppError> {
+        let path = Self::path()?;
+        match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| AppError::InvalidInput(format!("invalid {}: {e}", path.display()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.tool_groups, ToolGroup::DEFAULT);
+    }
+
+    #[test]
+    fn service_account_section() {
+        let config: Config = serde_json::from_str(
+            r#"{"serviceAccount": {"keyFile": "sa.json", "subject": "teacher@school.edu", "domain": "school.edu"}}"#,
+        )
+        .unwrap();
+        let sa = config.service_account.unwrap();
+        assert_eq!(sa.key_file, PathBuf::from("sa.json"));
+        assert_eq!(sa.subject.as_deref(), Some("teacher@school.edu"));
+        assert!(sa.users.is_empty());
+    }
+
+    #[test]
+    fn rate_limits_override_per_api() {
+        let config: Config =
+            serde_json::from_str(r#"{"rateLimits": {"drive": {"requestsPerSecond": 2}}}"#).unwrap();
+        assert_eq!(config.rate_limits.drive.requests_per_second, 2.0);
+        assert_eq!(config.rate_limits.drive.burst, 20);
+        assert_eq!(config.rate_limits.classroom.requests_per_second, 10.0);
+
+        let config: Config =
+            serde_json::from_str(r#"{"rateLimits": {"calendar": {"burst": 3}}}"#).unwrap();
+        assert_eq!(config.rate_limits.calendar.requests_per_second, 5.0);
+        assert_eq!(config.rate_limits.calendar.burst, 3);
+
+        for rate in ["1e-30", "-1"] {
+            let json = format!(r#"{{"rateLimits": {{"drive": {{"requestsPerSecond": {rate}}}}}}}"#);
+            assert!(serde_json::from_str::<Config>(&json).is_err());
+        }
+    }
+
+    #[test]
+    fn cache_limits() {
+        let config: Config = serde_json::from_str(r#"{"cache": {"maxAgeHours": 24}}"#).unwrap();
+        assert_eq!(config.cache.max_age().as_secs(), 24 * 3600);
+        assert_eq!(config.cache.max_disk_bytes(), 100 * 1024 * 1024);
+    }
+
+    #[test]
+    fn tool_groups_parse_kebab_case() {
+        let config: Config = serde_json::from_str(r#"{"toolGroups": ["calendar"]}"#).unwrap();
+        assert_eq!(config.tool_groups, vec![ToolGroup::Calendar]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Optional at-rest encryption for tokens and cached data.
+//!
+//! Encryption is enabled by setting `PGM_ENCRYPTION_PASSPHRASE`, or
+//! `PGM_ENCRYPTION_KEY_FILE` pointing at a file whose contents are used as the
+//! passphrase. A 256-bit key is derived with Argon2id and data is sealed with
+//! ChaCha20-Poly1305.
+//!
+//! Encrypted files start with a magic header, so plaintext files written before
+//! encryption was turned on are still readable and get encrypted on their next
+//! write.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::error::AppError;
+
+pub const PASSPHRASE_ENV: &str = "PGM_ENCRYPTION_PASSPHRASE";
+pub const KEY_FILE_ENV: &str = "PGM_ENCRYPTION_KEY_FILE";
+
+const MAGIC: &[u8; 5] = b"PGME\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+/// Encrypts and decrypts blobs with a key derived from the user's passphrase.
+pub struct Cipher {
+    passphrase: Vec<u8>,
+    /// Salt used for new ciphertexts; generated once per process.
+    salt: [u8; SALT_LEN],
+    /// Derived keys by salt, so older files don't re-run Argon2 on every read.
+    keys: Mutex<HashMap<[u8; SALT_LEN], Key>>,
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cipher").finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    /// Build a cipher from the environment, or `None` if encryption isn't configured.
+    pub fn from_env() -> Result<Option<Arc<Self>>, AppError> {
+        if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+            if !passphrase.is_empty() {
+                return Ok(Some(Arc::new(Self::new(passphrase.as_bytes())?)));
+            }
+        }
+
+        if let Ok(path) = std::env::var(KEY_FILE_ENV) {
+            let data = std::fs::read(&path).map_err(|e| {
+                AppError::Encryption(format!("failed to read key file {path}: {e}"))
+            })?;
+            return Ok(Some(Arc::new(Self::new(data.trim_ascii())?)));
+        }
+
+        Ok(None)
+    }
+
+    pub fn new(passphrase: &[u8]) -> Result<Self, AppError> {
+        if passphrase.is_empty() {
+            return Err(AppError::Encryption("passphrase cannot be empty".into()));
+        }
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Ok(Self {
+            passphrase: passphrase.to_vec(),
+            salt,
+            keys: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn key(&self, salt: &[u8; SALT_LEN]) -> Result<Key, AppError> {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(key) = keys.get(salt) {
+            return Ok(*key);
+        }
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(&self.passphrase, salt, &mut key)
+            .map_err(|e| AppError::Encryption(format!("key derivation failed: {e}")))?;
+        keys.insert(*salt, key);
+        Ok(key)
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, AppError> {
+        let cipher = ChaCha20Poly1305::new(&self.key(&self.salt)?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| AppError::Encryption("encryption failed".into()))?;
+
+        let mut out = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&self.salt);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, AppError> {
+        if !is_encrypted(data) || data.len() < HEADER_LEN {
+            return Err(AppError::Encryption("data is not encrypted".into()));
+        }
+        let (salt, rest) = data[MAGIC.len()..].split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let salt: [u8; SALT_LEN] = salt.try_into().expect("salt slice has fixed length");
+
+        let cipher = ChaCha20Poly1305::new(&self.key(&salt)?);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                AppError::Encryption(
+                    "decryption failed — wrong passphrase or corrupted data".into(),
+                )
+            })
+    }
+}
+
+/// Whether `data` was produced by [`Cipher::encrypt`].
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypt `plaintext` if a cipher is configured, otherwise pass it through.
+pub fn seal(cipher: Option<&Cipher>, plaintext: &[u8]) -> Result<Vec<u8>, AppError> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(plaintext),
+        None => Ok(plaintext.to_vec()),
+    }
+}
+
+/// Decrypt `data` if it is encrypted; plaintext data is returned unchanged.
+pub fn open(cipher: Option<&Cipher>, data: Vec<u8>) -> Result<Vec<u8>, AppError> {
+    if !is_encrypted(&data) {
+        return Ok(data);
+    }
+    match cipher {
+        Some(cipher) => cipher.decrypt(&data),
+        None => Err(AppError::Encryption(format!(
+            "data is encrypted — set {PASSPHRASE_ENV} or {KEY_FILE_ENV}"
+        ))),
+    }
+}
+
+/// Write a file readable and writable only by the current user (mode 0600).
+///
+/// The data goes to a temporary file next to `path` that is then renamed
+/// over it, so a crash mid-write leaves the old file intact.
+pub fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} isn't a file path", path.display()),
+        )
+    })?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{:016x}.tmp", OsRng.next_u64()));
+    let temp = path.with_file_name(temp_name);
+
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    let written = opts.open(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, path)
+    });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Set an existing file's mode to 0600.
+pub fn restrict_permissions(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let perms = std::fs::metadata(path)?.permissions();
+        if perms.mode() & 0o077 != 0 {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let cipher = Cipher::new(b"correct horse").unwrap();
+        let sealed = cipher.encrypt(b"{\"token\":1}").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), b"{\"token\":1}");
+    }
+
+    #[test]
+    fn wrong_passphrase_fails() {
+        let sealed = Cipher::new(b"one").unwrap().encrypt(b"secret").unwrap();
+        assert!(Cipher::new(b"two").unwrap().decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn open_passes_plaintext_through() {
+        assert_eq!(open(None, b"[]".to_vec()).unwrap(), b"[]");
+        let cipher = Cipher::new(b"pw").unwrap();
+        let sealed = seal(Some(&cipher), b"[]").unwrap();
+        assert!(open(None, sealed.clone()).is_err());
+        assert_eq!(open(Some(&cipher), sealed).unwrap(), b"[]");
+    }
+
+    #[test]
+    fn write_private_replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!("write-private-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tokens.json");
+        std::fs::write(&path, b"old contents").unwrap();
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 1, "the temporary file is renamed away");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Reading the times users give the calendar tools: RFC 3339 timestamps,
+//! dates and times without an offset, and a few words such as `today`,
+//! `last tuesday` or `3 days ago`.
+
+use chrono::{
+    DateTime, Datelike, Days, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+
+use crate::error::AppError;
+
+/// Resolve `input` to an instant, reading dates and times without an offset
+/// in `now`'s time zone. A date alone is the start of that day or, with
+/// `end_of_day`, the start of the next one, so that a window from `monday` to
+/// `monday` covers the whole day.
+pub fn parse_time<Tz: TimeZone>(
+    input: &str,
+    now: &DateTime<Tz>,
+    end_of_day: bool,
+) -> Result<DateTime<Utc>, AppError> {
+    let trimmed = input.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(trimmed) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let text = trimmed.to_lowercase();
+    if text == "now" {
+        return Ok(now.with_timezone(&Utc));
+    }
+    let tz = now.timezone();
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(trimmed, format) {
+            return Ok(local(&tz, naive));
+        }
+    }
+    let date = parse_date(&text, now.date_naive()).ok_or_else(|| {
+        AppError::InvalidInput(format!(
+            "can't read '{input}' as a time; use RFC 3339 (2026-03-02T09:00:00Z), \
+             a date (2026-03-02) or words like 'today', 'next friday' or '3 days ago'"
+        ))
+    })?;
+    let date = if end_of_day {
+        date.succ_opt().unwrap_or(date)
+    } else {
+        date
+    };
+    Ok(local(&tz, date.and_time(NaiveTime::MIN)))
+}
+
+/// Resolve `input`, in any form [`parse_time`] reads, to a day in `now`'s
+/// time zone.
+pub fn parse_day<Tz: TimeZone>(input: &str, now: &DateTime<Tz>) -> Result<NaiveDate, AppError> {
+    let time = parse_time(input, now, false)?;
+    Ok(time.with_timezone(&now.timezone()).date_naive())
+}
+
+/// A date given as `YYYY-MM-DD` or in words, relative to `today`.
+fn parse_date(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Some(date);
+    }
+    match text {
+        "today" => return Some(today),
+        "tomorrow" => return today.succ_opt(),
+        "yesterday" => return today.pred_opt(),
+        _ => {}
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    match words.as_slice() {
+        ["in", count, unit] => today.checked_add_days(days(count, unit)?),
+        [count, unit, "ago"] => today.checked_sub_days(days(count, unit)?),
+        [day] | ["this", day] => weekday_after(today, day.parse().ok()?, 0),
+        ["next", day] => weekday_after(today, day.parse().ok()?, 1),
+        ["last", day] => {
+            let day: Weekday = day.parse().ok()?;
+            let back =
+                (today.weekday().num_days_from_monday() + 7 - day.num_days_from_monday() - 1) % 7
+                    + 1;
+            today.checked_sub_days(Days::new(back.into()))
+        }
+        _ => None,
+    }
+}
+
+/// `count` days or weeks.
+fn days(count: &str, unit: &str) -> Option<Days> {
+    let count: u64 = count.parse().ok()?;
+    match unit.trim_end_matches('s') {
+        "day" => Some(Days::new(count)),
+        "week" => Some(Days::new(count.checked_mul(7)?)),
+        _ => None,
+    }
+}
+
+/// The first `day` at least `skip` days after `today`.
+fn weekday_after(today: NaiveDate, day: Weekday, skip: u64) -> Option<NaiveDate> {
+    let start = today.checked_add_days(Days::new(skip))?;
+    let ahead = (day.num_days_from_monday() + 7 - start.weekday().num_days_from_monday()) % 7;
+    start.checked_add_days(Days::new(ahead.into()))
+}
+
+/// A wall-clock time in `tz`. Times skipped by a daylight-saving change move
+/// forward an hour; repeated ones take the earlier instant.
+pub fn local<Tz: TimeZone>(tz: &Tz, naive: NaiveDateTime) -> DateTime<Utc> {
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(naive + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|| naive.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn reads_words_and_formats() {
+        // A Wednesday, in UTC+2.
+        let now = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2026, 3, 4, 15, 30, 0)
+            .unwrap();
+        let at = |input: &str, end| parse_time(input, &now, end).unwrap().to_rfc3339();
+
+        assert_eq!(
+            at("2026-03-02T09:00:00Z", false),
+            "2026-03-02T09:00:00+00:00"
+        );
+        assert_eq!(at("2026-03-02 09:00", false), "2026-03-02T07:00:00+00:00");
+        assert_eq!(at("now", false), "2026-03-04T13:30:00+00:00");
+        assert_eq!(at("today", false), "2026-03-03T22:00:00+00:00");
+        assert_eq!(at("today", true), "2026-03-04T22:00:00+00:00");
+        assert_eq!(at("Last Tuesday", false), "2026-03-02T22:00:00+00:00");
+        assert_eq!(at("last wednesday", false), "2026-02-24T22:00:00+00:00");
+        assert_eq!(at("wednesday", false), "2026-03-03T22:00:00+00:00");
+        assert_eq!(at("next wednesday", false), "2026-03-10T22:00:00+00:00");
+        assert_eq!(at("friday", false), "2026-03-05T22:00:00+00:00");
+        assert_eq!(at("2 weeks ago", false), "2026-02-17T22:00:00+00:00");
+        assert_eq!(at("in 1 day", false), "2026-03-04T22:00:00+00:00");
+        for input in ["someday", "in 3000000000000000000 weeks"] {
+            assert!(matches!(
+                parse_time(input, &now, false),
+                Err(AppError::InvalidInput(_))
+            ));
+        }
+    }
+}
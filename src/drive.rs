@@ -1,18 +1,35 @@
-use std::time::Duration;
+use std::sync::Arc;
 
+use google_drive3::api::File;
 use http_body_util::BodyExt;
-use moka::future::Cache;
 use serde_json::{json, Value};
 
 use crate::auth::DriveHubType;
+use crate::cache::{CacheKind, CacheLayer, Fetched};
 use crate::error::AppError;
+use crate::retry;
+use crate::scopes::{ToolGroup, DRIVE_READONLY as DRIVE_SCOPE};
+use crate::session::ManagedAuth;
+use crate::store::{RecordKind, Store};
+use crate::usage::ApiUsage;
 
 const MAX_CONTENT_BYTES: usize = 100 * 1024; // 100 KB
-const DRIVE_SCOPE: &str = "https://www.googleapis.com/auth/drive.readonly";
 
+const XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+/// Error reasons Google gives when the access token lacks a needed scope.
+const INSUFFICIENT_SCOPE_REASONS: &[&str] =
+    &["insufficientPermissions", "ACCESS_TOKEN_SCOPE_INSUFFICIENT"];
+
+#[derive(Clone)]
 pub struct DriveClient {
     hub: DriveHubType,
-    memory_cache: Cache<String, Value>,
+    usage: Arc<ApiUsage>,
+    cache: CacheLayer,
+    /// The account's snapshot, where fetched objects are saved.
+    store: Store,
+    /// For explaining a missing scope (`None` offline).
+    auth: Option<ManagedAuth>,
 }
 
 impl std::fmt::Debug for DriveClient {
@@ -22,13 +39,55 @@ impl std::fmt::Debug for DriveClient {
 }
 
 impl DriveClient {
-    pub fn new(hub: DriveHubType) -> Self {
-        let memory_cache = Cache::builder()
-            .max_capacity(200)
-            .time_to_live(Duration::from_secs(300))
-            .build();
+    pub fn new(
+        hub: DriveHubType,
+        usage: Arc<ApiUsage>,
+        cache: CacheLayer,
+        store: Store,
+        auth: Option<ManagedAuth>,
+    ) -> Self {
+        Self {
+            hub,
+            usage,
+            cache,
+            store,
+            auth,
+        }
+    }
+
+    pub fn cache(&self) -> &CacheLayer {
+        &self.cache
+    }
 
-        Self { hub, memory_cache }
+    /// Get the signed-in user's display name and email address.
+    pub async fn current_user(&self, refresh: bool) -> Result<Value, AppError> {
+        let this = self.clone();
+        self.cache
+            .get(CacheKind::DriveUser, "", refresh, move || async move {
+                this.fetch_current_user().await
+            })
+            .await
+    }
+
+    async fn fetch_current_user(&self) -> Result<Fetched, AppError> {
+        let (resp, about) = retry::call(&self.usage, "about.get", |mut probe| async move {
+            self.hub
+                .about()
+                .get()
+                .param("fields", "user(displayName,emailAddress)")
+                .add_scope(DRIVE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await?;
+
+        let user = about.user.unwrap_or_default();
+        let value = json!({
+            "email": user.email_address,
+            "displayName": user.display_name,
+        });
+        Ok(Fetched::with_etag(value, &resp))
     }
 
     /// Read the content of a Google Drive file by file ID or URL.
@@ -36,49 +95,112 @@ impl DriveClient {
     /// For Google Workspace documents (Docs, Sheets, Slides) the content is
     /// exported to a text format. For regular text files the content is
     /// downloaded directly. Binary files return metadata only.
-    pub async fn read_material(&self, file_id_or_url: &str) -> Result<Value, AppError> {
+    pub async fn read_material(
+        &self,
+        file_id_or_url: &str,
+        refresh: bool,
+    ) -> Result<Value, AppError> {
         let file_id = parse_file_id(file_id_or_url)?;
-
-        if let Some(cached) = self.memory_cache.get(&file_id).await {
-            tracing::debug!("drive cache hit: {file_id}");
-            return Ok(cached);
-        }
-        tracing::info!("drive cache miss, fetching metadata: {file_id}");
-
-        // Fetch file metadata
-        let (_resp, file) = self
-            .hub
-            .files()
-            .get(&file_id)
-            .param("fields", "id,name,mimeType,size,modifiedTime,webViewLink")
-            .add_scope(DRIVE_SCOPE)
-            .doit()
+        let this = self.clone();
+        let id = file_id.clone();
+        self.cache
+            .get(
+                CacheKind::DriveFile,
+                &file_id,
+                refresh,
+                move || async move { this.fetch_material(id).await },
+            )
             .await
-            .map_err(|e| {
-                let msg = e.to_string();
-                if msg.contains("403") || msg.contains("insufficient") {
-                    AppError::DriveApi(format!(
-                        "Access denied for file {file_id}. You may need to re-authenticate \
-                         with `cargo run -- auth` to grant the drive.readonly scope. \
-                         Original error: {msg}"
-                    ))
-                } else {
-                    AppError::DriveApi(msg)
+    }
+
+    /// When a file was last modified, as stored with [`read_material`]
+    /// results, so `sync` can skip files that haven't changed.
+    ///
+    /// [`read_material`]: Self::read_material
+    pub async fn modified_time(&self, file_id: &str) -> Result<Option<String>, AppError> {
+        let (_resp, file) = retry::call(&self.usage, "files.get", |mut probe| async move {
+            self.hub
+                .files()
+                .get(file_id)
+                .param("fields", "modifiedTime")
+                .add_scope(DRIVE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await
+        .map_err(|e| AppError::from(e).context(format!("file {file_id}")))?;
+        Ok(file.modified_time.map(|t| t.to_rfc3339()))
+    }
+
+    /// A file's metadata, with errors explaining missing access.
+    async fn metadata(&self, file_id: &str) -> Result<File, AppError> {
+        tracing::info!("fetching drive file metadata: {file_id}");
+        let (_resp, file) = retry::call(&self.usage, "files.get", |mut probe| async move {
+            self.hub
+                .files()
+                .get(file_id)
+                .param("fields", "id,name,mimeType,size,modifiedTime,webViewLink")
+                .add_scope(DRIVE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await
+        .map_err(|e| {
+            let missing_scope = e
+                .reasons()
+                .iter()
+                .any(|r| INSUFFICIENT_SCOPE_REASONS.contains(&r.as_str()));
+            match (AppError::from(e), &self.auth) {
+                (AppError::PermissionDenied(_), Some(auth)) if missing_scope => {
+                    auth.missing_scope(ToolGroup::Drive)
                 }
-            })?;
+                (AppError::PermissionDenied(msg), _) => AppError::PermissionDenied(format!(
+                    "file {file_id} isn't shared with this account: {msg}"
+                )),
+                (other, _) => other.context(format!("file {file_id}")),
+            }
+        })?;
+        Ok(file)
+    }
+
+    /// Download a file at full size for archiving. Google Docs, Slides and
+    /// Drawings are converted to PDF and Sheets to Excel; other Workspace
+    /// files (Forms, Sites, ...) can't be downloaded and have no content.
+    pub async fn download(&self, file_id: &str) -> Result<Download, AppError> {
+        let file = self.metadata(file_id).await?;
+        let name = file.name.clone().unwrap_or_else(|| file_id.to_string());
+        let mime_type = file.mime_type.as_deref().unwrap_or("unknown");
+        let (file_name, content) = match mime_type {
+            "application/vnd.google-apps.document"
+            | "application/vnd.google-apps.presentation"
+            | "application/vnd.google-apps.drawing" => (
+                format!("{name}.pdf"),
+                Some(self.export_bytes(file_id, "application/pdf").await?),
+            ),
+            "application/vnd.google-apps.spreadsheet" => (
+                format!("{name}.xlsx"),
+                Some(self.export_bytes(file_id, XLSX).await?),
+            ),
+            m if m.starts_with("application/vnd.google-apps.") => (name, None),
+            _ => (name, Some(self.download_bytes(file_id).await?)),
+        };
+        Ok(Download {
+            metadata: metadata_json(&file),
+            file_name,
+            content,
+        })
+    }
+
+    async fn fetch_material(&self, file_id: String) -> Result<Value, AppError> {
+        let file = self.metadata(&file_id).await?;
 
         let mime_type = file.mime_type.as_deref().unwrap_or("unknown");
         let file_name = file.name.as_deref().unwrap_or("unknown");
         tracing::info!("file metadata: name={file_name}, mime={mime_type}");
 
-        let metadata = json!({
-            "id": file.id,
-            "name": file.name,
-            "mimeType": file.mime_type,
-            "size": file.size,
-            "modifiedTime": file.modified_time.map(|t| t.to_rfc3339()),
-            "webViewLink": file.web_view_link,
-        });
+        let metadata = metadata_json(&file);
 
         // Determine how to fetch content based on mime type
         let (content, export_mime) = match mime_type {
@@ -132,59 +254,99 @@ impl DriveClient {
                 String::new()
             },
         });
+        self.store
+            .persist_record(RecordKind::DriveFile, "", &file_id, result.clone())
+            .await;
 
-        self.memory_cache.insert(file_id, result.clone()).await;
         Ok(result)
     }
 
-    /// Export a Google Workspace document to the given MIME type.
+    /// Export a Google Workspace document to the given text MIME type.
     async fn export_file(&self, file_id: &str, mime_type: &str) -> Result<String, AppError> {
+        let body = self.export_bytes(file_id, mime_type).await?;
+        String::from_utf8(body)
+            .map_err(|e| AppError::InvalidResponse(format!("export produced invalid UTF-8: {e}")))
+    }
+
+    /// Export a Google Workspace document to the given MIME type.
+    async fn export_bytes(&self, file_id: &str, mime_type: &str) -> Result<Vec<u8>, AppError> {
         tracing::info!("exporting {file_id} as {mime_type}");
-        let resp = self
-            .hub
-            .files()
-            .export(file_id, mime_type)
-            .add_scope(DRIVE_SCOPE)
-            .doit()
-            .await
-            .map_err(|e| AppError::DriveApi(format!("export failed for {file_id}: {e}")))?;
+        let resp = retry::call(&self.usage, "files.export", |mut probe| async move {
+            self.hub
+                .files()
+                .export(file_id, mime_type)
+                .add_scope(DRIVE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await
+        .map_err(|e| AppError::from(e).context(format!("export failed for {file_id}")))?;
 
         let body = resp
             .into_body()
             .collect()
             .await
-            .map_err(|e| AppError::DriveApi(format!("failed to read export body: {e}")))?
+            .map_err(|e| AppError::Transient(format!("failed to read export body: {e}")))?
             .to_bytes();
+        Ok(body.to_vec())
+    }
 
-        String::from_utf8(body.to_vec())
-            .map_err(|e| AppError::DriveApi(format!("export produced invalid UTF-8: {e}")))
+    /// Download a regular (non-Workspace) text file's content.
+    async fn download_file(&self, file_id: &str) -> Result<String, AppError> {
+        let body = self.download_bytes(file_id).await?;
+        String::from_utf8(body)
+            .map_err(|e| AppError::Unsupported(format!("file {file_id} isn't UTF-8 text: {e}")))
     }
 
     /// Download a regular (non-Workspace) file's content.
-    async fn download_file(&self, file_id: &str) -> Result<String, AppError> {
+    async fn download_bytes(&self, file_id: &str) -> Result<Vec<u8>, AppError> {
         tracing::info!("downloading {file_id} via alt=media");
-        let (resp, _file) = self
-            .hub
-            .files()
-            .get(file_id)
-            .param("alt", "media")
-            .add_scope(DRIVE_SCOPE)
-            .doit()
-            .await
-            .map_err(|e| AppError::DriveApi(format!("download failed for {file_id}: {e}")))?;
+        let (resp, _file) = retry::call(&self.usage, "files.get", |mut probe| async move {
+            self.hub
+                .files()
+                .get(file_id)
+                .param("alt", "media")
+                .add_scope(DRIVE_SCOPE)
+                .delegate(&mut probe)
+                .doit()
+                .await
+        })
+        .await
+        .map_err(|e| AppError::from(e).context(format!("download failed for {file_id}")))?;
 
         let body = resp
             .into_body()
             .collect()
             .await
-            .map_err(|e| AppError::DriveApi(format!("failed to read download body: {e}")))?
+            .map_err(|e| AppError::Transient(format!("failed to read download body: {e}")))?
             .to_bytes();
-
-        String::from_utf8(body.to_vec())
-            .map_err(|e| AppError::DriveApi(format!("file is not valid UTF-8: {e}")))
+        Ok(body.to_vec())
     }
 }
 
+/// A file downloaded for archiving.
+#[derive(Debug)]
+pub struct Download {
+    /// Same shape as the `metadata` of [`DriveClient::read_material`] results.
+    pub metadata: Value,
+    /// The file's name, with the extension of the format it was converted to.
+    pub file_name: String,
+    /// `None` for Workspace files that can't be downloaded.
+    pub content: Option<Vec<u8>>,
+}
+
+fn metadata_json(file: &File) -> Value {
+    json!({
+        "id": file.id,
+        "name": file.name,
+        "mimeType": file.mime_type,
+        "size": file.size,
+        "modifiedTime": file.modified_time.map(|t| t.to_rfc3339()),
+        "webViewLink": file.web_view_link,
+    })
+}
+
 /// Extract a Google Drive file ID from a URL or return the input as-is if it
 /// looks like a bare ID.
 ///
@@ -194,7 +356,7 @@ impl DriveClient {
 /// - `https://docs.google.com/spreadsheets/d/{ID}/...`
 /// - `https://docs.google.com/presentation/d/{ID}/...`
 /// - `https://drive.google.com/open?id={ID}`
-fn parse_file_id(input: &str) -> Result<String, AppError> {
+pub fn parse_file_id(input: &str) -> Result<String, AppError> {
     let input = input.trim();
     if input.is_empty() {
         return Err(AppError::InvalidInput(
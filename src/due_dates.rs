@@ -0,0 +1,543 @@
+//! The `sync_due_dates_to_calendar` tool: a calendar event for every
+//! coursework due date.
+//!
+//! The events live in a calendar of their own, created on the first run and
+//! remembered in the store, and carry their coursework's ID in a private
+//! extended property, so every run finds the events of earlier ones. A run
+//! creates events for new due dates, updates those whose title, due date or
+//! reminders changed, and deletes those whose coursework was deleted, lost
+//! its due date or was turned in. Events without the property are left alone.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Duration, NaiveDate, NaiveTime};
+use google_calendar3::api::{
+    Event, EventDateTime, EventExtendedProperties, EventReminder, EventReminders,
+};
+use serde_json::{json, Value};
+
+use crate::accounts::Account;
+use crate::error::AppError;
+use crate::scopes::ToolGroup;
+
+/// Private extended properties of the events.
+const COURSE_WORK_KEY: &str = "classroomCourseWorkId";
+const COURSE_KEY: &str = "classroomCourseId";
+
+/// Where the ID of the due-date calendar is kept in the store.
+const CALENDAR_SETTING: &str = "due_dates_calendar";
+const CALENDAR_NAME: &str = "Classroom due dates";
+
+/// Popup reminders, in minutes before the due time: a day and an hour.
+pub const DEFAULT_REMINDERS: &[u32] = &[24 * 60, 60];
+
+/// Work due at a time gets an event of this length ending then.
+const TIMED_EVENT_MINUTES: i64 = 30;
+
+#[derive(Debug)]
+pub struct Request {
+    /// Only sync this course; the events of other courses are left alone.
+    pub course_id: Option<String>,
+    /// Use this calendar instead of the dedicated one.
+    pub calendar_id: Option<String>,
+    pub reminders: Vec<u32>,
+    /// Report what would change without changing anything.
+    pub dry_run: bool,
+    /// Look the course up at Google rather than in the cache. Coursework,
+    /// submissions and events are always fetched.
+    pub refresh: bool,
+}
+
+/// The event a coursework item should have.
+#[derive(Debug)]
+struct Due {
+    course_work_id: String,
+    event: Event,
+    /// How the item is reported.
+    label: Value,
+}
+
+#[derive(Debug)]
+enum Action {
+    Create(Due),
+    Update { event_id: String, due: Due },
+    Delete { event_id: String, label: Value },
+}
+
+impl Action {
+    fn label(&self) -> &Value {
+        match self {
+            Action::Create(due) | Action::Update { due, .. } => &due.label,
+            Action::Delete { label, .. } => label,
+        }
+    }
+}
+
+/// Bring the due-date calendar in line with Classroom.
+pub async fn sync_due_dates(account: &Account, request: &Request) -> Result<Value, AppError> {
+    if account.is_offline() {
+        return Err(AppError::FailedPrecondition(
+            "due dates are synced with Google, which isn't possible offline".into(),
+        ));
+    }
+    account.require(ToolGroup::Classroom)?;
+    account.require(ToolGroup::CalendarWrite)?;
+    let calendar = &account.calendar;
+
+    let (calendar_id, existing) = match &request.calendar_id {
+        Some(id) => (Some(id.clone()), calendar.all_events(id).await?),
+        None => match account.store.setting(CALENDAR_SETTING)? {
+            Some(id) => match calendar.all_events(&id).await {
+                Ok(events) => (Some(id), events),
+                // The user deleted the calendar: start a new one.
+                Err(AppError::NotFound(_)) => (None, Vec::new()),
+                Err(e) => return Err(e),
+            },
+            None => (None, Vec::new()),
+        },
+    };
+
+    // The name goes into event descriptions, so it must be the same
+    // whether one course or all of them are synced.
+    let id_and_name = |course: &Value| {
+        let id = course["id"].as_str()?.to_string();
+        let name = course["name"].as_str().unwrap_or(&id).to_string();
+        Some((id, name))
+    };
+    let courses: Vec<(String, String)> = match &request.course_id {
+        Some(id) => {
+            let details = account
+                .classroom
+                .get_course_details(id, request.refresh)
+                .await?;
+            vec![id_and_name(&details["course"]).unwrap_or_else(|| (id.clone(), id.clone()))]
+        }
+        None => account
+            .classroom
+            .all_courses()
+            .await?
+            .iter()
+            .filter(|c| c["courseState"] == "ACTIVE")
+            .filter_map(id_and_name)
+            .collect(),
+    };
+    let mut wanted = Vec::new();
+    let mut done = HashSet::new();
+    let mut failures = Vec::new();
+    let mut failed_courses = HashSet::new();
+    for (course_id, course_name) in &courses {
+        let listings = async {
+            let course_work = account.classroom.all_course_work(course_id).await?;
+            let submissions = account.classroom.all_submissions(course_id).await?;
+            Ok::<_, AppError>((course_work, submissions))
+        };
+        let (course_work, submissions) = match listings.await {
+            Ok(listings) => listings,
+            // Asked about one course: fail rather than report nothing.
+            Err(e) if request.course_id.is_some() => return Err(e),
+            Err(e) => {
+                failures.push(json!({ "item": course_name, "error": e.to_string() }));
+                failed_courses.insert(course_id.as_str());
+                continue;
+            }
+        };
+        for work in &course_work {
+            if turned_in(work, &submissions) {
+                done.extend(work["id"].as_str().map(String::from));
+                continue;
+            }
+            wanted.extend(due(course_id, course_name, work, &request.reminders));
+        }
+    }
+
+    // The events of other courses, and of courses that couldn't be listed,
+    // are left as they are.
+    let in_scope = |event: &Event| {
+        let course = private(event, COURSE_KEY);
+        request
+            .course_id
+            .as_deref()
+            .is_none_or(|wanted| course == Some(wanted))
+            && course.is_none_or(|course| !failed_courses.contains(course))
+    };
+    let existing: Vec<Event> = existing.into_iter().filter(in_scope).collect();
+    let (actions, unchanged) = plan(wanted, existing, &done);
+
+    let new_calendar = calendar_id.is_none();
+    if request.dry_run {
+        return Ok(report(
+            calendar_id,
+            new_calendar,
+            true,
+            &actions,
+            unchanged,
+            failures,
+        ));
+    }
+    let id = match calendar_id {
+        Some(id) => id,
+        None => {
+            let id = calendar.create_calendar(CALENDAR_NAME).await?;
+            account.store.set_setting(CALENDAR_SETTING, &id)?;
+            id
+        }
+    };
+
+    for action in &actions {
+        let result = match action {
+            Action::Create(due) => calendar.insert_event(&id, due.event.clone(), false).await,
+            Action::Update { event_id, due } => {
+                calendar
+                    .patch_event(&id, event_id, due.event.clone(), false)
+                    .await
+            }
+            Action::Delete { event_id, .. } => calendar.delete_event(&id, event_id, false).await,
+        };
+        if let Err(e) = result {
+            failures.push(json!({ "item": action.label(), "error": e.to_string() }));
+        }
+    }
+    Ok(report(
+        Some(id),
+        new_calendar,
+        false,
+        &actions,
+        unchanged,
+        failures,
+    ))
+}
+
+fn report(
+    calendar_id: Option<String>,
+    new_calendar: bool,
+    dry_run: bool,
+    actions: &[Action],
+    unchanged: usize,
+    failures: Vec<Value>,
+) -> Value {
+    let mut created = Vec::new();
+    let mut updated = Vec::new();
+    let mut removed = Vec::new();
+    for action in actions {
+        let list = match action {
+            Action::Create(_) => &mut created,
+            Action::Update { .. } => &mut updated,
+            Action::Delete { .. } => &mut removed,
+        };
+        list.push(action.label().clone());
+    }
+    json!({
+        "calendarId": calendar_id,
+        // Whether the dedicated calendar was (or, in a dry run, would be) created.
+        "newCalendar": new_calendar,
+        "dryRun": dry_run,
+        "created": created,
+        "updated": updated,
+        "removed": removed,
+        "unchanged": unchanged,
+        "failures": failures,
+    })
+}
+
+/// Whether every submission to `work` has been turned in (for a student,
+/// their own one).
+fn turned_in(work: &Value, submissions: &[Value]) -> bool {
+    let mut mine = submissions
+        .iter()
+        .filter(|s| s["courseWorkId"] == work["id"])
+        .peekable();
+    mine.peek().is_some()
+        && mine.all(|s| matches!(s["state"].as_str(), Some("TURNED_IN" | "RETURNED")))
+}
+
+/// The event for a published coursework item with a due date.
+fn due(course_id: &str, course_name: &str, work: &Value, reminders: &[u32]) -> Option<Due> {
+    if work["state"]
+        .as_str()
+        .is_some_and(|state| state != "PUBLISHED")
+    {
+        return None;
+    }
+    let id = work["id"].as_str()?;
+    let date = &work["dueDate"];
+    let day = NaiveDate::from_ymd_opt(
+        date["year"].as_i64()? as i32,
+        date["month"].as_u64()? as u32,
+        date["day"].as_u64()? as u32,
+    )?;
+    let title = work["title"].as_str().unwrap_or("Untitled");
+
+    // Classroom gives due times in UTC, leaving out fields that are zero.
+    let time = &work["dueTime"];
+    let (start, end, due_at) = if time.is_object() {
+        let at = NaiveTime::from_hms_opt(
+            time["hours"].as_u64().unwrap_or(0) as u32,
+            time["minutes"].as_u64().unwrap_or(0) as u32,
+            0,
+        )?;
+        let at = day.and_time(at).and_utc();
+        let start = at - Duration::minutes(TIMED_EVENT_MINUTES);
+        (
+            EventDateTime {
+                date_time: Some(start),
+                ..EventDateTime::default()
+            },
+            EventDateTime {
+                date_time: Some(at),
+                ..EventDateTime::default()
+            },
+            at.to_rfc3339(),
+        )
+    } else {
+        (
+            EventDateTime {
+                date: Some(day),
+                ..EventDateTime::default()
+            },
+            EventDateTime {
+                date: day.succ_opt(),
+                ..EventDateTime::default()
+            },
+            day.to_string(),
+        )
+    };
+
+    let mut description = course_name.to_string();
+    if let Some(link) = work["alternateLink"].as_str() {
+        description.push('\n');
+        description.push_str(link);
+    }
+    let event = Event {
+        summary: Some(format!("Due: {title}")),
+        description: Some(description),
+        start: Some(start),
+        end: Some(end),
+        // A due date shouldn't make the user look busy.
+        transparency: Some("transparent".into()),
+        reminders: Some(EventReminders {
+            use_default: Some(false),
+            overrides: Some(
+                reminders
+                    .iter()
+                    .map(|&minutes| EventReminder {
+                        method: Some("popup".into()),
+                        minutes: Some(minutes as i32),
+                    })
+                    .collect(),
+            ),
+        }),
+        extended_properties: Some(EventExtendedProperties {
+            private: Some(HashMap::from([
+                (COURSE_WORK_KEY.to_string(), id.to_string()),
+                (COURSE_KEY.to_string(), course_id.to_string()),
+            ])),
+            shared: None,
+        }),
+        ..Event::default()
+    };
+    Some(Due {
+        course_work_id: id.to_string(),
+        event,
+        label: json!({
+            "courseWorkId": id,
+            "title": title,
+            "course": course_name,
+            "due": due_at,
+        }),
+    })
+}
+
+fn private<'a>(event: &'a Event, key: &str) -> Option<&'a str> {
+    event
+        .extended_properties
+        .as_ref()?
+        .private
+        .as_ref()?
+        .get(key)
+        .map(String::as_str)
+}
+
+/// Match the wanted events with the existing ones by coursework ID. Returns
+/// what to do and how many events are already right.
+fn plan(
+    wanted: Vec<Due>,
+    existing: Vec<Event>,
+    turned_in: &HashSet<String>,
+) -> (Vec<Action>, usize) {
+    let mut by_work: HashMap<String, Event> = HashMap::new();
+    let mut actions = Vec::new();
+    for event in existing {
+        let (Some(work), Some(event_id)) = (private(&event, COURSE_WORK_KEY), event.id.clone())
+        else {
+            continue;
+        };
+        match by_work.entry(work.to_string()) {
+            // A duplicate, say from an interrupted run.
+            Entry::Occupied(entry) => actions.push(Action::Delete {
+                event_id,
+                label: removed_label(entry.key(), &event, "duplicate"),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(event);
+            }
+        }
+    }
+
+    let mut unchanged = 0;
+    for due in wanted {
+        match by_work.remove(&due.course_work_id) {
+            None => actions.push(Action::Create(due)),
+            Some(event) if differs(&due.event, &event) => actions.push(Action::Update {
+                event_id: event.id.clone().unwrap_or_default(),
+                due,
+            }),
+            Some(_) => unchanged += 1,
+        }
+    }
+    let mut stale: Vec<(String, Event)> = by_work.into_iter().collect();
+    stale.sort_by(|a, b| a.0.cmp(&b.0));
+    for (work, event) in stale {
+        let reason = if turned_in.contains(&work) {
+            "turned in"
+        } else {
+            "deleted, without a due date or no longer in an active course"
+        };
+        actions.push(Action::Delete {
+            event_id: event.id.clone().unwrap_or_default(),
+            label: removed_label(&work, &event, reason),
+        });
+    }
+    (actions, unchanged)
+}
+
+fn removed_label(work: &str, event: &Event, reason: &str) -> Value {
+    json!({
+        "courseWorkId": work,
+        "summary": event.summary,
+        "reason": reason,
+    })
+}
+
+/// Whether an existing event differs from the wanted one in what a sync sets.
+fn differs(wanted: &Event, existing: &Event) -> bool {
+    let time = |t: &Option<EventDateTime>| t.as_ref().map(|t| (t.date, t.date_time));
+    let reminders = |e: &Event| {
+        e.reminders.as_ref().map(|r| {
+            let overrides: Vec<(Option<String>, Option<i32>)> = r
+                .overrides
+                .iter()
+                .flatten()
+                .map(|o| (o.method.clone(), o.minutes))
+                .collect();
+            (r.use_default, overrides)
+        })
+    };
+    wanted.summary != existing.summary
+        || wanted.description != existing.description
+        || time(&wanted.start) != time(&existing.start)
+        || time(&wanted.end) != time(&existing.end)
+        || wanted.transparency != existing.transparency
+        || reminders(wanted) != reminders(existing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_creates_updates_and_removals() {
+        let work = |id: &str, title: &str| {
+            json!({"id": id, "title": title, "state": "PUBLISHED",
+                   "dueDate": {"year": 2026, "month": 3, "day": 9},
+                   "dueTime": {"hours": 23, "minutes": 59}})
+        };
+        let reminders = DEFAULT_REMINDERS;
+        let essay = due("c1", "English", &work("w1", "Essay"), reminders).unwrap();
+        let start = essay.event.start.as_ref().unwrap().date_time.unwrap();
+        assert_eq!(start.to_rfc3339(), "2026-03-09T23:29:00+00:00");
+        let all_day = json!({"id": "w4", "title": "Reading", "dueDate": {"year": 2026, "month": 3, "day": 9}});
+        let reading = due("c1", "English", &all_day, reminders).unwrap();
+        assert_eq!(
+            reading.event.end.as_ref().unwrap().date,
+            NaiveDate::from_ymd_opt(2026, 3, 10)
+        );
+        assert!(due(
+            "c1",
+            "English",
+            &json!({"id": "w5", "title": "Open"}),
+            reminders
+        )
+        .is_none());
+
+        // What an earlier run created: w1 as it is, w2 under an old title,
+        // w3 since turned in, and a duplicate of w1.
+        let existing = |id: &str, due: Due| Event {
+            id: Some(id.into()),
+            ..due.event
+        };
+        let events = vec![
+            existing(
+                "e1",
+                due("c1", "English", &work("w1", "Essay"), reminders).unwrap(),
+            ),
+            existing(
+                "e2",
+                due("c1", "English", &work("w2", "Draft"), reminders).unwrap(),
+            ),
+            existing(
+                "e3",
+                due("c1", "English", &work("w3", "Poem"), reminders).unwrap(),
+            ),
+            existing(
+                "e4",
+                due("c1", "English", &work("w1", "Essay"), reminders).unwrap(),
+            ),
+            Event {
+                id: Some("mine".into()),
+                summary: Some("Not ours".into()),
+                ..Event::default()
+            },
+        ];
+        let wanted = vec![
+            essay,
+            due("c1", "English", &work("w2", "Final draft"), reminders).unwrap(),
+            reading,
+        ];
+        let turned_in = HashSet::from(["w3".to_string()]);
+
+        let (actions, unchanged) = plan(wanted, events, &turned_in);
+        assert_eq!(unchanged, 1);
+        let summary: Vec<String> = actions
+            .iter()
+            .map(|action| match action {
+                Action::Create(due) => format!("create {}", due.course_work_id),
+                Action::Update { event_id, .. } => format!("update {event_id}"),
+                Action::Delete { event_id, label } => {
+                    format!("delete {event_id} ({})", label["reason"].as_str().unwrap())
+                }
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "delete e4 (duplicate)",
+                "update e2",
+                "create w4",
+                "delete e3 (turned in)"
+            ]
+        );
+    }
+
+    #[test]
+    fn turned_in_needs_every_submission() {
+        let work = json!({"id": "w1"});
+        let submission = |state: &str| json!({"courseWorkId": "w1", "state": state});
+        assert!(!turned_in(&work, &[]));
+        assert!(turned_in(&work, &[submission("TURNED_IN")]));
+        assert!(!turned_in(
+            &work,
+            &[submission("RETURNED"), submission("CREATED")]
+        ));
+    }
+}
@@ -1,18 +1,58 @@
 use thiserror::Error;
 
+use crate::retry::ApiError;
+
 #[derive(Debug, Error)]
 pub enum AppError {
     #[error("not authenticated — run `auth` first to set up credentials")]
     NotAuthenticated,
 
+    #[error(
+        "account '{account}' is no longer authenticated ({reason}) — {remedy}; \
+         the server picks up new credentials automatically"
+    )]
+    AuthExpired {
+        account: String,
+        reason: String,
+        remedy: String,
+    },
+
+    #[error("account '{account}' hasn't granted the scopes for the '{group}' tools — {remedy}")]
+    MissingScope {
+        account: String,
+        group: &'static str,
+        remedy: String,
+    },
+
     #[error("failed to read credentials: {0}")]
     CredentialRead(String),
 
     #[error("Google API error: {0}")]
     GoogleApi(String),
 
-    #[error("Drive API error: {0}")]
-    DriveApi(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Google rejected the credentials: {0}")]
+    Unauthenticated(String),
+
+    #[error("failed precondition: {0}")]
+    FailedPrecondition(String),
+
+    #[error("temporary Google API failure, try again later: {0}")]
+    Transient(String),
+
+    #[error("unexpected response from Google: {0}")]
+    InvalidResponse(String),
+
+    #[error("not supported: {0}")]
+    Unsupported(String),
 
     #[error("Invalid input: {0}")]
     InvalidInput(String),
@@ -20,9 +60,215 @@ pub enum AppError {
     #[error("OAuth2 error: {0}")]
     OAuth2(String),
 
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    #[error("local store error: {0}")]
+    Store(String),
+
+    #[error("offline, and {0} was never fetched while online")]
+    NotCached(String),
+
+    #[error("search index error: {0}")]
+    Search(String),
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
 }
+
+impl AppError {
+    /// Recover an error shared between callers waiting on the same request
+    /// (see `CacheLayer`), copying it if others still hold a reference.
+    pub fn from_shared(err: std::sync::Arc<AppError>) -> Self {
+        std::sync::Arc::try_unwrap(err).unwrap_or_else(|err| err.duplicate())
+    }
+
+    fn duplicate(&self) -> Self {
+        match self {
+            Self::NotAuthenticated => Self::NotAuthenticated,
+            Self::AuthExpired {
+                account,
+                reason,
+                remedy,
+            } => Self::AuthExpired {
+                account: account.clone(),
+                reason: reason.clone(),
+                remedy: remedy.clone(),
+            },
+            Self::MissingScope {
+                account,
+                group,
+                remedy,
+            } => Self::MissingScope {
+                account: account.clone(),
+                group,
+                remedy: remedy.clone(),
+            },
+            Self::CredentialRead(m) => Self::CredentialRead(m.clone()),
+            Self::GoogleApi(m) => Self::GoogleApi(m.clone()),
+            Self::NotFound(m) => Self::NotFound(m.clone()),
+            Self::PermissionDenied(m) => Self::PermissionDenied(m.clone()),
+            Self::QuotaExceeded(m) => Self::QuotaExceeded(m.clone()),
+            Self::Unauthenticated(m) => Self::Unauthenticated(m.clone()),
+            Self::FailedPrecondition(m) => Self::FailedPrecondition(m.clone()),
+            Self::Transient(m) => Self::Transient(m.clone()),
+            Self::InvalidResponse(m) => Self::InvalidResponse(m.clone()),
+            Self::Unsupported(m) => Self::Unsupported(m.clone()),
+            Self::InvalidInput(m) => Self::InvalidInput(m.clone()),
+            Self::OAuth2(m) => Self::OAuth2(m.clone()),
+            Self::Encryption(m) => Self::Encryption(m.clone()),
+            Self::Store(m) => Self::Store(m.clone()),
+            Self::NotCached(m) => Self::NotCached(m.clone()),
+            Self::Search(m) => Self::Search(m.clone()),
+            Self::Io(e) => Self::Io(std::io::Error::new(e.kind(), e.to_string())),
+            Self::Json(e) => {
+                Self::Json(serde_json::Error::io(std::io::Error::other(e.to_string())))
+            }
+        }
+    }
+
+    /// Prefix the message of an API error with what was being done.
+    pub fn context(self, context: impl std::fmt::Display) -> Self {
+        let wrap = |msg: String| format!("{context}: {msg}");
+        match self {
+            Self::GoogleApi(m) => Self::GoogleApi(wrap(m)),
+            Self::InvalidResponse(m) => Self::InvalidResponse(wrap(m)),
+            Self::Unsupported(m) => Self::Unsupported(wrap(m)),
+            Self::NotFound(m) => Self::NotFound(wrap(m)),
+            Self::PermissionDenied(m) => Self::PermissionDenied(wrap(m)),
+            Self::QuotaExceeded(m) => Self::QuotaExceeded(wrap(m)),
+            Self::Unauthenticated(m) => Self::Unauthenticated(wrap(m)),
+            Self::FailedPrecondition(m) => Self::FailedPrecondition(wrap(m)),
+            Self::Transient(m) => Self::Transient(wrap(m)),
+            Self::InvalidInput(m) => Self::InvalidInput(wrap(m)),
+            other => other,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Store(err.to_string())
+    }
+}
+
+impl From<tantivy::TantivyError> for AppError {
+    fn from(err: tantivy::TantivyError) -> Self {
+        Self::Search(err.to_string())
+    }
+}
+
+/// Google error reasons meaning a quota or rate limit was hit.
+const QUOTA_REASONS: &[&str] = &[
+    "rateLimitExceeded",
+    "userRateLimitExceeded",
+    "quotaExceeded",
+    "dailyLimitExceeded",
+    "RATE_LIMIT_EXCEEDED",
+];
+
+/// Classify a failed call by its HTTP status and the `status` and `reason`
+/// fields of Google's JSON error body.
+impl From<ApiError> for AppError {
+    fn from(err: ApiError) -> Self {
+        use google_classroom1::common::Error;
+
+        let message = err.message();
+        match &err.source {
+            // The token provider's own errors (see `ManagedAuth`) pass through.
+            Error::MissingToken(e) => {
+                return match e.downcast_ref::<AppError>() {
+                    Some(e @ (Self::AuthExpired { .. } | Self::MissingScope { .. })) => {
+                        e.duplicate()
+                    }
+                    _ => Self::Unauthenticated(message),
+                };
+            }
+            Error::HttpError(_) | Error::Io(_) => return Self::Transient(message),
+            _ => {}
+        }
+        let reasons = err.reasons();
+        let is_quota = reasons.iter().any(|r| QUOTA_REASONS.contains(&r.as_str()));
+        match (err.status.map(|s| s.as_u16()), err.google_status()) {
+            (_, Some("RESOURCE_EXHAUSTED")) | (Some(429), _) => Self::QuotaExceeded(message),
+            (Some(403), _) if is_quota => Self::QuotaExceeded(message),
+            (_, Some("UNAUTHENTICATED")) | (Some(401), _) => Self::Unauthenticated(message),
+            (_, Some("PERMISSION_DENIED")) | (Some(403), _) => Self::PermissionDenied(message),
+            (_, Some("NOT_FOUND")) | (Some(404), _) => Self::NotFound(message),
+            (_, Some("FAILED_PRECONDITION")) | (Some(412), _) => Self::FailedPrecondition(message),
+            (_, Some("INVALID_ARGUMENT")) | (Some(400), _) => Self::InvalidInput(message),
+            (Some(408), _) | (Some(500..=599), _) => Self::Transient(message),
+            _ => Self::GoogleApi(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use google_classroom1::common::Error;
+    use hyper::StatusCode;
+    use serde_json::json;
+
+    fn api_error(code: u16, status: &str, reason: &str) -> AppError {
+        AppError::from(ApiError {
+            status: StatusCode::from_u16(code).ok(),
+            source: Error::BadRequest(json!({
+                "error": {
+                    "code": code,
+                    "message": "details from Google",
+                    "status": status,
+                    "errors": [{ "reason": reason }],
+                }
+            })),
+        })
+    }
+
+    #[test]
+    fn classifies_google_errors() {
+        assert!(matches!(
+            api_error(404, "NOT_FOUND", "notFound"),
+            AppError::NotFound(m) if m == "details from Google"
+        ));
+        assert!(matches!(
+            api_error(403, "PERMISSION_DENIED", "insufficientPermissions"),
+            AppError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            api_error(403, "PERMISSION_DENIED", "userRateLimitExceeded"),
+            AppError::QuotaExceeded(_)
+        ));
+        assert!(matches!(
+            api_error(429, "RESOURCE_EXHAUSTED", "rateLimitExceeded"),
+            AppError::QuotaExceeded(_)
+        ));
+        assert!(matches!(
+            api_error(401, "UNAUTHENTICATED", "authError"),
+            AppError::Unauthenticated(_)
+        ));
+        assert!(matches!(
+            api_error(400, "FAILED_PRECONDITION", "failedPrecondition"),
+            AppError::FailedPrecondition(_)
+        ));
+        assert!(matches!(
+            api_error(400, "INVALID_ARGUMENT", "badRequest"),
+            AppError::InvalidInput(_)
+        ));
+        assert!(matches!(
+            api_error(503, "UNAVAILABLE", "backendError"),
+            AppError::Transient(_)
+        ));
+    }
+
+    #[test]
+    fn context_prefixes_message() {
+        let err = api_error(404, "NOT_FOUND", "notFound").context("course 123");
+        assert_eq!(
+            err.to_string(),
+            "not found: course 123: details from Google"
+        );
+    }
+}
@@ -0,0 +1,613 @@
+//! Course archives: a permanent copy of a course, for when a class ends.
+//!
+//! An export is a directory (or a `.zip` of one) holding:
+//!
+//! - `README.md`, an index of the course's coursework and materials grouped
+//!   by topic, followed by its announcements;
+//! - one Markdown file per item under `coursework/`, `materials/` and
+//!   `announcements/`, linking to its attachments;
+//! - `attachments/`, the attached Drive files downloaded at full size, with
+//!   Docs, Slides and Drawings converted to PDF and Sheets to Excel;
+//! - `manifest.json`, everything above in machine-readable form, including
+//!   the raw Classroom objects.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::accounts::Account;
+use crate::auth::account_dir;
+use crate::classroom::{announcement_title, drive_file_ids};
+use crate::error::AppError;
+use crate::scopes::ToolGroup;
+
+/// What an export wrote.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Exported {
+    pub path: PathBuf,
+    pub course_work: usize,
+    pub materials: usize,
+    pub announcements: usize,
+    pub attachments: usize,
+    /// Attachments that could not be downloaded, with the reason.
+    pub failures: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    CourseWork,
+    Material,
+    Announcement,
+}
+
+impl Kind {
+    fn label(self) -> &'static str {
+        match self {
+            Kind::CourseWork => "Assignment",
+            Kind::Material => "Material",
+            Kind::Announcement => "Announcement",
+        }
+    }
+
+    fn dir(self) -> &'static str {
+        match self {
+            Kind::CourseWork => "coursework",
+            Kind::Material => "materials",
+            Kind::Announcement => "announcements",
+        }
+    }
+
+    fn manifest_name(self) -> &'static str {
+        match self {
+            Kind::CourseWork => "courseWork",
+            Kind::Material => "material",
+            Kind::Announcement => "announcement",
+        }
+    }
+}
+
+/// A coursework item, material or announcement, and the file it's written to.
+#[derive(Debug)]
+struct Item<'a> {
+    kind: Kind,
+    data: &'a Value,
+    title: String,
+    file: String,
+}
+
+impl<'a> Item<'a> {
+    fn new(kind: Kind, data: &'a Value) -> Self {
+        let id = data["id"].as_str().unwrap_or_default();
+        let (title, file) = match kind {
+            Kind::Announcement => {
+                let title = announcement_title(data);
+                let date = data["creationTime"].as_str().unwrap_or_default();
+                let file = format!("{}/{}-{id}.md", kind.dir(), date.get(..10).unwrap_or(""));
+                (title, file)
+            }
+            _ => {
+                let title = data["title"].as_str().unwrap_or("Untitled").to_string();
+                let file = format!("{}/{}-{id}.md", kind.dir(), slug(&title));
+                (title, file)
+            }
+        };
+        Self {
+            kind,
+            data,
+            title,
+            file,
+        }
+    }
+}
+
+/// An attached Drive file, downloaded into the archive or not.
+#[derive(Debug, Default)]
+struct Attachment {
+    /// Path inside the archive.
+    file: Option<String>,
+    metadata: Value,
+    error: Option<String>,
+}
+
+/// Where an export is written: a directory or, if the path ends in `.zip`, a
+/// zip file.
+#[derive(Debug, Clone, Copy)]
+pub enum Destination<'a> {
+    /// `exports/<course name>-<course id>` in the account's directory.
+    Default,
+    /// A relative path inside the account's `exports/` directory, as the
+    /// `course_export` tool takes it.
+    Exports(&'a str),
+    /// Any path, as the `export` command takes it.
+    Path(&'a Path),
+}
+
+/// Export a course to `destination`. An existing file, or a directory that
+/// isn't empty, is only written over with `overwrite`. The course's content is
+/// always fetched from Google; `refresh` also skips the cached course details.
+pub async fn export_course(
+    account: &Account,
+    course_id: &str,
+    destination: Destination<'_>,
+    overwrite: bool,
+    refresh: bool,
+) -> Result<Exported, AppError> {
+    if account.is_offline() {
+        return Err(AppError::FailedPrecondition(
+            "exports fetch the whole course from Google, which isn't possible offline".into(),
+        ));
+    }
+    account.require(ToolGroup::Classroom)?;
+    let classroom = &account.classroom;
+    let course = classroom.get_course_details(course_id, refresh).await?["course"].take();
+    let exports = || Ok::<_, AppError>(account_dir(&account.profile)?.join("exports"));
+    let path = match destination {
+        Destination::Default => {
+            let name = course["name"].as_str().unwrap_or(course_id);
+            exports()?.join(format!("{}-{course_id}", slug(name)))
+        }
+        Destination::Exports(name) => inside(&exports()?, name)?,
+        Destination::Path(path) => path.to_path_buf(),
+    };
+    let zip = path.extension().is_some_and(|ext| ext == "zip");
+    if !overwrite && is_taken(&path, zip) {
+        return Err(AppError::FailedPrecondition(format!(
+            "{} already exists{}; choose another path or set overwrite",
+            path.display(),
+            if zip { "" } else { " and isn't empty" }
+        )));
+    }
+
+    let topics = classroom.all_topics(course_id).await?;
+    let course_work = classroom.all_course_work(course_id).await?;
+    let materials = classroom.all_materials(course_id).await?;
+    let announcements = classroom.all_announcements(course_id).await?;
+
+    let items: Vec<Item> = [
+        (Kind::CourseWork, &course_work),
+        (Kind::Material, &materials),
+        (Kind::Announcement, &announcements),
+    ]
+    .into_iter()
+    .flat_map(|(kind, list)| list.iter().map(move |data| Item::new(kind, data)))
+    .collect();
+
+    let mut files = BTreeMap::new();
+    let mut attachments = BTreeMap::new();
+    let mut failures = Vec::new();
+    let mut file_ids = BTreeSet::new();
+    for item in &items {
+        drive_file_ids(item.data, &mut file_ids);
+    }
+    let skip_files = account.require(ToolGroup::Drive).err();
+    for file_id in file_ids {
+        let attachment = match &skip_files {
+            Some(e) => Attachment {
+                error: Some(e.to_string()),
+                ..Attachment::default()
+            },
+            None => match account.drive.download(&file_id).await {
+                Ok(download) => {
+                    let file = download.content.map(|content| {
+                        let file = format!(
+                            "attachments/{file_id}-{}",
+                            safe_file_name(&download.file_name)
+                        );
+                        files.insert(file.clone(), content);
+                        file
+                    });
+                    Attachment {
+                        error: file
+                            .is_none()
+                            .then(|| "this kind of Google file can't be downloaded".to_string()),
+                        file,
+                        metadata: download.metadata,
+                    }
+                }
+                Err(e) => Attachment {
+                    error: Some(e.to_string()),
+                    ..Attachment::default()
+                },
+            },
+        };
+        if let Some(error) = &attachment.error {
+            failures.push(format!("Drive file {file_id}: {error}"));
+        }
+        attachments.insert(file_id, attachment);
+    }
+
+    for item in &items {
+        files.insert(
+            item.file.clone(),
+            item_markdown(item, &topics, &attachments).into_bytes(),
+        );
+    }
+    files.insert(
+        "README.md".into(),
+        index(&course, &topics, &items).into_bytes(),
+    );
+    let manifest = json!({
+        "exportedAt": Utc::now().to_rfc3339(),
+        "account": account.profile,
+        "course": course,
+        "topics": topics,
+        "items": items.iter().map(|item| json!({
+            "kind": item.kind.manifest_name(),
+            "id": item.data["id"],
+            "title": item.title,
+            "topicId": item.data["topicId"],
+            "file": item.file,
+            "data": item.data,
+        })).collect::<Vec<_>>(),
+        "attachments": attachments.iter().map(|(id, attachment)| json!({
+            "fileId": id,
+            "file": attachment.file,
+            "metadata": attachment.metadata,
+            "error": attachment.error,
+        })).collect::<Vec<_>>(),
+    });
+    files.insert(
+        "manifest.json".into(),
+        serde_json::to_vec_pretty(&manifest)?,
+    );
+
+    if zip {
+        write_zip(&path, &files)?;
+    } else {
+        write_dir(&path, &files)?;
+    }
+
+    let count = |kind| items.iter().filter(|item| item.kind == kind).count();
+    Ok(Exported {
+        path,
+        course_work: count(Kind::CourseWork),
+        materials: count(Kind::Material),
+        announcements: count(Kind::Announcement),
+        attachments: attachments.values().filter(|a| a.file.is_some()).count(),
+        failures,
+    })
+}
+
+/// The archive's `README.md`: coursework and materials by topic, in the
+/// order they were posted, then the announcements.
+fn index(course: &Value, topics: &[Value], items: &[Item]) -> String {
+    let mut out = format!("# {}\n\n", course["name"].as_str().unwrap_or("Course"));
+    for field in ["section", "descriptionHeading", "description"] {
+        if let Some(text) = course[field].as_str().filter(|t| !t.is_empty()) {
+            out.push_str(&format!("{text}\n\n"));
+        }
+    }
+    out.push_str(&format!(
+        "Exported {}.\n",
+        Utc::now().format("%Y-%m-%d %H:%M UTC")
+    ));
+
+    let mut items: Vec<&Item> = items.iter().collect();
+    items.sort_by_key(|item| item.data["creationTime"].as_str().unwrap_or_default());
+    let posts = |topic: Option<&str>| {
+        items
+            .iter()
+            .filter(move |item| {
+                item.kind != Kind::Announcement
+                    && match topic {
+                        Some(topic) => item.data["topicId"].as_str() == Some(topic),
+                        None => !topics
+                            .iter()
+                            .any(|t| t["topicId"].as_str() == item.data["topicId"].as_str()),
+                    }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut sections: Vec<(&str, Vec<&&Item>)> = topics
+        .iter()
+        .filter_map(|t| {
+            let id = t["topicId"].as_str()?;
+            Some((t["name"].as_str().unwrap_or(id), posts(Some(id))))
+        })
+        .collect();
+    sections.push(("Other", posts(None)));
+    for (name, posts) in sections {
+        if posts.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("\n## {name}\n\n"));
+        for item in posts {
+            out.push_str(&format!(
+                "- {}: [{}]({})",
+                item.kind.label(),
+                item.title,
+                item.file
+            ));
+            if let Some(due) = due(item.data) {
+                out.push_str(&format!(" — due {due}"));
+            }
+            out.push('\n');
+        }
+    }
+
+    let announcements: Vec<_> = items
+        .iter()
+        .filter(|item| item.kind == Kind::Announcement)
+        .collect();
+    if !announcements.is_empty() {
+        out.push_str("\n## Announcements\n\n");
+        for item in announcements {
+            let date = item.data["creationTime"].as_str().unwrap_or_default();
+            out.push_str(&format!(
+                "- {}: [{}]({})\n",
+                date.get(..10).unwrap_or(""),
+                item.title,
+                item.file
+            ));
+        }
+    }
+    out
+}
+
+/// One item's Markdown file.
+fn item_markdown(
+    item: &Item,
+    topics: &[Value],
+    attachments: &BTreeMap<String, Attachment>,
+) -> String {
+    let data = item.data;
+    let mut out = format!("# {}\n\n- Type: {}\n", item.title, item.kind.label());
+    let topic = topics
+        .iter()
+        .find(|t| t["topicId"].as_str().is_some() && t["topicId"] == data["topicId"]);
+    if let Some(name) = topic.and_then(|t| t["name"].as_str()) {
+        out.push_str(&format!("- Topic: {name}\n"));
+    }
+    if let Some(due) = due(data) {
+        out.push_str(&format!("- Due: {due}\n"));
+    }
+    if let Some(points) = data["maxPoints"].as_f64() {
+        out.push_str(&format!("- Points: {points}\n"));
+    }
+    if let Some(posted) = data["creationTime"].as_str() {
+        out.push_str(&format!("- Posted: {posted}\n"));
+    }
+    if let Some(link) = data["alternateLink"].as_str() {
+        out.push_str(&format!("- Classroom: {link}\n"));
+    }
+    let body = match item.kind {
+        Kind::Announcement => &data["text"],
+        _ => &data["description"],
+    };
+    if let Some(body) = body.as_str().filter(|b| !b.trim().is_empty()) {
+        out.push_str(&format!("\n{}\n", body.trim_end()));
+    }
+
+    let materials = data["materials"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    if !materials.is_empty() {
+        out.push_str("\n## Attachments\n\n");
+    }
+    for material in materials {
+        let line = if let Some(file) = material["driveFile"]["driveFile"].as_object() {
+            let id = file["id"].as_str().unwrap_or_default();
+            let title = file["title"].as_str().unwrap_or(id);
+            let link = file["alternateLink"].as_str().unwrap_or_default();
+            match attachments.get(id) {
+                Some(Attachment {
+                    file: Some(path), ..
+                }) => format!("[{title}](../{path}) ({link})"),
+                Some(Attachment {
+                    error: Some(error), ..
+                }) => format!("{title} — not downloaded: {error} ({link})"),
+                _ => format!("{title} ({link})"),
+            }
+        } else if let Some(link) = material["link"].as_object() {
+            let url = link["url"].as_str().unwrap_or_default();
+            format!("[{}]({url})", link["title"].as_str().unwrap_or(url))
+        } else if let Some(video) = material["youtubeVideo"].as_object() {
+            let url = video["alternateLink"].as_str().unwrap_or_default();
+            format!("Video: [{}]({url})", video["title"].as_str().unwrap_or(url))
+        } else if let Some(form) = material["form"].as_object() {
+            let url = form["formUrl"].as_str().unwrap_or_default();
+            format!("Form: [{}]({url})", form["title"].as_str().unwrap_or(url))
+        } else {
+            continue;
+        };
+        out.push_str(&format!("- {line}\n"));
+    }
+    out
+}
+
+/// A coursework item's due date (and time, in UTC), if it has one.
+fn due(data: &Value) -> Option<String> {
+    let date = &data["dueDate"];
+    let date = format!(
+        "{}-{:02}-{:02}",
+        date["year"].as_u64()?,
+        date["month"].as_u64()?,
+        date["day"].as_u64()?
+    );
+    let time = &data["dueTime"];
+    Some(match time["hours"].as_u64() {
+        Some(hours) => format!(
+            "{date} {hours:02}:{:02} UTC",
+            time["minutes"].as_u64().unwrap_or(0)
+        ),
+        None => date,
+    })
+}
+
+/// A lowercase ASCII slug of a title, for file names.
+fn slug(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = truncate(&slug, 50);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "untitled".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// A Drive file name made safe to write on any file system.
+fn safe_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || "/\\:*?\"<>|".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        "file".to_string()
+    } else {
+        truncate(name, 120)
+    }
+}
+
+/// At most `max` characters of `s`.
+fn truncate(s: &str, max: usize) -> String {
+    s.chars().take(max).collect()
+}
+
+/// `name` under `base`, which it may not leave: absolute paths and `..` are
+/// refused.
+fn inside(base: &Path, name: &str) -> Result<PathBuf, AppError> {
+    let relative = Path::new(name);
+    if name.trim().is_empty()
+        || !relative
+            .components()
+            .all(|part| matches!(part, Component::Normal(_)))
+    {
+        return Err(AppError::InvalidInput(format!(
+            "'{name}' must be a relative path inside the exports directory, without '..'"
+        )));
+    }
+    Ok(base.join(relative))
+}
+
+/// Whether writing to `path` would replace something: any existing entry for
+/// a zip file, a directory with anything in it otherwise.
+fn is_taken(path: &Path, zip: bool) -> bool {
+    match std::fs::read_dir(path) {
+        Ok(mut entries) => zip || entries.next().is_some(),
+        Err(_) => path.symlink_metadata().is_ok(),
+    }
+}
+
+fn write_dir(path: &Path, files: &BTreeMap<String, Vec<u8>>) -> Result<(), AppError> {
+    for (name, content) in files {
+        let file = path.join(name);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(file, content)?;
+    }
+    Ok(())
+}
+
+fn write_zip(path: &Path, files: &BTreeMap<String, Vec<u8>>) -> Result<(), AppError> {
+    use zip::write::SimpleFileOptions;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path)?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, content) in files {
+        zip.start_file(name.as_str(), options)
+            .map_err(std::io::Error::other)?;
+        zip.write_all(content)?;
+    }
+    zip.finish().map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!(slug("Week 1: Intro (draft)!"), "week-1-intro-draft");
+        assert_eq!(slug("???"), "untitled");
+        assert_eq!(safe_file_name("../notes: v2?.pdf"), "_notes_ v2_.pdf");
+        assert_eq!(safe_file_name("..."), "file");
+    }
+
+    #[test]
+    fn tool_paths_stay_in_exports() {
+        let base = Path::new("/home/me/exports");
+        assert_eq!(
+            inside(base, "biology/2024.zip").unwrap(),
+            base.join("biology/2024.zip")
+        );
+        for name in ["", "/etc", "../README.md", "biology/../../x", "./biology"] {
+            assert!(
+                matches!(inside(base, name), Err(AppError::InvalidInput(_))),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn index_groups_by_topic() {
+        let course = json!({ "name": "Biology", "section": "Period 2" });
+        let topics = vec![
+            json!({ "topicId": "t1", "name": "Cells" }),
+            json!({ "topicId": "t2", "name": "Empty" }),
+        ];
+        let work = json!({
+            "id": "w1", "title": "Lab report", "topicId": "t1",
+            "creationTime": "2024-02-01T00:00:00Z",
+            "dueDate": { "year": 2024, "month": 2, "day": 9 },
+            "dueTime": { "hours": 23, "minutes": 59 },
+        });
+        let material = json!({
+            "id": "m1", "title": "Reading", "topicId": "t1",
+            "creationTime": "2024-01-01T00:00:00Z",
+        });
+        let loose = json!({ "id": "m2", "title": "Syllabus" });
+        let note = json!({
+            "id": "a1", "text": "\nWelcome!\nMore text", "creationTime": "2024-01-02T10:00:00Z",
+        });
+        let items = vec![
+            Item::new(Kind::CourseWork, &work),
+            Item::new(Kind::Material, &material),
+            Item::new(Kind::Material, &loose),
+            Item::new(Kind::Announcement, &note),
+        ];
+        let index = index(&course, &topics, &items);
+        let cells = index.find("## Cells").unwrap();
+        let reading = index
+            .find("- Material: [Reading](materials/reading-m1.md)")
+            .unwrap();
+        let lab = index
+            .find("- Assignment: [Lab report](coursework/lab-report-w1.md) — due 2024-02-09 23:59 UTC")
+            .unwrap();
+        let other = index.find("## Other").unwrap();
+        let syllabus = index.find("[Syllabus](materials/syllabus-m2.md)").unwrap();
+        let welcome = index
+            .find("- 2024-01-02: [Welcome!](announcements/2024-01-02-a1.md)")
+            .unwrap();
+        assert!(cells < reading && reading < lab && lab < other && other < syllabus);
+        assert!(syllabus < welcome);
+        assert!(index.contains("Period 2"));
+        assert!(!index.contains("## Empty"));
+    }
+}
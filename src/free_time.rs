@@ -0,0 +1,278 @@
+//! The `find_free_time` tool: open slots within working hours.
+//!
+//! Busy periods come from Calendar's free/busy query over the chosen
+//! calendars. Each day of the window offers its working hours, in the user's
+//! time zone, minus every busy period widened by the buffer; what is left is
+//! returned as slots, dropping those shorter than the minimum length.
+
+use chrono::{DateTime, Datelike, Days, Duration, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+
+use crate::accounts::Account;
+use crate::calendar::{merge_periods, Period};
+use crate::dates::{local, parse_time};
+use crate::error::AppError;
+
+/// Slots start on a multiple of this many minutes.
+const ALIGN_MINUTES: i64 = 5;
+
+/// The longest window searched. Free/busy rejects long ranges, and slots
+/// are worked out one day at a time.
+const MAX_DAYS: u32 = 62;
+
+/// What `find_free_time` was asked for; times are read in the user's zone.
+#[derive(Debug)]
+pub struct Request {
+    pub calendar_ids: Vec<String>,
+    pub time_min: Option<String>,
+    pub time_max: Option<String>,
+    pub days_ahead: u32,
+    pub hours: Hours,
+    pub min_minutes: u32,
+    pub buffer_minutes: u32,
+    /// IANA name; defaults to the primary calendar's time zone.
+    pub time_zone: Option<String>,
+    /// Look the time zone up at Google rather than in the cache. Busy times
+    /// are always fetched.
+    pub refresh: bool,
+}
+
+/// The part of each day in which slots are offered.
+#[derive(Debug, Clone, Copy)]
+pub struct Hours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub weekends: bool,
+}
+
+impl Hours {
+    /// Working hours from `HH:MM` times.
+    pub fn parse(start: &str, end: &str, weekends: bool) -> Result<Self, AppError> {
+        let time = |text: &str| {
+            NaiveTime::parse_from_str(text.trim(), "%H:%M").map_err(|_| {
+                AppError::InvalidInput(format!("'{text}' is not a time of day (HH:MM)"))
+            })
+        };
+        let hours = Self {
+            start: time(start)?,
+            end: time(end)?,
+            weekends,
+        };
+        if hours.end <= hours.start {
+            return Err(AppError::InvalidInput(
+                "working hours must end after they start".into(),
+            ));
+        }
+        Ok(hours)
+    }
+}
+
+/// Find open slots in the requested calendars.
+pub async fn find_free_time(account: &Account, request: &Request) -> Result<Value, AppError> {
+    if account.is_offline() {
+        return Err(AppError::FailedPrecondition(
+            "free time is looked up at Google, which isn't possible offline".into(),
+        ));
+    }
+    let calendar = &account.calendar;
+    let tz: Tz = match &request.time_zone {
+        Some(zone) => zone
+            .parse()
+            .map_err(|_| AppError::InvalidInput(format!("unknown time zone '{zone}'")))?,
+        None => calendar.zone("primary", request.refresh).await?,
+    };
+
+    let (time_min, time_max) = window(request, &Utc::now().with_timezone(&tz))?;
+
+    let (busy, failures) = calendar
+        .busy(&request.calendar_ids, time_min, time_max)
+        .await?;
+    let slots = free_slots(
+        &busy,
+        (time_min, time_max),
+        &request.hours,
+        Duration::minutes(request.min_minutes.max(1).into()),
+        Duration::minutes(request.buffer_minutes.into()),
+        &tz,
+    );
+
+    let at = |time: DateTime<Utc>| time.with_timezone(&tz).to_rfc3339();
+    Ok(json!({
+        "timeZone": tz.name(),
+        "timeMin": at(time_min),
+        "timeMax": at(time_max),
+        "workingHours": format!(
+            "{}-{}",
+            request.hours.start.format("%H:%M"),
+            request.hours.end.format("%H:%M")
+        ),
+        "slots": slots
+            .iter()
+            .map(|&(start, end)| json!({
+                "start": at(start),
+                "end": at(end),
+                "minutes": (end - start).num_minutes(),
+            }))
+            .collect::<Vec<_>>(),
+        // Calendars whose busy times Google couldn't give (not found, no access).
+        "failures": failures,
+    }))
+}
+
+/// The searched window, at most [`MAX_DAYS`] long.
+fn window(
+    request: &Request,
+    now: &DateTime<Tz>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), AppError> {
+    let time_min = match &request.time_min {
+        Some(text) => parse_time(text, now, false)?,
+        None => now.with_timezone(&Utc),
+    };
+    let time_max = match &request.time_max {
+        Some(text) => parse_time(text, now, true)?,
+        None => time_min
+            .checked_add_days(Days::new(request.days_ahead.into()))
+            .ok_or_else(|| {
+                AppError::InvalidInput(format!("days_ahead {} is too far", request.days_ahead))
+            })?,
+    };
+    if time_max <= time_min {
+        return Err(AppError::InvalidInput(
+            "time_max must be after time_min".into(),
+        ));
+    }
+    if time_max - time_min > Duration::days(MAX_DAYS.into()) {
+        return Err(AppError::InvalidInput(format!(
+            "free time can be searched at most {MAX_DAYS} days at a time"
+        )));
+    }
+    Ok((time_min, time_max))
+}
+
+/// The stretches of `window` within `hours` on each day, in `tz`, that are at
+/// least `buffer` away from every busy period and at least `min` long.
+fn free_slots<Z: TimeZone>(
+    busy: &[Period],
+    window: Period,
+    hours: &Hours,
+    min: Duration,
+    buffer: Duration,
+    tz: &Z,
+) -> Vec<Period> {
+    let busy = merge_periods(
+        busy.iter()
+            .map(|&(start, end)| (start - buffer, end + buffer))
+            .collect(),
+    );
+    let mut slots = Vec::new();
+    let mut day = window.0.with_timezone(tz).date_naive();
+    let last = window.1.with_timezone(tz).date_naive();
+    while day <= last {
+        let weekend = matches!(day.weekday(), Weekday::Sat | Weekday::Sun);
+        if hours.weekends || !weekend {
+            let end = local(tz, day.and_time(hours.end)).min(window.1);
+            let mut free = local(tz, day.and_time(hours.start)).max(window.0);
+            for &(busy_start, busy_end) in &busy {
+                if busy_end <= free {
+                    continue;
+                }
+                if busy_start >= end {
+                    break;
+                }
+                push_slot(&mut slots, free, busy_start, min);
+                free = busy_end;
+            }
+            push_slot(&mut slots, free, end, min);
+        }
+        let Some(next) = day.succ_opt() else {
+            break;
+        };
+        day = next;
+    }
+    slots
+}
+
+/// Add the slot from `start`, rounded up to a multiple of [`ALIGN_MINUTES`],
+/// to `end` if it is long enough.
+fn push_slot(slots: &mut Vec<Period>, start: DateTime<Utc>, end: DateTime<Utc>, min: Duration) {
+    let step = ALIGN_MINUTES * 60;
+    let seconds = start.timestamp();
+    let start = if seconds.rem_euclid(step) == 0 && start.timestamp_subsec_nanos() == 0 {
+        start
+    } else {
+        DateTime::from_timestamp(seconds - seconds.rem_euclid(step) + step, 0).unwrap_or(start)
+    };
+    if end - start >= min {
+        slots.push((start, end));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_avoid_busy_time_within_working_hours() {
+        let tz = chrono_tz::Europe::Berlin;
+        let at = |d: u32, h: u32, m: u32| {
+            tz.with_ymd_and_hms(2026, 3, d, h, m, 0)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let hours = Hours::parse("09:00", "17:00", false).unwrap();
+        // Friday 6 March, 10:02, to Monday 9 March, 12:00.
+        let window = (at(6, 10, 2), at(9, 12, 0));
+        let busy = [
+            (at(6, 11, 0), at(6, 12, 0)),
+            (at(6, 11, 30), at(6, 13, 0)),
+            (at(6, 16, 0), at(6, 16, 45)),
+            (at(9, 9, 30), at(9, 10, 0)),
+        ];
+        let slots = free_slots(
+            &busy,
+            window,
+            &hours,
+            Duration::minutes(30),
+            Duration::minutes(10),
+            &tz,
+        );
+        assert_eq!(
+            slots,
+            [
+                (at(6, 10, 5), at(6, 10, 50)),
+                (at(6, 13, 10), at(6, 15, 50)),
+                (at(9, 10, 10), at(9, 12, 0)),
+            ]
+        );
+        assert!(Hours::parse("17:00", "09:00", true).is_err());
+    }
+
+    #[test]
+    fn window_is_bounded() {
+        let now = chrono_tz::UTC
+            .with_ymd_and_hms(2026, 3, 6, 12, 0, 0)
+            .unwrap();
+        let mut request = Request {
+            calendar_ids: vec!["primary".into()],
+            time_min: None,
+            time_max: None,
+            days_ahead: 7,
+            hours: Hours::parse("09:00", "17:00", false).unwrap(),
+            min_minutes: 30,
+            buffer_minutes: 0,
+            time_zone: None,
+            refresh: false,
+        };
+        let (start, end) = window(&request, &now).unwrap();
+        assert_eq!(end - start, Duration::days(7));
+
+        request.days_ahead = u32::MAX;
+        assert!(matches!(
+            window(&request, &now),
+            Err(AppError::InvalidInput(_))
+        ));
+        request.days_ahead = MAX_DAYS + 1;
+        assert!(window(&request, &now).is_err());
+    }
+}
@@ -1,21 +1,48 @@
+mod accounts;
 mod auth;
+mod cache;
 mod calendar;
 mod classroom;
+mod config;
+mod crypto;
+mod dates;
 mod drive;
+mod due_dates;
 mod error;
+mod export;
+mod free_time;
+mod retry;
+mod scopes;
+mod search;
+#[cfg(feature = "semantic-search")]
+mod semantic;
+mod session;
+mod store;
+mod sync;
+mod token_store;
 mod tools;
+mod usage;
+mod whats_new;
 
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use clap::{Parser, Subcommand};
 use rmcp::ServiceExt;
 use rmcp::transport::stdio;
 
-use crate::auth::{build_hubs, run_auth_flow};
-use crate::calendar::CalendarClient;
-use crate::classroom::ClassroomClient;
-use crate::drive::DriveClient;
+use crate::accounts::{Account, AccountRegistry};
+use crate::auth::{
+    auth_refresh, auth_revoke, auth_status, build_hubs, build_profile_hubs, run_auth_flow,
+    AuthFlowOptions, HubSet, ServiceAccount, DEFAULT_PROFILE, OAUTH_REDIRECT_PORT,
+};
+use crate::config::Config;
+use crate::crypto::Cipher;
+use crate::error::AppError;
+use crate::export::Destination;
+use crate::scopes::ToolGroup;
 use crate::tools::GoogleService;
+use crate::usage::Usage;
 
 #[derive(Parser)]
 #[command(name = "personal-google-mcp", about = "MCP server for personal Google services")]
@@ -27,9 +54,90 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
     /// Start the MCP server (default)
-    Run,
+    Run {
+        /// Start without credentials and serve every tool from the local
+        /// store, without contacting Google
+        #[arg(long)]
+        offline: bool,
+    },
     /// Authenticate with Google and save tokens
-    Auth,
+    Auth {
+        /// Profile name to store the tokens under (e.g. `school`, `personal`)
+        #[arg(long, global = true, default_value = DEFAULT_PROFILE)]
+        profile: String,
+        /// Don't wait for a local browser: print the consent URL and paste
+        /// back the redirect URL (for SSH sessions and remote machines)
+        #[arg(long)]
+        headless: bool,
+        /// Local port for the `http://localhost:<port>` OAuth redirect
+        #[arg(long, default_value_t = OAUTH_REDIRECT_PORT)]
+        redirect_port: u16,
+        /// Grant the scopes of an additional tool group (repeatable)
+        #[arg(long, value_enum)]
+        add_scope: Vec<ToolGroup>,
+        #[command(subcommand)]
+        action: Option<AuthCommand>,
+    },
+    /// List, show or purge the on-disk cache
+    Cache {
+        /// Profile (or delegated user's email) whose cache to use
+        #[arg(long, global = true, default_value = DEFAULT_PROFILE)]
+        profile: String,
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+    /// Mirror all Classroom data and the Drive files it references into the
+    /// local store, reporting what changed since the last sync
+    Sync {
+        /// Profile to sync
+        #[arg(long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+    },
+    /// Write a course's content, attachments and a manifest to a directory
+    /// or zip file
+    Export {
+        /// The ID of the course, as listed by the `courses` tool
+        course_id: String,
+        /// Directory to write, or a path ending in `.zip` for a zip file
+        /// (default: `exports/` in the profile's directory)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Write over an existing file or a directory that isn't empty
+        #[arg(long)]
+        overwrite: bool,
+        /// Profile to export from
+        #[arg(long, default_value = DEFAULT_PROFILE)]
+        profile: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuthCommand {
+    /// Show the signed-in account, granted scopes and token lifetimes
+    Status,
+    /// Revoke access at Google and delete the saved tokens
+    Revoke,
+    /// Force an access-token refresh to check the refresh token still works
+    Refresh,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List cached entries with their age and size
+    List,
+    /// Print a cached entry
+    Show {
+        /// Entry key, as shown by `cache list`
+        key: String,
+    },
+    /// Delete cached entries
+    Purge {
+        /// Entry keys, as shown by `cache list`
+        keys: Vec<String>,
+        /// Delete every entry
+        #[arg(long)]
+        all: bool,
+    },
 }
 
 #[tokio::main]
@@ -48,20 +156,112 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let cli = Cli::parse();
+    let cipher = Cipher::from_env()?;
+    let config = Config::load()?;
 
-    match cli.command.unwrap_or(Command::Run) {
-        Command::Auth => {
-            run_auth_flow().await?;
+    match cli.command.unwrap_or(Command::Run { offline: false }) {
+        Command::Auth {
+            profile,
+            headless,
+            redirect_port,
+            add_scope,
+            action,
+        } => match action {
+            None => {
+                let options = AuthFlowOptions {
+                    headless,
+                    redirect_port,
+                    add_scopes: add_scope,
+                };
+                run_auth_flow(&profile, &options, &config, cipher).await?
+            }
+            Some(AuthCommand::Status) => auth_status(&profile, &config, cipher).await?,
+            Some(AuthCommand::Revoke) => auth_revoke(&profile, cipher).await?,
+            Some(AuthCommand::Refresh) => auth_refresh(&profile, &config, cipher).await?,
+        },
+        Command::Cache { profile, action } => match action {
+            CacheCommand::List => cache::list_entries(&profile, cipher)?,
+            CacheCommand::Show { key } => cache::show_entry(&profile, &key, cipher)?,
+            CacheCommand::Purge { keys, all } => {
+                cache::purge_entries(&profile, &keys, all, cipher)?
+            }
+        },
+        Command::Sync { profile } => {
+            let usage = Usage::new(&config.rate_limits);
+            let hubs = build_profile_hubs(&profile, &config, cipher.clone()).await?;
+            let account = Account::new(&profile, hubs, &usage, &config.cache, cipher)?;
+            sync::sync(&account).await?;
+        }
+        Command::Export {
+            course_id,
+            output,
+            overwrite,
+            profile,
+        } => {
+            let usage = Usage::new(&config.rate_limits);
+            let hubs = build_profile_hubs(&profile, &config, cipher.clone()).await?;
+            let account = Account::new(&profile, hubs, &usage, &config.cache, cipher)?;
+            let destination = match &output {
+                Some(path) => Destination::Path(path),
+                None => Destination::Default,
+            };
+            let exported =
+                export::export_course(&account, &course_id, destination, overwrite, false).await?;
+            println!(
+                "Exported {} coursework items, {} materials, {} announcements and {} attachments to {}",
+                exported.course_work,
+                exported.materials,
+                exported.announcements,
+                exported.attachments,
+                exported.path.display()
+            );
+            if !exported.failures.is_empty() {
+                println!("\nNot exported:");
+                for failure in &exported.failures {
+                    println!("  - {failure}");
+                }
+            }
         }
-        Command::Run => {
-            let (classroom_hub, drive_hub, calendar_hub) = build_hubs().await?;
-            let client = Arc::new(ClassroomClient::new(classroom_hub));
-            let drive_client = Arc::new(DriveClient::new(drive_hub));
-            let calendar_client = Arc::new(CalendarClient::new(calendar_hub));
-            let service = GoogleService::new(client, drive_client, calendar_client);
+        Command::Run { offline } => {
+            let usage = Arc::new(Usage::new(&config.rate_limits));
+            let (hubs, service_account) = if offline {
+                let accounts = store::stored_accounts()?;
+                if accounts.is_empty() {
+                    anyhow::bail!("no local data to serve offline — run the server online first");
+                }
+                tracing::info!(
+                    "offline mode: serving {} from the local store",
+                    accounts.join(", ")
+                );
+                let hubs = accounts
+                    .into_iter()
+                    .map(|account| Ok((account, HubSet::offline()?)))
+                    .collect::<Result<Vec<_>, AppError>>()?;
+                (hubs, None)
+            } else {
+                let service_account = ServiceAccount::load(&config).await?;
+                let hubs = build_hubs(&config, service_account.as_ref(), cipher.clone()).await?;
+                (hubs, service_account)
+            };
+            let accounts = hubs
+                .into_iter()
+                .map(|(profile, hubs)| {
+                    Account::new(&profile, hubs, &usage, &config.cache, cipher.clone())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let registry = AccountRegistry::new(
+                accounts,
+                config.default_account.clone(),
+                service_account,
+                usage,
+                config.cache.clone(),
+                cipher,
+            )?;
+            let service = GoogleService::new(Arc::new(registry), &config.tool_groups);
 
             tracing::info!("Starting MCP server on stdio...");
             let server = service.serve(stdio()).await?;
+            session::set_peer(server.peer().clone());
             server.waiting().await?;
         }
     }
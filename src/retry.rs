@@ -0,0 +1,373 @@
+//! Retries for Google API calls.
+//!
+//! Every `.doit()` call goes through [`call`], which retries rate-limit
+//! (429, 403 `rateLimitExceeded`), server (5xx) and connection errors with
+//! jittered exponential backoff, honoring the server's `Retry-After`, until
+//! the attempt limit or the per-call deadline is reached. Each attempt first
+//! waits for the API's rate limiter and is counted in its usage stats.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use google_classroom1::common::{self, Delegate, Retry};
+use hyper::header::RETRY_AFTER;
+use hyper::StatusCode;
+use serde_json::Value;
+
+use crate::usage::ApiUsage;
+
+/// How hard to retry a failing call.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Give up once this much time has passed since the first attempt.
+    pub deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(16),
+            deadline: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `retry` (0-based): exponential, capped,
+    /// with "equal jitter" — a random delay between half and all of it.
+    fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let half = exp / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+/// A failed Google API call, with the HTTP status it failed with (if any).
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Option<StatusCode>,
+    pub source: common::Error,
+}
+
+impl ApiError {
+    /// Google's human-readable `error.message`, or the error itself.
+    pub fn message(&self) -> String {
+        match &self.source {
+            common::Error::BadRequest(body) => body["error"]["message"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| body.to_string()),
+            other => other.to_string(),
+        }
+    }
+
+    /// The canonical `error.status` of a Google JSON error body, e.g. `NOT_FOUND`.
+    pub fn google_status(&self) -> Option<&str> {
+        match &self.source {
+            common::Error::BadRequest(body) => body["error"]["status"].as_str(),
+            _ => None,
+        }
+    }
+
+    /// The `error.errors[].reason` values of a Google JSON error body.
+    pub fn reasons(&self) -> Vec<String> {
+        let common::Error::BadRequest(body) = &self.source else {
+            return Vec::new();
+        };
+        body["error"]["errors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e["reason"].as_str().map(String::from))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// What the server said about the last failed attempt.
+#[derive(Debug, Default)]
+struct Failure {
+    status: Option<StatusCode>,
+    retry_after: Option<Duration>,
+}
+
+/// Delegate passed to the call builder to capture the status and
+/// `Retry-After` header of a failed response, which the hubs otherwise
+/// discard when they turn a JSON error body into `Error::BadRequest`.
+#[derive(Debug, Clone, Default)]
+pub struct Probe(Arc<Mutex<Failure>>);
+
+impl Probe {
+    fn take(&self) -> Failure {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl Delegate for Probe {
+    fn http_failure(&mut self, response: &common::Response, _err: Option<&Value>) -> Retry {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| parse_retry_after(v, Utc::now()));
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Failure {
+            status: Some(response.status()),
+            retry_after,
+        };
+        // Retrying is up to `call`, outside the hub's own loop.
+        Retry::Abort
+    }
+}
+
+/// Run a Google API call, retrying transient failures with the default policy.
+///
+/// `f` builds and sends the request, attaching the given [`Probe`] with
+/// `.delegate(&mut probe)`:
+///
+/// ```ignore
+/// retry::call(&usage, "courses.list", |mut probe| async move {
+///     hub.courses().list().delegate(&mut probe).doit().await
+/// })
+/// ```
+pub async fn call<T, F, Fut>(api: &ApiUsage, op: &str, f: F) -> Result<T, ApiError>
+where
+    F: FnMut(Probe) -> Fut,
+    Fut: Future<Output = common::Result<T>>,
+{
+    call_with(&RetryPolicy::default(), api, op, f).await
+}
+
+/// Like [`call`], with an explicit policy.
+pub async fn call_with<T, F, Fut>(
+    policy: &RetryPolicy,
+    api: &ApiUsage,
+    op: &str,
+    mut f: F,
+) -> Result<T, ApiError>
+where
+    F: FnMut(Probe) -> Fut,
+    Fut: Future<Output = common::Result<T>>,
+{
+    let start = Instant::now();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        api.acquire().await;
+        let probe = Probe::default();
+        let remaining = policy.deadline.saturating_sub(start.elapsed());
+        let result = match tokio::time::timeout(remaining, f(probe.clone())).await {
+            Ok(result) => result,
+            Err(_) => Err(common::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("{op} timed out after {:?}", policy.deadline),
+            ))),
+        };
+        let err = match result {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        let failure = probe.take();
+        let status = failure.status.or_else(|| status_of(&err));
+        let err = ApiError {
+            status,
+            source: err,
+        };
+        if !is_retryable(&err) || attempt >= policy.max_attempts {
+            api.record_failure();
+            return Err(err);
+        }
+
+        let delay = policy
+            .backoff(attempt - 1)
+            .max(failure.retry_after.unwrap_or_default());
+        if start.elapsed() + delay >= policy.deadline {
+            tracing::warn!("{op} failed and the retry deadline is reached: {err}");
+            api.record_failure();
+            return Err(err);
+        }
+        api.record_retry(is_rate_limited(&err));
+        tracing::warn!(
+            "{op} failed (attempt {attempt}/{}), retrying in {delay:?}: {err}",
+            policy.max_attempts
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// HTTP status of a failed call, from the response or the `error.code` of a
+/// Google JSON error body.
+fn status_of(err: &common::Error) -> Option<StatusCode> {
+    match err {
+        common::Error::Failure(response) => Some(response.status()),
+        common::Error::BadRequest(body) => body["error"]["code"]
+            .as_u64()
+            .and_then(|code| u16::try_from(code).ok())
+            .and_then(|code| StatusCode::from_u16(code).ok()),
+        _ => None,
+    }
+}
+
+/// Reasons Google gives for 403s that are really rate limits.
+const RATE_LIMIT_REASONS: &[&str] = &["rateLimitExceeded", "userRateLimitExceeded"];
+
+/// Whether Google rejected the call for exceeding a rate limit or quota.
+fn is_rate_limited(err: &ApiError) -> bool {
+    match err.status {
+        Some(StatusCode::TOO_MANY_REQUESTS) => true,
+        Some(StatusCode::FORBIDDEN) => err
+            .reasons()
+            .iter()
+            .any(|r| RATE_LIMIT_REASONS.contains(&r.as_str())),
+        _ => false,
+    }
+}
+
+/// Whether retrying the call might succeed.
+fn is_retryable(err: &ApiError) -> bool {
+    match &err.source {
+        common::Error::HttpError(_) | common::Error::Io(_) => true,
+        _ => {
+            is_rate_limited(err)
+                || err.status.is_some_and(|status| {
+                    status == StatusCode::REQUEST_TIMEOUT || status.is_server_error()
+                })
+        }
+    }
+}
+
+/// Parse a `Retry-After` value: delay seconds or an HTTP date.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::config::RateLimit;
+
+    fn bad_request(code: u16, reason: &str) -> ApiError {
+        let source = common::Error::BadRequest(json!({
+            "error": { "code": code, "message": "x", "errors": [{ "reason": reason }] }
+        }));
+        ApiError {
+            status: status_of(&source),
+            source,
+        }
+    }
+
+    #[test]
+    fn classifies_by_status() {
+        assert!(is_retryable(&bad_request(429, "rateLimitExceeded")));
+        assert!(is_retryable(&bad_request(503, "backendError")));
+        assert!(is_retryable(&bad_request(403, "userRateLimitExceeded")));
+        assert!(!is_retryable(&bad_request(403, "insufficientPermissions")));
+        assert!(!is_retryable(&bad_request(404, "notFound")));
+        assert!(!is_retryable(&ApiError {
+            status: None,
+            source: common::Error::MissingToken("expired".into()),
+        }));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let policy = RetryPolicy::default();
+        for retry in 0..10 {
+            let exp = policy
+                .initial_backoff
+                .saturating_mul(2u32.saturating_pow(retry))
+                .min(policy.max_backoff);
+            let delay = policy.backoff(retry);
+            assert!(delay >= exp / 2 && delay <= exp, "{delay:?} vs {exp:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            ..RetryPolicy::default()
+        };
+        let api = ApiUsage::new(
+            "test",
+            RateLimit {
+                requests_per_second: 0.0,
+                burst: 1,
+            },
+        );
+        let mut calls = 0;
+        let result = call_with(&policy, &api, "test", |_probe| {
+            calls += 1;
+            let n = calls;
+            async move {
+                if n < 3 {
+                    Err(common::Error::BadRequest(
+                        json!({ "error": { "code": 503 } }),
+                    ))
+                } else {
+                    Ok(n)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<(), _> = call_with(&policy, &api, "test", |_probe| {
+            calls += 1;
+            async {
+                Err(common::Error::BadRequest(
+                    json!({ "error": { "code": 404 } }),
+                ))
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap_err().status, Some(StatusCode::NOT_FOUND));
+        assert_eq!(calls, 1);
+    }
+}
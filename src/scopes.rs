@@ -0,0 +1,136 @@
+//! Registry of OAuth scopes, grouped by the tools that need them.
+//!
+//! Each tool belongs to exactly one [`ToolGroup`]. Only the groups enabled in
+//! `config.json` are exposed and have their scopes requested at sign-in; more
+//! can be granted later with `auth --add-scope <group>`.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+/// A set of tools that share the same OAuth scopes.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToolGroup {
+    /// Read-only Classroom: courses, announcements, coursework, materials, topics
+    Classroom,
+    /// Read-only Drive: reading course materials
+    Drive,
+    /// Read-only Calendar: calendars and events
+    Calendar,
+    /// Creating, changing and deleting calendar events, and the due-date
+    /// calendar. Never enabled by default: it has to be listed in `config.json`.
+    CalendarWrite,
+}
+
+impl ToolGroup {
+    pub const ALL: &'static [ToolGroup] = &[
+        ToolGroup::Classroom,
+        ToolGroup::Drive,
+        ToolGroup::Calendar,
+        ToolGroup::CalendarWrite,
+    ];
+
+    /// Groups enabled when `config.json` doesn't list any: all the read-only ones.
+    pub const DEFAULT: &'static [ToolGroup] =
+        &[ToolGroup::Classroom, ToolGroup::Drive, ToolGroup::Calendar];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToolGroup::Classroom => "classroom",
+            ToolGroup::Drive => "drive",
+            ToolGroup::Calendar => "calendar",
+            ToolGroup::CalendarWrite => "calendar-write",
+        }
+    }
+
+    pub fn scopes(self) -> &'static [&'static str] {
+        match self {
+            ToolGroup::Classroom => &[
+                "https://www.googleapis.com/auth/classroom.courses.readonly",
+                "https://www.googleapis.com/auth/classroom.announcements.readonly",
+                "https://www.googleapis.com/auth/classroom.coursework.me.readonly",
+                "https://www.googleapis.com/auth/classroom.rosters.readonly",
+                "https://www.googleapis.com/auth/classroom.courseworkmaterials.readonly",
+                "https://www.googleapis.com/auth/classroom.topics.readonly",
+            ],
+            ToolGroup::Drive => &[DRIVE_READONLY],
+            ToolGroup::Calendar => &[CALENDAR_READONLY],
+            ToolGroup::CalendarWrite => &[CALENDAR_EVENTS, CALENDAR_APP_CREATED],
+        }
+    }
+
+    /// Whether every scope of this group is in `granted`.
+    pub fn is_granted(self, granted: &BTreeSet<String>) -> bool {
+        self.scopes().iter().all(|s| granted.contains(*s))
+    }
+}
+
+pub const DRIVE_READONLY: &str = "https://www.googleapis.com/auth/drive.readonly";
+pub const CALENDAR_READONLY: &str = "https://www.googleapis.com/auth/calendar.readonly";
+pub const CALENDAR_EVENTS: &str = "https://www.googleapis.com/auth/calendar.events";
+/// Creating secondary calendars, such as the one for Classroom due dates.
+pub const CALENDAR_APP_CREATED: &str = "https://www.googleapis.com/auth/calendar.app.created";
+
+/// Which group each MCP tool belongs to. Tools of disabled groups are hidden.
+pub const TOOL_GROUPS: &[(&str, ToolGroup)] = &[
+    ("courses", ToolGroup::Classroom),
+    ("course_details", ToolGroup::Classroom),
+    ("assignments", ToolGroup::Classroom),
+    ("course_materials", ToolGroup::Classroom),
+    ("course_topics", ToolGroup::Classroom),
+    ("course_export", ToolGroup::Classroom),
+    ("whats_new", ToolGroup::Classroom),
+    ("search_classroom", ToolGroup::Classroom),
+    ("semantic_search", ToolGroup::Classroom),
+    ("read_material", ToolGroup::Drive),
+    ("calendars", ToolGroup::Calendar),
+    ("calendar_events", ToolGroup::Calendar),
+    ("agenda", ToolGroup::Calendar),
+    ("find_free_time", ToolGroup::Calendar),
+    ("calendar_event_details", ToolGroup::Calendar),
+    ("calendar_create_event", ToolGroup::CalendarWrite),
+    ("calendar_update_event", ToolGroup::CalendarWrite),
+    ("calendar_delete_event", ToolGroup::CalendarWrite),
+    ("sync_due_dates_to_calendar", ToolGroup::CalendarWrite),
+];
+
+/// Union of the scopes needed by `groups`, sorted and de-duplicated.
+pub fn scopes_for(groups: &[ToolGroup]) -> Vec<&'static str> {
+    let set: BTreeSet<&'static str> = groups.iter().flat_map(|g| g.scopes()).copied().collect();
+    set.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_are_deduplicated() {
+        let scopes = scopes_for(&[ToolGroup::Drive, ToolGroup::Drive, ToolGroup::Calendar]);
+        assert_eq!(scopes, vec![CALENDAR_READONLY, DRIVE_READONLY]);
+    }
+
+    #[test]
+    fn group_granted_needs_every_scope() {
+        let mut granted: BTreeSet<String> = ToolGroup::Classroom.scopes()[..2]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(!ToolGroup::Classroom.is_granted(&granted));
+        granted.extend(ToolGroup::Classroom.scopes().iter().map(|s| s.to_string()));
+        assert!(ToolGroup::Classroom.is_granted(&granted));
+    }
+}
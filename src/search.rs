@@ -0,0 +1,449 @@
+//! Full-text search over the course content in the local store.
+//!
+//! The `search_classroom` tool searches announcements, coursework and
+//! materials (titles and descriptions) and the text of Drive files, using a
+//! [tantivy] index of the store's records. The index is kept in memory and
+//! rebuilt whenever the records have changed since it was built, so course
+//! text is never written to disk outside the (possibly encrypted) store.
+//! Whatever the tools or a `sync` have fetched is searchable, online or
+//! offline.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use tantivy::collector::TopDocs;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, TermQuery};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value as _, STORED, STRING,
+};
+use tantivy::snippet::{Snippet, SnippetGenerator};
+use tantivy::{doc, Index, IndexReader, TantivyDocument, Term};
+
+use crate::classroom::{announcement_title, drive_file_ids};
+use crate::error::AppError;
+use crate::store::{RecordKind, Store};
+
+/// Most results a search returns.
+pub const MAX_RESULTS: usize = 50;
+
+/// Longest snippet, in characters.
+const SNIPPET_CHARS: usize = 200;
+
+/// Course content searched, besides Drive files.
+const CONTENT_KINDS: &[RecordKind] = &[
+    RecordKind::Announcement,
+    RecordKind::CourseWork,
+    RecordKind::Material,
+];
+
+/// A searchable announcement, coursework item, material or Drive file.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub kind: RecordKind,
+    pub id: String,
+    /// The course of an item; empty for Drive files.
+    pub parent: String,
+    pub title: String,
+    pub body: String,
+    pub link: String,
+    /// For Drive files, the course, kind and ID of each item attaching them.
+    pub attached_to: Vec<(String, RecordKind, String)>,
+}
+
+impl Document {
+    /// The item's course, or every course a Drive file is attached in.
+    pub fn courses(&self) -> Vec<&str> {
+        if self.kind == RecordKind::DriveFile {
+            self.attached_to
+                .iter()
+                .map(|(course, ..)| course.as_str())
+                .collect()
+        } else {
+            vec![self.parent.as_str()]
+        }
+    }
+
+    /// The document's kind, ID, title and link, and its course or the items
+    /// attaching it, for tool results.
+    pub fn describe(&self, course_names: &HashMap<String, String>) -> Value {
+        let mut value = json!({
+            "kind": self.kind.as_str(),
+            "id": self.id,
+            "title": self.title,
+            "link": self.link,
+        });
+        if self.kind == RecordKind::DriveFile {
+            value["attachedTo"] = self
+                .attached_to
+                .iter()
+                .map(|(course, kind, id)| {
+                    json!({
+                        "courseId": course,
+                        "courseName": course_names.get(course),
+                        "kind": kind.as_str(),
+                        "id": id,
+                    })
+                })
+                .collect();
+        } else {
+            value["courseId"] = json!(self.parent);
+            value["courseName"] = json!(course_names.get(&self.parent));
+        }
+        value
+    }
+}
+
+/// Every announcement, coursework item, material and Drive file in the
+/// store, except those a `sync` found removed.
+pub fn documents(store: &Store) -> Result<Vec<Document>, AppError> {
+    let mut documents = Vec::new();
+    // Drive file ID -> the items attaching it.
+    let mut attachments: BTreeMap<String, BTreeSet<(String, RecordKind, String)>> = BTreeMap::new();
+    for kind in CONTENT_KINDS {
+        for record in store.records(*kind, None)? {
+            if record.removed_at.is_some() {
+                continue;
+            }
+            let item = &record.value;
+            let mut files = BTreeSet::new();
+            drive_file_ids(item, &mut files);
+            for file in files {
+                attachments.entry(file).or_default().insert((
+                    record.parent.clone(),
+                    *kind,
+                    record.id.clone(),
+                ));
+            }
+            let (title, body) = match kind {
+                RecordKind::Announcement => (announcement_title(item), &item["text"]),
+                _ => (
+                    item["title"].as_str().unwrap_or_default().to_string(),
+                    &item["description"],
+                ),
+            };
+            documents.push(Document {
+                kind: *kind,
+                body: body.as_str().unwrap_or_default().to_string(),
+                link: item["alternateLink"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                title,
+                id: record.id,
+                parent: record.parent,
+                attached_to: Vec::new(),
+            });
+        }
+    }
+    for record in store.records(RecordKind::DriveFile, None)? {
+        let metadata = &record.value["metadata"];
+        documents.push(Document {
+            kind: RecordKind::DriveFile,
+            title: metadata["name"].as_str().unwrap_or_default().to_string(),
+            body: record.value["content"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            link: metadata["webViewLink"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            attached_to: attachments
+                .remove(&record.id)
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            id: record.id,
+            parent: record.parent,
+        });
+    }
+    Ok(documents)
+}
+
+/// Course names by ID.
+pub fn course_names(store: &Store) -> Result<HashMap<String, String>, AppError> {
+    Ok(store
+        .records(RecordKind::Course, None)?
+        .into_iter()
+        .filter_map(|r| Some((r.id, r.value["name"].as_str()?.to_string())))
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    /// Position in [`Built::documents`].
+    position: Field,
+    /// The courses of [`Document::courses`].
+    course: Field,
+    title: Field,
+    body: Field,
+}
+
+impl Fields {
+    fn schema() -> (Schema, Fields) {
+        let mut builder = Schema::builder();
+        let text = TextOptions::default()
+            .set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer("en_stem")
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            )
+            .set_stored();
+        let fields = Fields {
+            position: builder.add_u64_field("position", STORED),
+            course: builder.add_text_field("course", STRING),
+            title: builder.add_text_field("title", text.clone()),
+            body: builder.add_text_field("body", text),
+        };
+        (builder.build(), fields)
+    }
+}
+
+struct Built {
+    /// [`Store::records_revision`] when the index was built.
+    revision: String,
+    documents: Vec<Document>,
+    course_names: HashMap<String, String>,
+    index: Index,
+    reader: IndexReader,
+}
+
+/// An account's search index, built from its store on first use.
+pub struct SearchIndex(Arc<Shared>);
+
+struct Shared {
+    store: Store,
+    fields: Fields,
+    schema: Schema,
+    built: Mutex<Option<Built>>,
+}
+
+impl std::fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchIndex").finish_non_exhaustive()
+    }
+}
+
+impl SearchIndex {
+    pub fn new(store: Store) -> Self {
+        let (schema, fields) = Fields::schema();
+        Self(Arc::new(Shared {
+            store,
+            fields,
+            schema,
+            built: Mutex::new(None),
+        }))
+    }
+
+    /// Search for `query`, optionally only in one course, best matches first.
+    pub async fn search(
+        &self,
+        query: &str,
+        course_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Value, AppError> {
+        if query.trim().is_empty() {
+            return Err(AppError::InvalidInput("the search query is empty".into()));
+        }
+        let shared = self.0.clone();
+        let query = query.to_string();
+        let course_id = course_id.map(String::from);
+        // Rebuilding the index after the store changed reads every record.
+        tokio::task::spawn_blocking(move || shared.search(&query, course_id.as_deref(), limit))
+            .await
+            .map_err(|e| AppError::Search(format!("search failed: {e}")))?
+    }
+}
+
+impl Shared {
+    fn search(
+        &self,
+        query: &str,
+        course_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Value, AppError> {
+        let revision = self.store.records_revision()?;
+        let mut guard = self.built.lock().unwrap_or_else(|e| e.into_inner());
+        let built = match &mut *guard {
+            Some(built) if built.revision == revision => built,
+            slot => slot.insert(self.build(revision)?),
+        };
+
+        let f = self.fields;
+        let mut parser = QueryParser::for_index(&built.index, vec![f.title, f.body]);
+        parser.set_field_boost(f.title, 2.0);
+        // Stray quotes or colons shouldn't make a search fail.
+        let (parsed, _errors) = parser.parse_query_lenient(query);
+        let query: Box<dyn Query> = match course_id {
+            Some(course) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, parsed),
+                (
+                    Occur::Must,
+                    Box::new(TermQuery::new(
+                        Term::from_field_text(f.course, course),
+                        IndexRecordOption::Basic,
+                    )),
+                ),
+            ])),
+            None => parsed,
+        };
+
+        let searcher = built.reader.searcher();
+        let top = searcher.search(&query, &TopDocs::with_limit(limit.clamp(1, MAX_RESULTS)))?;
+        let mut snippets = SnippetGenerator::create(&searcher, &*query, f.body)?;
+        snippets.set_max_num_chars(SNIPPET_CHARS);
+
+        let mut results = Vec::new();
+        for (score, address) in top {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            let Some(document) = doc
+                .get_first(f.position)
+                .and_then(|v| v.as_u64())
+                .and_then(|i| built.documents.get(i as usize))
+            else {
+                continue;
+            };
+            let mut result = document.describe(&built.course_names);
+            result["score"] = json!(score);
+            result["snippet"] = snippet(&snippets.snippet_from_doc(&doc), &document.body).into();
+            results.push(result);
+        }
+        Ok(json!({ "results": results }))
+    }
+
+    fn build(&self, revision: String) -> Result<Built, AppError> {
+        let started = std::time::Instant::now();
+        let f = self.fields;
+        let index = Index::create_in_ram(self.schema.clone());
+        let mut writer = index.writer_with_num_threads(1, 50_000_000)?;
+        let documents = documents(&self.store)?;
+        for (position, document) in documents.iter().enumerate() {
+            let mut doc = doc!(
+                f.position => position as u64,
+                f.title => document.title.as_str(),
+                f.body => document.body.as_str(),
+            );
+            for course in document.courses() {
+                doc.add_text(f.course, course);
+            }
+            writer.add_document(doc)?;
+        }
+        writer.commit()?;
+        tracing::info!(
+            "built the search index of {} documents in {:?}",
+            documents.len(),
+            started.elapsed()
+        );
+        Ok(Built {
+            revision,
+            course_names: course_names(&self.store)?,
+            documents,
+            reader: index.reader()?,
+            index,
+        })
+    }
+}
+
+/// The snippet's fragment with its matches in bold, or the start of `body`
+/// when only the title matched.
+fn snippet(snippet: &Snippet, body: &str) -> String {
+    let fragment = snippet.fragment();
+    if fragment.is_empty() {
+        let start: String = body.chars().take(SNIPPET_CHARS).collect();
+        return if start.len() < body.len() {
+            format!("{}…", start.trim_end())
+        } else {
+            start
+        };
+    }
+    let mut out = String::new();
+    let mut at = 0;
+    for range in snippet.highlighted() {
+        out.push_str(&fragment[at..range.start]);
+        out.push_str("**");
+        out.push_str(&fragment[range.clone()]);
+        out.push_str("**");
+        at = range.end;
+    }
+    out.push_str(&fragment[at..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_ranked_content_with_snippets() {
+        let store = Store::open_in_memory(None);
+        store
+            .save_records(
+                RecordKind::Course,
+                "",
+                &[json!({"id": "c1", "name": "Chemistry"})],
+            )
+            .unwrap();
+        store
+            .save_records(
+                RecordKind::Material,
+                "c1",
+                &[
+                    json!({"id": "m1", "title": "Lab safety quiz",
+                           "description": "Read the handout before the quiz.",
+                           "materials": [{"driveFile": {"driveFile": {"id": "f1"}}}]}),
+                    json!({"id": "m2", "title": "Week 2", "description": "Acids and bases"}),
+                ],
+            )
+            .unwrap();
+        store
+            .save_records(
+                RecordKind::Announcement,
+                "c2",
+                &[json!({"id": "a1", "text": "Pop quiz on Monday"})],
+            )
+            .unwrap();
+        store
+            .save_record(
+                RecordKind::DriveFile,
+                "",
+                "f1",
+                &json!({"metadata": {"name": "Handout"},
+                        "content": "Goggles are required. There will be a safety quiz."}),
+            )
+            .unwrap();
+        let index = SearchIndex::new(store.clone());
+
+        let found = index.search("safety quiz", None, 10).await.unwrap();
+        let results = found["results"].as_array().unwrap();
+        assert_eq!(results[0]["id"], "m1");
+        assert_eq!(results[0]["courseName"], "Chemistry");
+        assert!(results[0]["snippet"].as_str().unwrap().contains("**quiz**"));
+        let file = results.iter().find(|r| r["id"] == "f1").unwrap();
+        assert_eq!(file["attachedTo"][0]["id"], "m1");
+        assert!(results.iter().any(|r| r["id"] == "a1"));
+        // Words are stemmed.
+        let found = index.search("handouts", None, 10).await.unwrap();
+        assert_eq!(found["results"].as_array().unwrap().len(), 2);
+
+        let found = index.search("quiz", Some("c2"), 10).await.unwrap();
+        let ids: Vec<_> = found["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["id"].clone())
+            .collect();
+        assert_eq!(ids, ["a1"]);
+
+        // New records are picked up.
+        store
+            .save_records(
+                RecordKind::CourseWork,
+                "c1",
+                &[json!({"id": "w1", "title": "Titration report"})],
+            )
+            .unwrap();
+        let found = index.search("titration", None, 10).await.unwrap();
+        assert_eq!(found["results"][0]["id"], "w1");
+    }
+}
@@ -0,0 +1,401 @@
+//! Semantic search over course content, with embeddings computed locally
+//! (the `semantic-search` feature).
+//!
+//! The `semantic_search` tool finds passages by meaning rather than by
+//! keyword. The documents of [`search::documents`] are split into
+//! overlapping passages, each embedded with a small sentence-transformer
+//! model run on the CPU by [candle]. The model is downloaded from Hugging
+//! Face on first use (into `HF_HOME`, by default `~/.cache/huggingface`) and
+//! never sent any course text. Vectors are kept in the store and only
+//! recomputed for documents whose text changed; a query is embedded the
+//! same way and compared with every stored passage.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use serde_json::{json, Value};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::error::AppError;
+use crate::search::{self, Document, MAX_RESULTS};
+use crate::store::{fingerprint, EmbeddingRow, Store};
+
+/// The embedding model: small, fast on a CPU, 384 dimensions.
+const MODEL: &str = "sentence-transformers/all-MiniLM-L6-v2";
+
+/// Longest input the model is given, in tokens.
+const MAX_TOKENS: usize = 256;
+
+/// Passage length and overlap, in bytes: roughly what fits in [`MAX_TOKENS`].
+const PASSAGE_BYTES: usize = 1000;
+const OVERLAP_BYTES: usize = 200;
+
+/// Passages embedded per model call.
+const BATCH: usize = 16;
+
+fn error(context: &str, e: impl std::fmt::Display) -> AppError {
+    AppError::Search(format!("{context}: {e}"))
+}
+
+/// The loaded model.
+struct Embedder {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl Embedder {
+    /// Load the model, downloading it unless it's in the Hugging Face cache.
+    async fn load() -> Result<Self, AppError> {
+        let download = |e: hf_hub::api::tokio::ApiError| {
+            AppError::Search(format!(
+                "failed to download the embedding model {MODEL}: {e}"
+            ))
+        };
+        let api = hf_hub::api::tokio::ApiBuilder::from_env()
+            .with_progress(false)
+            .build()
+            .map_err(download)?;
+        let repo = api.model(MODEL.to_string());
+        let config = repo.get("config.json").await.map_err(download)?;
+        let tokenizer = repo.get("tokenizer.json").await.map_err(download)?;
+        let weights = repo.get("model.safetensors").await.map_err(download)?;
+
+        let config: Config = serde_json::from_slice(&std::fs::read(config)?)?;
+        let mut tokenizer =
+            Tokenizer::from_file(tokenizer).map_err(|e| error("bad tokenizer", e))?;
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_TOKENS,
+                ..TruncationParams::default()
+            }))
+            .map_err(|e| error("bad tokenizer", e))?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        let device = Device::Cpu;
+        let weights =
+            VarBuilder::from_buffered_safetensors(std::fs::read(weights)?, DTYPE, &device)
+                .map_err(|e| error("bad model weights", e))?;
+        let model = BertModel::load(weights, &config).map_err(|e| error("bad model", e))?;
+        Ok(Self {
+            model,
+            tokenizer,
+            device,
+        })
+    }
+
+    /// Unit-length embeddings of `texts`: the mean of the token vectors.
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AppError> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, true)
+            .map_err(|e| error("tokenizing failed", e))?;
+        let run = || -> candle_core::Result<Vec<Vec<f32>>> {
+            let tensor = |values: Vec<&[u32]>| -> candle_core::Result<Tensor> {
+                let rows = values
+                    .into_iter()
+                    .map(|v| Tensor::new(v, &self.device))
+                    .collect::<candle_core::Result<Vec<_>>>()?;
+                Tensor::stack(&rows, 0)
+            };
+            let ids = tensor(encodings.iter().map(|e| e.get_ids()).collect())?;
+            let mask = tensor(encodings.iter().map(|e| e.get_attention_mask()).collect())?;
+            let tokens = self.model.forward(&ids, &ids.zeros_like()?, Some(&mask))?;
+            let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+            let mean = tokens
+                .broadcast_mul(&mask)?
+                .sum(1)?
+                .broadcast_div(&mask.sum(1)?)?;
+            let norm = mean.sqr()?.sum_keepdim(1)?.sqrt()?;
+            mean.broadcast_div(&norm)?.to_vec2()
+        };
+        run().map_err(|e| error("embedding failed", e))
+    }
+}
+
+/// A passage of a document: a byte range of its body, and its embedding.
+#[derive(Debug, Clone, PartialEq)]
+struct Passage {
+    start: usize,
+    end: usize,
+    vector: Vec<f32>,
+}
+
+/// An account's passage vectors, kept up to date with its store.
+pub struct SemanticIndex {
+    store: Store,
+    embedder: OnceCell<Arc<Embedder>>,
+    /// Held while vectors are updated, so concurrent searches don't embed
+    /// the same documents twice.
+    updating: Mutex<()>,
+}
+
+impl std::fmt::Debug for SemanticIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SemanticIndex").finish_non_exhaustive()
+    }
+}
+
+impl SemanticIndex {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store,
+            embedder: OnceCell::new(),
+            updating: Mutex::new(()),
+        }
+    }
+
+    /// The passages closest in meaning to `query`, optionally only in one
+    /// course, best first.
+    pub async fn search(
+        &self,
+        query: &str,
+        course_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Value, AppError> {
+        if query.trim().is_empty() {
+            return Err(AppError::InvalidInput("the search query is empty".into()));
+        }
+        let embedder = self
+            .embedder
+            .get_or_try_init(|| async { Embedder::load().await.map(Arc::new) })
+            .await?
+            .clone();
+        let _updating = self.updating.lock().await;
+        let store = self.store.clone();
+        let query = query.to_string();
+        let course_id = course_id.map(String::from);
+        // Embedding is CPU-bound and can take a while for new documents.
+        tokio::task::spawn_blocking(move || {
+            search_blocking(&store, &embedder, &query, course_id.as_deref(), limit)
+        })
+        .await
+        .map_err(|e| AppError::Search(format!("semantic search failed: {e}")))?
+    }
+}
+
+fn search_blocking(
+    store: &Store,
+    embedder: &Embedder,
+    query: &str,
+    course_id: Option<&str>,
+    limit: usize,
+) -> Result<Value, AppError> {
+    let documents = search::documents(store)?;
+    let passages = update(store, embedder, &documents)?;
+    let query = embedder
+        .embed(vec![query.to_string()])?
+        .pop()
+        .unwrap_or_default();
+
+    let mut scored: Vec<(f32, &Document, &Passage)> = documents
+        .iter()
+        .zip(&passages)
+        .filter(|(document, _)| course_id.is_none_or(|c| document.courses().contains(&c)))
+        .flat_map(|(document, passages)| {
+            let query = &query;
+            passages.iter().map(move |passage| {
+                let score: f32 = passage.vector.iter().zip(query).map(|(a, b)| a * b).sum();
+                (score, document, passage)
+            })
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.truncate(limit.clamp(1, MAX_RESULTS));
+
+    let course_names = search::course_names(store)?;
+    let results: Vec<Value> = scored
+        .into_iter()
+        .map(|(score, document, passage)| {
+            let mut result = document.describe(&course_names);
+            result["score"] = json!(score);
+            result["offset"] = json!(passage.start);
+            result["length"] = json!(passage.end - passage.start);
+            result["passage"] = json!(&document.body[passage.start..passage.end]);
+            result
+        })
+        .collect();
+    Ok(json!({ "results": results }))
+}
+
+/// Each document's passages, embedding those whose text changed since their
+/// vectors were stored and dropping vectors of documents no longer there.
+fn update(
+    store: &Store,
+    embedder: &Embedder,
+    documents: &[Document],
+) -> Result<Vec<Vec<Passage>>, AppError> {
+    let mut stored: HashMap<(String, String, String), EmbeddingRow> = store
+        .embeddings()?
+        .into_iter()
+        .map(|row| ((row.kind.clone(), row.parent.clone(), row.id.clone()), row))
+        .collect();
+    let mut passages = Vec::with_capacity(documents.len());
+    let mut embedded = 0;
+    for document in documents {
+        let key = (
+            document.kind.as_str().to_string(),
+            document.parent.clone(),
+            document.id.clone(),
+        );
+        let fingerprint = fingerprint(&[MODEL, &document.title, &document.body]);
+        let row = stored.remove(&key);
+        if let Some(row) = row.filter(|row| row.fingerprint == fingerprint) {
+            if let Some(decoded) = decode(&row.data) {
+                passages.push(decoded);
+                continue;
+            }
+        }
+
+        let ranges = match chunks(&document.body) {
+            ranges if ranges.is_empty() && !document.title.is_empty() => vec![(0, 0)],
+            ranges => ranges,
+        };
+        let mut embedded_passages = Vec::with_capacity(ranges.len());
+        for batch in ranges.chunks(BATCH) {
+            let texts = batch
+                .iter()
+                .map(|&(start, end)| format!("{}\n{}", document.title, &document.body[start..end]))
+                .collect();
+            for (&(start, end), vector) in batch.iter().zip(embedder.embed(texts)?) {
+                embedded_passages.push(Passage { start, end, vector });
+            }
+        }
+        store.save_embeddings(&EmbeddingRow {
+            kind: key.0,
+            parent: key.1,
+            id: key.2,
+            fingerprint,
+            data: encode(&embedded_passages),
+        })?;
+        passages.push(embedded_passages);
+        embedded += 1;
+    }
+    for (kind, parent, id) in stored.into_keys() {
+        store.delete_embeddings(&kind, &parent, &id)?;
+    }
+    if embedded > 0 {
+        tracing::info!("embedded {embedded} new or changed documents");
+    }
+    Ok(passages)
+}
+
+/// Byte ranges of `text` about [`PASSAGE_BYTES`] long, each overlapping the
+/// previous one by about [`OVERLAP_BYTES`], split between words.
+fn chunks(text: &str) -> Vec<(usize, usize)> {
+    let floor = |mut i: usize| {
+        while !text.is_char_boundary(i) {
+            i -= 1;
+        }
+        i
+    };
+    let skip_space = |i: usize| {
+        text[i..]
+            .find(|c: char| !c.is_whitespace())
+            .map_or(text.len(), |n| i + n)
+    };
+    let mut ranges = Vec::new();
+    let mut start = skip_space(0);
+    while start < text.len() {
+        let mut end = floor((start + PASSAGE_BYTES).min(text.len()));
+        if end < text.len() {
+            if let Some(space) = text[start..end].rfind(char::is_whitespace) {
+                if space > PASSAGE_BYTES / 2 {
+                    end = start + space;
+                }
+            }
+        }
+        ranges.push((start, text[..end].trim_end().len()));
+        if end >= text.len() {
+            break;
+        }
+        let mut next = floor(end - OVERLAP_BYTES);
+        if let Some(space) = text[next..end].find(char::is_whitespace) {
+            next += space;
+        }
+        start = skip_space(next);
+    }
+    ranges
+}
+
+/// Passages as little-endian `u32` start and end offsets, a `u32`
+/// dimension count and the vector's `f32`s.
+fn encode(passages: &[Passage]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for passage in passages {
+        for n in [passage.start, passage.end, passage.vector.len()] {
+            data.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        for x in &passage.vector {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+    data
+}
+
+fn decode(data: &[u8]) -> Option<Vec<Passage>> {
+    let le = |b: &[u8]| [b[0], b[1], b[2], b[3]];
+    let mut rest = data;
+    let mut passages = Vec::new();
+    while !rest.is_empty() {
+        let (header, tail) = rest.split_at_checked(12)?;
+        let field = |i: usize| u32::from_le_bytes(le(&header[i * 4..])) as usize;
+        let (vector, tail) = tail.split_at_checked(field(2).checked_mul(4)?)?;
+        passages.push(Passage {
+            start: field(0),
+            end: field(1),
+            vector: vector
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(le(b)))
+                .collect(),
+        });
+        rest = tail;
+    }
+    Some(passages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passages_overlap_between_words() {
+        let text = "word ".repeat(500);
+        let ranges = chunks(&text);
+        assert!(ranges.len() > 2);
+        for pair in ranges.windows(2) {
+            let ((a_start, a_end), (b_start, _)) = (pair[0], pair[1]);
+            assert!(a_end - a_start <= PASSAGE_BYTES);
+            assert!(b_start > a_start && b_start < a_end);
+        }
+        for &(start, end) in &ranges {
+            assert!(text[start..end].starts_with("word"));
+            assert!(text[start..end].ends_with("word"));
+        }
+        assert_eq!(ranges.last().unwrap().1, text.trim_end().len());
+        assert_eq!(chunks("  short note "), [(2, 12)]);
+        assert!(chunks(" \n ").is_empty());
+    }
+
+    #[test]
+    fn vectors_round_trip() {
+        let passages = vec![
+            Passage {
+                start: 0,
+                end: 900,
+                vector: vec![0.5, -0.25, 1.0],
+            },
+            Passage {
+                start: 700,
+                end: 1200,
+                vector: vec![0.0, 0.125, -1.0],
+            },
+        ];
+        assert_eq!(decode(&encode(&passages)), Some(passages));
+        assert_eq!(decode(&[1, 0, 0]), None);
+        assert_ne!(fingerprint(&["ab", "c"]), fingerprint(&["a", "bc"]));
+    }
+}
@@ -0,0 +1,608 @@
+//! Token health tracking for long-running server sessions.
+//!
+//! Every account's hubs get their tokens through a [`ManagedAuth`], which
+//! remembers whether the last refresh worked. A background task refreshes the
+//! access token ahead of expiry; once the refresh token stops working the
+//! account is marked failed, tools fail fast with [`AppError::AuthExpired`] and
+//! the MCP client gets a log notification. Recovery is automatic: the task
+//! watches the profile's `tokens.json` and picks up new tokens as soon as
+//! `auth` is rerun.
+
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use google_classroom1::common::GetToken;
+use rmcp::model::{LoggingLevel, LoggingMessageNotificationParam};
+use rmcp::service::{Peer, RoleServer};
+use serde_json::{json, Value};
+use yup_oauth2::AccessToken;
+
+use crate::auth::{profile_auth, tokens_path, Authenticator};
+use crate::crypto::Cipher;
+use crate::error::AppError;
+use crate::scopes::ToolGroup;
+
+/// Refresh the access token this long before it expires.
+const REFRESH_AHEAD: Duration = Duration::from_secs(5 * 60);
+
+/// Bounds on the wait between background checks of a healthy account.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// How often a failed profile's `tokens.json` is checked for new tokens.
+const RECOVERY_POLL: Duration = Duration::from_secs(15);
+
+/// How often a failed account retries its current credentials, in case the
+/// failure was a network outage rather than a revoked token.
+const FAILED_RETRY: Duration = Duration::from_secs(5 * 60);
+
+static PEER: OnceLock<Peer<RoleServer>> = OnceLock::new();
+
+/// Minimum severity forwarded to the client, as set by `logging/setLevel`.
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(1);
+
+/// Register the connected MCP client so auth changes can be reported to it.
+pub fn set_peer(peer: Peer<RoleServer>) {
+    let _ = PEER.set(peer);
+}
+
+/// Only forward notifications at `level` or above to the client.
+pub fn set_log_level(level: LoggingLevel) {
+    MIN_LEVEL.store(severity(level), Ordering::Relaxed);
+}
+
+fn severity(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
+}
+
+/// Send an MCP log notification, if a client is connected and listening at `level`.
+fn notify(level: LoggingLevel, data: Value) {
+    let Some(peer) = PEER.get() else {
+        return;
+    };
+    if severity(level) < MIN_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let peer = peer.clone();
+    tokio::spawn(async move {
+        let param = LoggingMessageNotificationParam {
+            level,
+            logger: Some("auth".into()),
+            data,
+        };
+        if let Err(e) = peer.notify_logging_message(param).await {
+            tracing::debug!("failed to send log notification: {e}");
+        }
+    });
+}
+
+/// Where an account's tokens come from, which decides how it recovers.
+pub enum AuthSource {
+    /// A sign-in profile, reloaded from `tokens.json` after `auth` is rerun.
+    Profile {
+        tool_groups: Vec<ToolGroup>,
+        cipher: Option<Arc<Cipher>>,
+    },
+    /// A service account, which mints its own tokens and is simply retried.
+    ServiceAccount,
+}
+
+/// Whether an account can currently get access tokens.
+#[derive(Debug, Clone)]
+pub enum AuthHealth {
+    Healthy,
+    Failed {
+        reason: String,
+        since: DateTime<Utc>,
+    },
+}
+
+struct State {
+    account: String,
+    source: AuthSource,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    authenticator: Authenticator,
+    /// Scopes refreshed in the background: the enabled groups the account granted.
+    scopes: Vec<String>,
+    granted: BTreeSet<String>,
+    health: AuthHealth,
+    /// When the current access token expires, if known.
+    expires_at: Option<DateTime<Utc>>,
+    /// `tokens.json` modification time when the account last failed.
+    failed_mtime: Option<SystemTime>,
+    last_attempt: Instant,
+}
+
+/// A token provider for the API hubs that tracks auth health and keeps the
+/// access token fresh in the background.
+#[derive(Clone)]
+pub struct ManagedAuth {
+    state: Arc<State>,
+}
+
+impl std::fmt::Debug for ManagedAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ManagedAuth")
+            .field("account", &self.state.account)
+            .field("health", &self.lock().health)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ManagedAuth {
+    /// Validate `authenticator` once, then start refreshing it in the
+    /// background. A failed validation doesn't prevent startup: the account
+    /// is marked failed and recovers on its own once new tokens are available.
+    pub async fn start(
+        account: &str,
+        source: AuthSource,
+        authenticator: Authenticator,
+        scopes: Vec<String>,
+        granted: BTreeSet<String>,
+    ) -> Self {
+        let auth = Self {
+            state: Arc::new(State {
+                account: account.to_string(),
+                source,
+                inner: Mutex::new(Inner {
+                    authenticator,
+                    scopes,
+                    granted,
+                    health: AuthHealth::Healthy,
+                    expires_at: None,
+                    failed_mtime: None,
+                    last_attempt: Instant::now(),
+                }),
+            }),
+        };
+        auth.refresh(false).await;
+        tokio::spawn(monitor(Arc::downgrade(&auth.state)));
+        auth
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.state.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn health(&self) -> AuthHealth {
+        self.lock().health.clone()
+    }
+
+    /// Scopes the account has granted.
+    pub fn granted_scopes(&self) -> BTreeSet<String> {
+        self.lock().granted.clone()
+    }
+
+    /// Fail with [`AppError::AuthExpired`] if the account can't get tokens.
+    pub fn check(&self) -> Result<(), AppError> {
+        match self.health() {
+            AuthHealth::Healthy => Ok(()),
+            AuthHealth::Failed { reason, .. } => Err(self.expired_error(reason)),
+        }
+    }
+
+    /// Health as reported by the `accounts` tool.
+    pub fn describe(&self) -> Value {
+        let inner = self.lock();
+        match &inner.health {
+            AuthHealth::Healthy => json!({
+                "status": "ok",
+                "accessTokenExpiresAt": inner.expires_at,
+            }),
+            AuthHealth::Failed { reason, since } => json!({
+                "status": "failed",
+                "reason": reason,
+                "since": since,
+                "remedy": self.remedy(),
+            }),
+        }
+    }
+
+    fn remedy(&self) -> String {
+        match &self.state.source {
+            AuthSource::Profile { .. } => format!(
+                "run `personal-google-mcp auth --profile {}`",
+                self.state.account
+            ),
+            AuthSource::ServiceAccount => {
+                "check the service account key and its domain-wide delegation".into()
+            }
+        }
+    }
+
+    /// The error for a tool needing `group`, which the account hasn't granted,
+    /// with the fix for this kind of account.
+    pub fn missing_scope(&self, group: ToolGroup) -> AppError {
+        let account = &self.state.account;
+        let name = group.name();
+        let remedy = match &self.state.source {
+            AuthSource::Profile { .. } => {
+                format!("run `personal-google-mcp auth --profile {account} --add-scope {name}`")
+            }
+            AuthSource::ServiceAccount => format!(
+                "add '{name}' to toolGroups in config.json and have a Workspace admin delegate \
+                 its scopes ({}) to the service account in the Admin console",
+                group.scopes().join(", ")
+            ),
+        };
+        AppError::MissingScope {
+            account: account.clone(),
+            group: name,
+            remedy,
+        }
+    }
+
+    fn expired_error(&self, reason: String) -> AppError {
+        AppError::AuthExpired {
+            account: self.state.account.clone(),
+            reason,
+            remedy: self.remedy(),
+        }
+    }
+
+    fn tokens_mtime(&self) -> Option<SystemTime> {
+        match self.state.source {
+            AuthSource::Profile { .. } => tokens_path(&self.state.account)
+                .ok()
+                .and_then(|p: PathBuf| std::fs::metadata(p).ok())
+                .and_then(|m| m.modified().ok()),
+            AuthSource::ServiceAccount => None,
+        }
+    }
+
+    /// Get a token for the background scopes, forcing a refresh if `force`,
+    /// and update the account's health from the outcome.
+    async fn refresh(&self, force: bool) {
+        let (authenticator, scopes) = {
+            let mut inner = self.lock();
+            inner.last_attempt = Instant::now();
+            (inner.authenticator.clone(), inner.scopes.clone())
+        };
+        let result = if force {
+            authenticator.force_refreshed_token(&scopes).await
+        } else {
+            authenticator.token(&scopes).await
+        };
+        match result {
+            Ok(token) => self.mark_healthy(&token),
+            Err(e) => match classify(&e) {
+                TokenFailure::Credentials => self.mark_failed(e.to_string()),
+                TokenFailure::Scope => tracing::warn!(
+                    "account '{}' can't get tokens for every enabled tool group: {e}",
+                    self.state.account
+                ),
+                TokenFailure::Transient => tracing::warn!(
+                    "token refresh for account '{}' failed, will retry: {e}",
+                    self.state.account
+                ),
+            },
+        }
+    }
+
+    fn mark_healthy(&self, token: &AccessToken) {
+        let recovered = {
+            let mut inner = self.lock();
+            inner.expires_at = token
+                .expiration_time()
+                .and_then(|t| DateTime::from_timestamp(t.unix_timestamp(), 0));
+            let recovered = matches!(inner.health, AuthHealth::Failed { .. });
+            inner.health = AuthHealth::Healthy;
+            inner.failed_mtime = None;
+            recovered
+        };
+        if recovered {
+            let account = &self.state.account;
+            tracing::info!("account '{account}' is authenticated again");
+            notify(
+                LoggingLevel::Info,
+                json!({
+                    "event": "auth_recovered",
+                    "account": account,
+                    "message": format!("account '{account}' is authenticated again"),
+                }),
+            );
+        }
+    }
+
+    fn mark_failed(&self, reason: String) {
+        let mtime = self.tokens_mtime();
+        {
+            let mut inner = self.lock();
+            if matches!(inner.health, AuthHealth::Failed { .. }) {
+                return;
+            }
+            inner.health = AuthHealth::Failed {
+                reason: reason.clone(),
+                since: Utc::now(),
+            };
+            inner.expires_at = None;
+            inner.failed_mtime = mtime;
+        }
+        let err = self.expired_error(reason.clone());
+        tracing::error!("{err}");
+        notify(
+            LoggingLevel::Error,
+            json!({
+                "event": "auth_failed",
+                "account": self.state.account,
+                "reason": reason,
+                "remedy": self.remedy(),
+                "message": err.to_string(),
+            }),
+        );
+    }
+
+    /// Try to get a failed account working again: reload a profile whose
+    /// `tokens.json` changed, or periodically retry the current credentials.
+    async fn try_recover(&self) {
+        if let AuthSource::Profile {
+            tool_groups,
+            cipher,
+        } = &self.state.source
+        {
+            let mtime = self.tokens_mtime();
+            let changed = mtime.is_some() && mtime != self.lock().failed_mtime;
+            if changed {
+                let account = &self.state.account;
+                tracing::info!("tokens for account '{account}' changed on disk, reloading");
+                match profile_auth(account, tool_groups, cipher.clone()).await {
+                    Ok((authenticator, scopes, granted)) => {
+                        let mut inner = self.lock();
+                        inner.authenticator = authenticator;
+                        inner.scopes = scopes;
+                        inner.granted = granted;
+                        inner.failed_mtime = mtime;
+                    }
+                    Err(e) => {
+                        tracing::warn!("could not reload tokens for account '{account}': {e}");
+                        self.lock().failed_mtime = mtime;
+                        return;
+                    }
+                }
+                self.refresh(false).await;
+                return;
+            }
+        }
+        if self.lock().last_attempt.elapsed() >= FAILED_RETRY {
+            self.refresh(true).await;
+        }
+    }
+
+    /// Whether the access token is due for a refresh (or its expiry is unknown).
+    fn expires_soon(&self) -> bool {
+        self.lock().expires_at.is_none_or(|t| {
+            t - Utc::now()
+                <= chrono::Duration::from_std(REFRESH_AHEAD + MIN_CHECK_INTERVAL)
+                    .unwrap_or_default()
+        })
+    }
+
+    /// How long the background task waits before its next check.
+    fn next_check(&self) -> Duration {
+        let inner = self.lock();
+        match (&inner.health, inner.expires_at) {
+            (AuthHealth::Failed { .. }, _) => RECOVERY_POLL,
+            (AuthHealth::Healthy, Some(expires_at)) => (expires_at - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+                .saturating_sub(REFRESH_AHEAD)
+                .clamp(MIN_CHECK_INTERVAL, MAX_CHECK_INTERVAL),
+            (AuthHealth::Healthy, None) => MIN_CHECK_INTERVAL,
+        }
+    }
+}
+
+/// Refresh tokens ahead of expiry and recover failed accounts until the
+/// account is dropped.
+async fn monitor(state: Weak<State>) {
+    loop {
+        let delay = match state.upgrade() {
+            Some(state) => ManagedAuth { state }.next_check(),
+            None => return,
+        };
+        tokio::time::sleep(delay).await;
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let auth = ManagedAuth { state };
+        match auth.health() {
+            AuthHealth::Healthy => auth.refresh(auth.expires_soon()).await,
+            AuthHealth::Failed { .. } => auth.try_recover().await,
+        }
+    }
+}
+
+/// What a failure to get a token says about the account.
+#[derive(Debug, PartialEq)]
+enum TokenFailure {
+    /// The credentials no longer work: the account is marked failed.
+    /// Refresh failures of installed-app profiles surface as `UserError` from
+    /// the non-interactive flow delegate, which only runs once the refresh
+    /// token was rejected, as scopes that were never granted are turned away
+    /// before asking (see [`ungranted_group`]).
+    Credentials,
+    /// Google won't issue a token for these scopes, as when a service
+    /// account's domain-wide delegation doesn't cover them.
+    Scope,
+    /// A network or server hiccup.
+    Transient,
+}
+
+fn classify(err: &yup_oauth2::Error) -> TokenFailure {
+    use yup_oauth2::error::AuthErrorCode;
+
+    match err {
+        yup_oauth2::Error::AuthError(e)
+            if matches!(
+                e.error,
+                AuthErrorCode::UnauthorizedClient
+                    | AuthErrorCode::InvalidScope
+                    | AuthErrorCode::AccessDenied
+            ) =>
+        {
+            TokenFailure::Scope
+        }
+        yup_oauth2::Error::AuthError(_)
+        | yup_oauth2::Error::UserError(_)
+        | yup_oauth2::Error::MissingAccessToken => TokenFailure::Credentials,
+        _ => TokenFailure::Transient,
+    }
+}
+
+/// The tool group of the first of `scopes` the account hasn't granted, or
+/// `Err` with the scope if no group asks for it.
+fn ungranted_group<'a>(
+    scopes: &[&'a str],
+    granted: &BTreeSet<String>,
+) -> Option<Result<ToolGroup, &'a str>> {
+    let scope = *scopes.iter().find(|s| !granted.contains(**s))?;
+    Some(
+        ToolGroup::ALL
+            .iter()
+            .copied()
+            .find(|group| group.scopes().contains(&scope))
+            .ok_or(scope),
+    )
+}
+
+/// The tool group asking for any of `scopes`.
+fn group_of(scopes: &[&str]) -> Option<ToolGroup> {
+    ToolGroup::ALL
+        .iter()
+        .copied()
+        .find(|group| scopes.iter().any(|s| group.scopes().contains(s)))
+}
+
+impl GetToken for ManagedAuth {
+    fn get_token<'a>(
+        &'a self,
+        scopes: &'a [&str],
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<Option<String>, Box<dyn std::error::Error + Send + Sync>>>
+                + Send
+                + 'a,
+        >,
+    > {
+        Box::pin(async move {
+            self.check()?;
+            let (authenticator, granted) = {
+                let inner = self.lock();
+                (inner.authenticator.clone(), inner.granted.clone())
+            };
+            // Asking for a scope that was never granted would run the sign-in
+            // flow, which can't run here and would look like lost credentials.
+            match ungranted_group(scopes, &granted) {
+                Some(Ok(group)) => return Err(self.missing_scope(group).into()),
+                Some(Err(scope)) => {
+                    return Err(AppError::OAuth2(format!("scope {scope} isn't granted")).into())
+                }
+                None => {}
+            }
+            match authenticator.token(scopes).await {
+                Ok(token) => Ok(token.token().map(str::to_owned)),
+                Err(e) => match (classify(&e), group_of(scopes)) {
+                    (TokenFailure::Credentials, _) => {
+                        self.mark_failed(e.to_string());
+                        Err(e.into())
+                    }
+                    (TokenFailure::Scope, Some(group)) => Err(self.missing_scope(group).into()),
+                    _ => Err(e.into()),
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scopes::{CALENDAR_EVENTS, CALENDAR_READONLY};
+    use yup_oauth2::error::AuthErrorCode;
+
+    #[test]
+    fn classifies_token_errors() {
+        let auth_error = |error| {
+            yup_oauth2::Error::AuthError(yup_oauth2::error::AuthError {
+                error,
+                error_description: None,
+                error_uri: None,
+            })
+        };
+        assert_eq!(
+            classify(&yup_oauth2::Error::UserError(
+                "interactive auth not available in server mode".into()
+            )),
+            TokenFailure::Credentials
+        );
+        assert_eq!(
+            classify(&auth_error(AuthErrorCode::InvalidGrant)),
+            TokenFailure::Credentials
+        );
+        assert_eq!(
+            classify(&auth_error(AuthErrorCode::UnauthorizedClient)),
+            TokenFailure::Scope
+        );
+        assert_eq!(
+            classify(&yup_oauth2::Error::LowLevelError(std::io::Error::other(
+                "connection reset"
+            ))),
+            TokenFailure::Transient
+        );
+    }
+
+    #[tokio::test]
+    async fn ungranted_scopes_fail_without_touching_health() {
+        let authenticator = yup_oauth2::InstalledFlowAuthenticator::builder(
+            yup_oauth2::ApplicationSecret::default(),
+            yup_oauth2::InstalledFlowReturnMethod::Interactive,
+        )
+        .build()
+        .await
+        .unwrap();
+        let auth = ManagedAuth {
+            state: Arc::new(State {
+                account: "school".into(),
+                source: AuthSource::Profile {
+                    tool_groups: ToolGroup::DEFAULT.to_vec(),
+                    cipher: None,
+                },
+                inner: Mutex::new(Inner {
+                    authenticator,
+                    scopes: vec![CALENDAR_EVENTS.into()],
+                    granted: [CALENDAR_EVENTS.to_string()].into(),
+                    health: AuthHealth::Healthy,
+                    expires_at: None,
+                    failed_mtime: None,
+                    last_attempt: Instant::now(),
+                }),
+            }),
+        };
+
+        let err = auth.get_token(&[CALENDAR_READONLY]).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AppError>(),
+            Some(AppError::MissingScope { group: "calendar", remedy, .. })
+                if remedy.contains("--profile school --add-scope calendar")
+        ));
+        assert!(auth.check().is_ok());
+    }
+}
@@ -0,0 +1,1168 @@
+//! Per-account SQLite store holding the disk cache and a local snapshot of
+//! Google data.
+//!
+//! These tables live in `store.db` in the account's directory:
+//!
+//! - `cache_entries` — the disk tier of [`CacheLayer`](crate::cache::CacheLayer):
+//!   one tool response per cache key, with fetch time, ETag and last use.
+//! - `records` — every course, coursework item, submission, material, topic,
+//!   announcement, calendar event and Drive file the clients have fetched,
+//!   keyed by kind, parent (course or calendar ID) and ID, with Google's
+//!   update time and when it was fetched. Later features query this snapshot.
+//!   Objects that a `sync` no longer finds at Google are kept, marked removed.
+//! - `synced_records` — a fingerprint of each object as the last `sync` saw
+//!   it. Tool fetches overwrite `records` between runs, so `sync` reports
+//!   its changes against this instead.
+//! - `sync_runs` / `sync_progress` — `sync` runs and the courses each has
+//!   finished, so an interrupted run resumes where it stopped.
+//! - `checkpoints` — per course, what the `whats_new` tool last reported, so
+//!   the next call returns only what changed since.
+//! - `embeddings` — passage vectors of course content for the
+//!   `semantic_search` tool (with the `semantic-search` feature).
+//!
+//! Payloads (`data` columns) are JSON, sealed with the at-rest cipher when
+//! encryption is enabled; keys and timestamps are always plaintext so they
+//! can be queried.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::auth::{account_dir, DEFAULT_PROFILE};
+use crate::config::config_dir;
+use crate::crypto::{self, Cipher};
+use crate::error::AppError;
+
+const SCHEMA_VERSION: i64 = 6;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS cache_entries (
+    key TEXT PRIMARY KEY,
+    fetched_at TEXT NOT NULL,
+    etag TEXT,
+    last_used TEXT NOT NULL,
+    data BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS records (
+    kind TEXT NOT NULL,
+    parent TEXT NOT NULL,
+    id TEXT NOT NULL,
+    updated_at TEXT,
+    fetched_at TEXT NOT NULL,
+    removed_at TEXT,
+    data BLOB NOT NULL,
+    PRIMARY KEY (kind, parent, id)
+);
+CREATE INDEX IF NOT EXISTS records_by_fetch ON records (kind, fetched_at);
+CREATE TABLE IF NOT EXISTS synced_records (
+    kind TEXT NOT NULL,
+    parent TEXT NOT NULL,
+    id TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    PRIMARY KEY (kind, parent, id)
+);
+CREATE TABLE IF NOT EXISTS sync_runs (
+    id INTEGER PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    finished_at TEXT
+);
+CREATE TABLE IF NOT EXISTS sync_progress (
+    run_id INTEGER NOT NULL REFERENCES sync_runs (id),
+    unit TEXT NOT NULL,
+    summary TEXT NOT NULL,
+    PRIMARY KEY (run_id, unit)
+);
+CREATE TABLE IF NOT EXISTS checkpoints (
+    course_id TEXT PRIMARY KEY,
+    checked_at TEXT NOT NULL,
+    data BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS embeddings (
+    kind TEXT NOT NULL,
+    parent TEXT NOT NULL,
+    id TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (kind, parent, id)
+);
+";
+
+/// The store file of an account (profile or delegated user).
+pub fn store_path(account: &str) -> Result<PathBuf, AppError> {
+    Ok(account_dir(account)?.join("store.db"))
+}
+
+/// Accounts that have a store, for `run --offline`: the default profile
+/// first, then named profiles, then delegated users, each alphabetically.
+pub fn stored_accounts() -> Result<Vec<String>, AppError> {
+    let mut accounts = Vec::new();
+    if store_path(DEFAULT_PROFILE)?.exists() {
+        accounts.push(DEFAULT_PROFILE.to_string());
+    }
+    for dir in ["profiles", "delegated"] {
+        let entries = match std::fs::read_dir(config_dir()?.join(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter(|entry| entry.path().join("store.db").exists())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name != DEFAULT_PROFILE && account_dir(name).is_ok())
+            .collect();
+        names.sort();
+        accounts.append(&mut names);
+    }
+    Ok(accounts)
+}
+
+/// Kinds of Google objects kept in the `records` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RecordKind {
+    Course,
+    CourseWork,
+    Submission,
+    Material,
+    Topic,
+    Announcement,
+    Event,
+    DriveFile,
+}
+
+impl RecordKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Course => "course",
+            Self::CourseWork => "coursework",
+            Self::Submission => "submission",
+            Self::Material => "material",
+            Self::Topic => "topic",
+            Self::Announcement => "announcement",
+            Self::Event => "event",
+            Self::DriveFile => "drive_file",
+        }
+    }
+}
+
+/// A row of the `records` table.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// The course or calendar the object belongs to, or empty.
+    pub parent: String,
+    pub id: String,
+    pub updated_at: Option<String>,
+    /// When a `sync` stopped finding the object at Google.
+    pub removed_at: Option<DateTime<Utc>>,
+    pub value: Value,
+}
+
+/// How a complete listing from Google differed from the stored snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changes {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+impl Changes {
+    pub fn add(&mut self, other: Changes) {
+        self.added += other.added;
+        self.updated += other.updated;
+        self.removed += other.removed;
+    }
+}
+
+/// A `sync` run, new or resumed after an interruption.
+#[derive(Debug)]
+pub struct SyncRun {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    /// Units already finished, with the summaries they were completed with.
+    pub done: BTreeMap<String, Value>,
+}
+
+/// A row of the `checkpoints` table.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub checked_at: DateTime<Utc>,
+    pub snapshot: Value,
+}
+
+/// A row of the `embeddings` table, with `data` unsealed.
+#[cfg(feature = "semantic-search")]
+#[derive(Debug, Clone)]
+pub struct EmbeddingRow {
+    pub kind: String,
+    pub parent: String,
+    pub id: String,
+    /// Identifies the text and model the vectors were computed from.
+    pub fingerprint: String,
+    pub data: Vec<u8>,
+}
+
+/// A row of the `cache_entries` table.
+#[derive(Debug, Clone)]
+pub struct CachedRow {
+    pub fetched_at: DateTime<Utc>,
+    pub etag: Option<String>,
+    pub value: Value,
+}
+
+/// Size and timestamps of a cache entry, for listing and eviction.
+#[derive(Debug, Clone)]
+pub struct CacheRowInfo {
+    pub key: String,
+    pub fetched_at: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// An account's store. Clones share one connection.
+#[derive(Clone)]
+pub struct Store {
+    conn: Arc<Mutex<Connection>>,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl std::fmt::Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Store").finish_non_exhaustive()
+    }
+}
+
+impl Store {
+    /// Open (creating if needed) an account's store, importing any JSON
+    /// files left in the old `cache/` directory next to it.
+    pub fn open(path: &Path, cipher: Option<Arc<Cipher>>) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        crypto::restrict_permissions(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        let store = Self::init(conn, cipher)?;
+        if let Some(dir) = path.parent() {
+            store.import_legacy_cache(&dir.join("cache"));
+        }
+        Ok(store)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory(cipher: Option<Arc<Cipher>>) -> Self {
+        Self::init(Connection::open_in_memory().unwrap(), cipher).unwrap()
+    }
+
+    fn init(conn: Connection, cipher: Option<Arc<Cipher>>) -> Result<Self, AppError> {
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        conn.execute_batch(SCHEMA)?;
+        if version == 1 {
+            conn.execute_batch("ALTER TABLE records ADD COLUMN removed_at TEXT")?;
+        }
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        let store = Self {
+            conn: Arc::new(Mutex::new(conn)),
+            cipher,
+        };
+        if (1..6).contains(&version) {
+            // Stores from before `synced_records` take the snapshot as the
+            // baseline, as `sync` used to.
+            store.seed_synced_records()?;
+        }
+        Ok(store)
+    }
+
+    fn seed_synced_records(&self) -> Result<(), AppError> {
+        let rows = {
+            let conn = self.conn();
+            let mut stmt = conn.prepare("SELECT kind, parent, id, data FROM records")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                ))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for (kind, parent, id, data) in rows {
+            tx.execute(
+                "INSERT OR REPLACE INTO synced_records (kind, parent, id, fingerprint)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![kind, parent, id, record_fingerprint(&self.unseal(data)?)],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn seal(&self, value: &Value) -> Result<Vec<u8>, AppError> {
+        crypto::seal(self.cipher.as_deref(), &serde_json::to_vec(value)?)
+    }
+
+    fn unseal(&self, data: Vec<u8>) -> Result<Value, AppError> {
+        let data = crypto::open(self.cipher.as_deref(), data)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// Read a cache entry and mark it as recently used.
+    pub fn cache_get(&self, key: &str) -> Result<Option<CachedRow>, AppError> {
+        let row = self.cache_peek(key)?;
+        if row.is_some() {
+            self.conn().execute(
+                "UPDATE cache_entries SET last_used = ?1 WHERE key = ?2",
+                params![timestamp(Utc::now()), key],
+            )?;
+        }
+        Ok(row)
+    }
+
+    /// Read a cache entry without marking it as used.
+    pub fn cache_peek(&self, key: &str) -> Result<Option<CachedRow>, AppError> {
+        let row = self
+            .conn()
+            .query_row(
+                "SELECT fetched_at, etag, data FROM cache_entries WHERE key = ?1",
+                [key],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((fetched_at, etag, data)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(CachedRow {
+            fetched_at: parse_timestamp(&fetched_at),
+            etag,
+            value: self.unseal(data)?,
+        }))
+    }
+
+    /// Store a cache entry, then evict the least recently used entries
+    /// (other than this one) while the table holds more than `max_bytes`.
+    pub fn cache_put(&self, key: &str, row: &CachedRow, max_bytes: u64) -> Result<(), AppError> {
+        let data = self.seal(&row.value)?;
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO cache_entries (key, fetched_at, etag, last_used, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                key,
+                timestamp(row.fetched_at),
+                row.etag,
+                timestamp(Utc::now()),
+                data
+            ],
+        )?;
+        let mut total: u64 = tx.query_row(
+            "SELECT COALESCE(SUM(LENGTH(data)), 0) FROM cache_entries",
+            [],
+            |row| row.get(0),
+        )?;
+        if total > max_bytes {
+            let victims = {
+                let mut stmt = tx.prepare(
+                    "SELECT key, LENGTH(data) FROM cache_entries WHERE key != ?1
+                     ORDER BY last_used",
+                )?;
+                let rows = stmt.query_map([key], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect::<Result<Vec<(String, u64)>, _>>()?
+            };
+            for (victim, size) in victims {
+                if total <= max_bytes {
+                    break;
+                }
+                tx.execute("DELETE FROM cache_entries WHERE key = ?1", [&victim])?;
+                tracing::debug!("evicted cache entry {victim}");
+                total -= size;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Every cache entry, ordered by key.
+    pub fn cache_entries(&self) -> Result<Vec<CacheRowInfo>, AppError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT key, fetched_at, last_used, LENGTH(data) FROM cache_entries ORDER BY key",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(CacheRowInfo {
+                key: row.get(0)?,
+                fetched_at: parse_timestamp(&row.get::<_, String>(1)?),
+                last_used: parse_timestamp(&row.get::<_, String>(2)?),
+                size: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Delete a cache entry, returning whether it existed.
+    pub fn cache_delete(&self, key: &str) -> Result<bool, AppError> {
+        Ok(self
+            .conn()
+            .execute("DELETE FROM cache_entries WHERE key = ?1", [key])?
+            > 0)
+    }
+
+    /// Insert or update objects of one kind under `parent`, using each
+    /// item's `id` field. Items without an ID are skipped.
+    pub fn save_records(
+        &self,
+        kind: RecordKind,
+        parent: &str,
+        items: &[Value],
+    ) -> Result<(), AppError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        self.insert_records(&tx, kind, parent, items)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_records(
+        &self,
+        tx: &Transaction,
+        kind: RecordKind,
+        parent: &str,
+        items: &[Value],
+    ) -> Result<(), AppError> {
+        let items: Vec<_> = items
+            .iter()
+            .filter_map(|item| Some((item["id"].as_str()?, item)))
+            .collect();
+        let now = timestamp(Utc::now());
+        let mut stmt = tx.prepare(
+            "INSERT OR REPLACE INTO records (kind, parent, id, updated_at, fetched_at, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for (id, item) in items {
+            stmt.execute(params![
+                kind.as_str(),
+                parent,
+                id,
+                updated_at(item),
+                now,
+                self.seal(item)?
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Insert or update a single object whose ID isn't an `id` field.
+    pub fn save_record(
+        &self,
+        kind: RecordKind,
+        parent: &str,
+        id: &str,
+        item: &Value,
+    ) -> Result<(), AppError> {
+        let data = self.seal(item)?;
+        self.conn().execute(
+            "INSERT OR REPLACE INTO records (kind, parent, id, updated_at, fetched_at, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                kind.as_str(),
+                parent,
+                id,
+                updated_at(item),
+                timestamp(Utc::now()),
+                data
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// [`save_records`](Self::save_records) on a blocking thread, for the
+    /// API clients persisting what they fetch. Failures are logged: the
+    /// snapshot must never fail a tool call.
+    pub async fn persist_records(&self, kind: RecordKind, parent: &str, items: Vec<Value>) {
+        let (store, owned_parent) = (self.clone(), parent.to_string());
+        let result =
+            tokio::task::spawn_blocking(move || store.save_records(kind, &owned_parent, &items))
+                .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("failed to save {} records: {e}", kind.as_str()),
+            Err(e) => tracing::warn!("saving {} records did not finish: {e}", kind.as_str()),
+        }
+    }
+
+    /// Like [`persist_records`](Self::persist_records), for one object
+    /// stored under `id`.
+    pub async fn persist_record(&self, kind: RecordKind, parent: &str, id: &str, item: Value) {
+        let store = self.clone();
+        let (owned_parent, owned_id) = (parent.to_string(), id.to_string());
+        let result = tokio::task::spawn_blocking(move || {
+            store.save_record(kind, &owned_parent, &owned_id, &item)
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("failed to save {} record {id}: {e}", kind.as_str()),
+            Err(e) => tracing::warn!("saving {} record {id} did not finish: {e}", kind.as_str()),
+        }
+    }
+
+    /// Objects of one kind, optionally only those under `parent`.
+    pub fn records(&self, kind: RecordKind, parent: Option<&str>) -> Result<Vec<Record>, AppError> {
+        self.query_records(
+            "kind = ?1 AND (?2 IS NULL OR parent = ?2)",
+            params![kind.as_str(), parent],
+        )
+    }
+
+    /// One object, if it has been stored.
+    pub fn record(
+        &self,
+        kind: RecordKind,
+        parent: &str,
+        id: &str,
+    ) -> Result<Option<Record>, AppError> {
+        let mut records = self.query_records(
+            "kind = ?1 AND parent = ?2 AND id = ?3",
+            params![kind.as_str(), parent, id],
+        )?;
+        Ok(records.pop())
+    }
+
+    fn query_records(
+        &self,
+        filter: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<Record>, AppError> {
+        let rows = {
+            let conn = self.conn();
+            let mut stmt = conn.prepare(&format!(
+                "SELECT parent, id, updated_at, removed_at, data FROM records
+                 WHERE {filter} ORDER BY parent, id"
+            ))?;
+            let rows = stmt.query_map(params, |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Vec<u8>>(4)?,
+                ))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        rows.into_iter()
+            .map(|(parent, id, updated_at, removed_at, data)| {
+                Ok(Record {
+                    parent,
+                    id,
+                    updated_at,
+                    removed_at: removed_at.as_deref().map(parse_timestamp),
+                    value: self.unseal(data)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Bring the objects of one kind under `parent` in line with a complete
+    /// listing from Google. Everything listed is saved; objects no longer
+    /// listed are marked removed but kept, so the snapshot still holds them
+    /// after access to a course is lost.
+    ///
+    /// Changes are counted against what the previous `sync` saw, not against
+    /// `records`, which tool calls refresh in between.
+    pub fn sync_records(
+        &self,
+        kind: RecordKind,
+        parent: &str,
+        items: &[Value],
+    ) -> Result<Changes, AppError> {
+        let stored: HashMap<String, Record> = self
+            .records(kind, Some(parent))?
+            .into_iter()
+            .map(|record| (record.id.clone(), record))
+            .collect();
+        let synced = self.synced_fingerprints(kind, parent)?;
+        let mut changes = Changes::default();
+        let mut listed = HashMap::new();
+        for item in items {
+            let Some(id) = item["id"].as_str() else {
+                continue;
+            };
+            let fingerprint = record_fingerprint(item);
+            let reappeared = stored.get(id).is_some_and(|old| old.removed_at.is_some());
+            match synced.get(id) {
+                None => changes.added += 1,
+                Some(old) if *old != fingerprint || reappeared => changes.updated += 1,
+                Some(_) => {}
+            }
+            listed.insert(id, fingerprint);
+        }
+        // The upserts and removals land together, so a failure part-way
+        // can't leave records marked removed without the new listing saved.
+        let now = timestamp(Utc::now());
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        self.insert_records(&tx, kind, parent, items)?;
+        for (id, fingerprint) in &listed {
+            insert_synced(&tx, kind, parent, id, fingerprint)?;
+        }
+        for record in stored.values() {
+            if record.removed_at.is_none() && !listed.contains_key(record.id.as_str()) {
+                tx.execute(
+                    "UPDATE records SET removed_at = ?1 WHERE kind = ?2 AND parent = ?3 AND id = ?4",
+                    params![now, kind.as_str(), parent, record.id],
+                )?;
+                // Only objects a `sync` reported can be reported removed.
+                if synced.contains_key(&record.id) {
+                    changes.removed += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(changes)
+    }
+
+    /// Fingerprint of an object as the last `sync` saw it.
+    pub fn synced_fingerprint(
+        &self,
+        kind: RecordKind,
+        parent: &str,
+        id: &str,
+    ) -> Result<Option<String>, AppError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT fingerprint FROM synced_records WHERE kind = ?1 AND parent = ?2 AND id = ?3",
+                params![kind.as_str(), parent, id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Fingerprints of the objects of one kind under `parent` as the last
+    /// `sync` saw them, by ID.
+    fn synced_fingerprints(
+        &self,
+        kind: RecordKind,
+        parent: &str,
+    ) -> Result<HashMap<String, String>, AppError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, fingerprint FROM synced_records WHERE kind = ?1 AND parent = ?2",
+        )?;
+        let rows = stmt.query_map(params![kind.as_str(), parent], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Record an object as `sync` saw it, for objects synced one at a time
+    /// (like Drive files) rather than through [`Self::sync_records`].
+    pub fn mark_synced(
+        &self,
+        kind: RecordKind,
+        parent: &str,
+        id: &str,
+        item: &Value,
+    ) -> Result<(), AppError> {
+        insert_synced(&self.conn(), kind, parent, id, &record_fingerprint(item))
+    }
+
+    /// Changes whenever a record is saved or removed, so data derived from
+    /// the records (like the search index) knows when to rebuild.
+    pub fn records_revision(&self) -> Result<String, AppError> {
+        let (count, fetched_at, removed_at): (i64, Option<String>, Option<String>) =
+            self.conn().query_row(
+                "SELECT COUNT(*), MAX(fetched_at), MAX(removed_at) FROM records",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+        Ok(format!(
+            "{count}/{}/{}",
+            fetched_at.unwrap_or_default(),
+            removed_at.unwrap_or_default()
+        ))
+    }
+
+    /// Start a `sync` run, or resume the last one if it didn't finish.
+    pub fn begin_sync(&self) -> Result<SyncRun, AppError> {
+        let conn = self.conn();
+        let unfinished = conn
+            .query_row(
+                "SELECT id, started_at FROM sync_runs WHERE finished_at IS NULL
+                 ORDER BY id DESC LIMIT 1",
+                [],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+        let Some((id, started_at)) = unfinished else {
+            let now = Utc::now();
+            conn.execute(
+                "INSERT INTO sync_runs (started_at) VALUES (?1)",
+                [timestamp(now)],
+            )?;
+            return Ok(SyncRun {
+                id: conn.last_insert_rowid(),
+                started_at: now,
+                done: BTreeMap::new(),
+            });
+        };
+        let mut stmt = conn.prepare("SELECT unit, summary FROM sync_progress WHERE run_id = ?1")?;
+        let done = stmt
+            .query_map([id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .map(|row| {
+                let (unit, summary) = row?;
+                Ok((unit, serde_json::from_str(&summary)?))
+            })
+            .collect::<Result<_, AppError>>()?;
+        Ok(SyncRun {
+            id,
+            started_at: parse_timestamp(&started_at),
+            done,
+        })
+    }
+
+    /// Record that a unit of a `sync` run (e.g. one course) is finished.
+    pub fn complete_sync_unit(
+        &self,
+        run: i64,
+        unit: &str,
+        summary: &Value,
+    ) -> Result<(), AppError> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO sync_progress (run_id, unit, summary) VALUES (?1, ?2, ?3)",
+            params![run, unit, summary.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a `sync` run as finished.
+    pub fn finish_sync(&self, run: i64) -> Result<(), AppError> {
+        self.conn().execute(
+            "UPDATE sync_runs SET finished_at = ?1 WHERE id = ?2",
+            params![timestamp(Utc::now()), run],
+        )?;
+        Ok(())
+    }
+
+    /// When the last finished `sync` run started, if there was one.
+    pub fn last_sync(&self) -> Result<Option<DateTime<Utc>>, AppError> {
+        let started_at: Option<String> = self
+            .conn()
+            .query_row(
+                "SELECT started_at FROM sync_runs WHERE finished_at IS NOT NULL
+                 ORDER BY id DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(started_at.as_deref().map(parse_timestamp))
+    }
+
+    /// The last `whats_new` checkpoint of a course.
+    pub fn checkpoint(&self, course_id: &str) -> Result<Option<Checkpoint>, AppError> {
+        let row = self
+            .conn()
+            .query_row(
+                "SELECT checked_at, data FROM checkpoints WHERE course_id = ?1",
+                [course_id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )
+            .optional()?;
+        row.map(|(checked_at, data)| {
+            Ok(Checkpoint {
+                checked_at: parse_timestamp(&checked_at),
+                snapshot: self.unseal(data)?,
+            })
+        })
+        .transpose()
+    }
+
+    /// Replace a course's `whats_new` checkpoint.
+    pub fn save_checkpoint(
+        &self,
+        course_id: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), AppError> {
+        let data = self.seal(&checkpoint.snapshot)?;
+        self.conn().execute(
+            "INSERT OR REPLACE INTO checkpoints (course_id, checked_at, data) VALUES (?1, ?2, ?3)",
+            params![course_id, timestamp(checkpoint.checked_at), data],
+        )?;
+        Ok(())
+    }
+
+    /// A value the tools keep between runs, such as the ID of a calendar
+    /// they created.
+    pub fn setting(&self, key: &str) -> Result<Option<String>, AppError> {
+        Ok(self
+            .conn()
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<(), AppError> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Every object's stored passage vectors.
+    #[cfg(feature = "semantic-search")]
+    pub fn embeddings(&self) -> Result<Vec<EmbeddingRow>, AppError> {
+        let rows = {
+            let conn = self.conn();
+            let mut stmt =
+                conn.prepare("SELECT kind, parent, id, fingerprint, data FROM embeddings")?;
+            let rows = stmt.query_map([], |row| {
+                Ok(EmbeddingRow {
+                    kind: row.get(0)?,
+                    parent: row.get(1)?,
+                    id: row.get(2)?,
+                    fingerprint: row.get(3)?,
+                    data: row.get(4)?,
+                })
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        rows.into_iter()
+            .map(|row| {
+                Ok(EmbeddingRow {
+                    data: crypto::open(self.cipher.as_deref(), row.data)?,
+                    ..row
+                })
+            })
+            .collect()
+    }
+
+    /// Replace an object's passage vectors.
+    #[cfg(feature = "semantic-search")]
+    pub fn save_embeddings(&self, row: &EmbeddingRow) -> Result<(), AppError> {
+        let data = crypto::seal(self.cipher.as_deref(), &row.data)?;
+        self.conn().execute(
+            "INSERT OR REPLACE INTO embeddings (kind, parent, id, fingerprint, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![row.kind, row.parent, row.id, row.fingerprint, data],
+        )?;
+        Ok(())
+    }
+
+    /// Drop an object's passage vectors.
+    #[cfg(feature = "semantic-search")]
+    pub fn delete_embeddings(&self, kind: &str, parent: &str, id: &str) -> Result<(), AppError> {
+        self.conn().execute(
+            "DELETE FROM embeddings WHERE kind = ?1 AND parent = ?2 AND id = ?3",
+            params![kind, parent, id],
+        )?;
+        Ok(())
+    }
+
+    /// Move the JSON files of the pre-SQLite disk cache into
+    /// `cache_entries`, deleting each file once imported.
+    fn import_legacy_cache(&self, dir: &Path) {
+        let Ok(files) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for file in files.flatten() {
+            let path = file.path();
+            let Some(key) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_suffix(".json"))
+            else {
+                continue;
+            };
+            match self.import_legacy_file(key, &path) {
+                Ok(()) => {
+                    tracing::info!("imported legacy cache file {}", path.display());
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        tracing::warn!("failed to remove {}: {e}", path.display());
+                    }
+                }
+                Err(e) => tracing::warn!("cannot import {}: {e}", path.display()),
+            }
+        }
+        // Only succeeds once every file was imported.
+        let _ = std::fs::remove_dir(dir);
+    }
+
+    /// Files hold either `{fetchedAt, etag, value}` or, before entries carried
+    /// metadata, the bare value (whose fetch time is the file's mtime).
+    fn import_legacy_file(&self, key: &str, path: &Path) -> Result<(), AppError> {
+        let data = crypto::open(self.cipher.as_deref(), std::fs::read(path)?)?;
+        let value: Value = serde_json::from_slice(&data)?;
+        let row = match (value["fetchedAt"].as_str(), value.get("value")) {
+            (Some(fetched_at), Some(inner)) => CachedRow {
+                fetched_at: parse_timestamp(fetched_at),
+                etag: value["etag"].as_str().map(String::from),
+                value: inner.clone(),
+            },
+            _ => CachedRow {
+                fetched_at: std::fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH)
+                    .into(),
+                etag: None,
+                value,
+            },
+        };
+        self.cache_put(key, &row, u64::MAX)
+    }
+}
+
+/// Google's last-modified time of an object, where it has one.
+fn updated_at(item: &Value) -> Option<&str> {
+    item["updateTime"]
+        .as_str()
+        .or_else(|| item["updated"].as_str())
+        .or_else(|| item["modifiedTime"].as_str())
+        .or_else(|| item["metadata"]["modifiedTime"].as_str())
+}
+
+fn insert_synced(
+    conn: &Connection,
+    kind: RecordKind,
+    parent: &str,
+    id: &str,
+    fingerprint: &str,
+) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO synced_records (kind, parent, id, fingerprint)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![kind.as_str(), parent, id, fingerprint],
+    )?;
+    Ok(())
+}
+
+/// Fingerprint of a stored object, for [`Store::sync_records`].
+pub fn record_fingerprint(item: &Value) -> String {
+    fingerprint(&[&item.to_string()])
+}
+
+/// Stable hash of `parts`, to tell whether a document or record has changed.
+pub fn fingerprint(parts: &[&str]) -> String {
+    // FNV-1a, with a separator after each part.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.bytes().chain([0xff]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+/// Fixed-width UTC timestamps, so they sort correctly as text.
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse_timestamp(text: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn cache_entries_are_evicted_least_recently_used_first() {
+        let store = Store::open_in_memory(None);
+        let row = |n: usize| CachedRow {
+            fetched_at: Utc::now(),
+            etag: Some(format!("\"v{n}\"")),
+            value: json!("x".repeat(100)),
+        };
+        store.cache_put("topics_1", &row(1), 250).unwrap();
+        store.cache_put("materials_1", &row(2), 250).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        store.cache_get("topics_1").unwrap().unwrap();
+        store.cache_put("materials_2", &row(3), 250).unwrap();
+
+        let keys: Vec<_> = store
+            .cache_entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        assert_eq!(keys, ["materials_2", "topics_1"]);
+        let entry = store.cache_peek("materials_2").unwrap().unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"v3\""));
+        assert!(store.cache_delete("topics_1").unwrap());
+        assert!(!store.cache_delete("topics_1").unwrap());
+    }
+
+    #[test]
+    fn records_are_upserted_and_encrypted() {
+        let cipher = Arc::new(Cipher::new(b"test passphrase").unwrap());
+        let store = Store::open_in_memory(Some(cipher));
+        let items = [
+            json!({"id": "1", "title": "Essay", "updateTime": "2026-09-01T10:00:00Z"}),
+            json!({"title": "no id"}),
+        ];
+        store
+            .save_records(RecordKind::CourseWork, "42", &items)
+            .unwrap();
+        store
+            .save_records(
+                RecordKind::CourseWork,
+                "42",
+                &[json!({"id": "1", "title": "Essay v2"})],
+            )
+            .unwrap();
+        store
+            .save_records(RecordKind::CourseWork, "7", &[json!({"id": "9"})])
+            .unwrap();
+
+        let count =
+            |sql: &str| -> i64 { store.conn().query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM records WHERE parent = '42'"), 1);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM records WHERE kind = 'coursework'"),
+            2
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM records WHERE updated_at IS NOT NULL"),
+            0
+        );
+
+        let raw: Vec<u8> = store
+            .conn()
+            .query_row("SELECT data FROM records WHERE parent = '42'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(crypto::is_encrypted(&raw));
+        assert_eq!(store.unseal(raw).unwrap()["title"], "Essay v2");
+    }
+
+    #[test]
+    fn syncs_track_changes_and_resume() {
+        let store = Store::open_in_memory(None);
+        let topics = [
+            json!({"id": "1", "name": "Week 1"}),
+            json!({"id": "2", "name": "Week 2"}),
+        ];
+        let changes = store
+            .sync_records(RecordKind::Topic, "42", &topics)
+            .unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                added: 2,
+                updated: 0,
+                removed: 0
+            }
+        );
+
+        let topics = [json!({"id": "1", "name": "Week 1 (revised)"})];
+        let changes = store
+            .sync_records(RecordKind::Topic, "42", &topics)
+            .unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                added: 0,
+                updated: 1,
+                removed: 1
+            }
+        );
+        let removed = store.record(RecordKind::Topic, "42", "2").unwrap().unwrap();
+        assert!(removed.removed_at.is_some());
+        assert_eq!(removed.value["name"], "Week 2");
+
+        let run = store.begin_sync().unwrap();
+        store
+            .complete_sync_unit(run.id, "courses", &json!({}))
+            .unwrap();
+        let resumed = store.begin_sync().unwrap();
+        assert_eq!(resumed.id, run.id);
+        assert!(resumed.done.contains_key("courses"));
+        assert_eq!(store.last_sync().unwrap(), None);
+        store.finish_sync(run.id).unwrap();
+        assert!(store.last_sync().unwrap().is_some());
+        assert_ne!(store.begin_sync().unwrap().id, run.id);
+    }
+
+    #[test]
+    fn syncs_count_changes_seen_by_tools_in_between() {
+        let store = Store::open_in_memory(None);
+        let topics = [json!({"id": "1", "name": "Week 1"})];
+        store
+            .sync_records(RecordKind::Topic, "42", &topics)
+            .unwrap();
+
+        // A tool call fetches the revised topic and a new one before the next sync.
+        let topics = [
+            json!({"id": "1", "name": "Week 1 (revised)"}),
+            json!({"id": "2", "name": "Week 2"}),
+        ];
+        store
+            .save_records(RecordKind::Topic, "42", &topics)
+            .unwrap();
+        let changes = store
+            .sync_records(RecordKind::Topic, "42", &topics)
+            .unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                added: 1,
+                updated: 1,
+                removed: 0
+            }
+        );
+
+        let file = json!({"id": "f", "content": "v2"});
+        store
+            .save_record(RecordKind::DriveFile, "", "f", &file)
+            .unwrap();
+        assert_eq!(
+            store
+                .synced_fingerprint(RecordKind::DriveFile, "", "f")
+                .unwrap(),
+            None
+        );
+        store
+            .mark_synced(RecordKind::DriveFile, "", "f", &file)
+            .unwrap();
+        assert_eq!(
+            store
+                .synced_fingerprint(RecordKind::DriveFile, "", "f")
+                .unwrap(),
+            Some(record_fingerprint(&file))
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! The `sync` subcommand: mirror an account's Classroom data, and the text of
+//! the Drive files it references, into the local store.
+//!
+//! A run lists every course, then syncs each course in turn: its topics,
+//! coursework, materials, announcements and submissions, and every Drive
+//! file attached to them. Each finished course is recorded in the store, so
+//! a run that is interrupted, or fails on some courses, resumes with the
+//! remaining ones the next time `sync` runs. The report counts what was
+//! added, updated and removed since the previous finished run.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::accounts::Account;
+use crate::classroom::drive_file_ids;
+use crate::error::AppError;
+use crate::scopes::ToolGroup;
+use crate::store::{record_fingerprint, Changes, RecordKind};
+
+/// The sync unit for the course list itself; courses are `course:<id>`.
+const COURSES_UNIT: &str = "courses";
+
+/// What a run, or one unit of it, changed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Summary {
+    /// Changes by record kind.
+    changes: BTreeMap<String, Changes>,
+    /// Drive files unchanged since the last sync.
+    unchanged_files: usize,
+    /// Courses and files that could not be synced, with the reason.
+    failures: Vec<String>,
+}
+
+impl Summary {
+    fn count(&mut self, kind: RecordKind, changes: Changes) {
+        self.changes
+            .entry(kind.as_str().to_string())
+            .or_default()
+            .add(changes);
+    }
+
+    fn merge(&mut self, other: Summary) {
+        for (kind, changes) in other.changes {
+            self.changes.entry(kind).or_default().add(changes);
+        }
+        self.unchanged_files += other.unchanged_files;
+        self.failures.extend(other.failures);
+    }
+
+    fn print(&self, previous: Option<DateTime<Utc>>) {
+        match previous {
+            Some(at) => println!("\nChanges since the last sync ({}):", at.to_rfc3339()),
+            None => println!("\nFirst sync:"),
+        }
+        println!(
+            "\n{:<14} {:>7} {:>8} {:>8}",
+            "KIND", "ADDED", "UPDATED", "REMOVED"
+        );
+        for (kind, changes) in &self.changes {
+            println!(
+                "{:<14} {:>7} {:>8} {:>8}",
+                kind, changes.added, changes.updated, changes.removed
+            );
+        }
+        if self.unchanged_files > 0 {
+            println!(
+                "\n{} Drive files were unchanged since the last sync.",
+                self.unchanged_files
+            );
+        }
+        if !self.failures.is_empty() {
+            println!("\nNot synced:");
+            for failure in &self.failures {
+                println!("  - {failure}");
+            }
+        }
+    }
+}
+
+/// Sync an account into its local store and print what changed.
+pub async fn sync(account: &Account) -> Result<(), AppError> {
+    account.require(ToolGroup::Classroom)?;
+    let with_files = match account.require(ToolGroup::Drive) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Skipping Drive files: {e}");
+            false
+        }
+    };
+    let store = &account.store;
+    let previous = store.last_sync()?;
+    let mut run = store.begin_sync()?;
+    if !run.done.is_empty() {
+        println!(
+            "Resuming the sync started {} ({} of its steps already done).",
+            run.started_at.to_rfc3339(),
+            run.done.len()
+        );
+    }
+
+    let courses = account.classroom.all_courses().await?;
+    if !run.done.contains_key(COURSES_UNIT) {
+        let mut summary = Summary::default();
+        summary.count(
+            RecordKind::Course,
+            store.sync_records(RecordKind::Course, "", &courses)?,
+        );
+        complete(account, &mut run.done, run.id, COURSES_UNIT, &summary)?;
+    }
+
+    let mut pending = 0;
+    for course in &courses {
+        let Some(course_id) = course["id"].as_str() else {
+            continue;
+        };
+        let unit = format!("course:{course_id}");
+        if run.done.contains_key(&unit) {
+            continue;
+        }
+        let name = course["name"].as_str().unwrap_or(course_id);
+        println!("Syncing {name}...");
+        let summary = match sync_course(account, course_id, with_files).await {
+            Ok(summary) => summary,
+            // Retrying won't help: record the failure and move on.
+            Err(e @ (AppError::PermissionDenied(_) | AppError::NotFound(_))) => Summary {
+                failures: vec![format!("course {name}: {e}")],
+                ..Summary::default()
+            },
+            Err(e) => {
+                eprintln!("  failed, will retry on the next sync: {e}");
+                pending += 1;
+                continue;
+            }
+        };
+        complete(account, &mut run.done, run.id, &unit, &summary)?;
+    }
+
+    let mut total = Summary::default();
+    for summary in run.done.into_values() {
+        total.merge(serde_json::from_value(summary)?);
+    }
+    total.print(previous);
+    if pending > 0 {
+        return Err(AppError::Transient(format!(
+            "{pending} courses could not be synced; run `sync` again to retry them"
+        )));
+    }
+    store.finish_sync(run.id)?;
+    Ok(())
+}
+
+/// Record a finished unit in the store and in the run's progress.
+fn complete(
+    account: &Account,
+    done: &mut BTreeMap<String, Value>,
+    run: i64,
+    unit: &str,
+    summary: &Summary,
+) -> Result<(), AppError> {
+    let summary = serde_json::to_value(summary)?;
+    account.store.complete_sync_unit(run, unit, &summary)?;
+    done.insert(unit.to_string(), summary);
+    Ok(())
+}
+
+/// Sync one course and the Drive files attached to its content.
+async fn sync_course(
+    account: &Account,
+    course_id: &str,
+    with_files: bool,
+) -> Result<Summary, AppError> {
+    let classroom = &account.classroom;
+    let listings = [
+        (RecordKind::Topic, classroom.all_topics(course_id).await?),
+        (
+            RecordKind::CourseWork,
+            classroom.all_course_work(course_id).await?,
+        ),
+        (
+            RecordKind::Material,
+            classroom.all_materials(course_id).await?,
+        ),
+        (
+            RecordKind::Announcement,
+            classroom.all_announcements(course_id).await?,
+        ),
+        (
+            RecordKind::Submission,
+            classroom.all_submissions(course_id).await?,
+        ),
+    ];
+
+    let mut summary = Summary::default();
+    let mut files = BTreeSet::new();
+    for (kind, items) in &listings {
+        summary.count(*kind, account.store.sync_records(*kind, course_id, items)?);
+        for item in items {
+            drive_file_ids(item, &mut files);
+        }
+    }
+    if with_files {
+        for file_id in files {
+            sync_file(account, &file_id, &mut summary).await;
+        }
+    }
+    Ok(summary)
+}
+
+/// Fetch a Drive file's text unless the stored copy is current, and count
+/// it against the copy the last `sync` saw.
+async fn sync_file(account: &Account, file_id: &str, summary: &mut Summary) {
+    let store = &account.store;
+    let result = async {
+        let mut current = None;
+        if let Some(stored) = store.record(RecordKind::DriveFile, "", file_id)? {
+            let modified = account.drive.modified_time(file_id).await?;
+            if modified.is_some() && stored.updated_at == modified {
+                current = Some(stored.value);
+            }
+        }
+        let value = match current {
+            Some(value) => value,
+            // Also saves the file's record.
+            None => account.drive.read_material(file_id, true).await?,
+        };
+        let synced = store.synced_fingerprint(RecordKind::DriveFile, "", file_id)?;
+        let changes = match synced {
+            None => Changes {
+                added: 1,
+                ..Changes::default()
+            },
+            Some(old) if old != record_fingerprint(&value) => Changes {
+                updated: 1,
+                ..Changes::default()
+            },
+            Some(_) => return Ok(None),
+        };
+        store.mark_synced(RecordKind::DriveFile, "", file_id, &value)?;
+        Ok::<_, AppError>(Some(changes))
+    }
+    .await;
+    match result {
+        Ok(Some(changes)) => summary.count(RecordKind::DriveFile, changes),
+        Ok(None) => summary.unchanged_files += 1,
+        Err(e) => summary.failures.push(format!("Drive file {file_id}: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries_merge() {
+        let mut total = Summary::default();
+        let mut course = Summary::default();
+        course.count(
+            RecordKind::Topic,
+            Changes {
+                added: 2,
+                updated: 0,
+                removed: 1,
+            },
+        );
+        course.failures.push("Drive file x: not found".into());
+        total.merge(serde_json::from_value(serde_json::to_value(&course).unwrap()).unwrap());
+        total.merge(course);
+        assert_eq!(
+            total.changes["topic"],
+            Changes {
+                added: 4,
+                updated: 0,
+                removed: 2
+            }
+        );
+        assert_eq!(total.failures.len(), 2);
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use yup_oauth2::storage::{TokenInfo, TokenStorage, TokenStorageError};
+
+use crate::crypto::{self, Cipher};
+use crate::error::AppError;
+
+/// One entry of `tokens.json`: a token and the scopes it was granted for.
+///
+/// Uses the same layout as yup-oauth2's built-in disk storage, so token files
+/// written by older versions load unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredToken {
+    pub scopes: Vec<String>,
+    pub token: TokenInfo,
+}
+
+/// Read and (if needed) decrypt a token file.
+pub fn read_stored_tokens(
+    path: &Path,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<StoredToken>, AppError> {
+    let data = crypto::open(cipher, std::fs::read(path)?)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Token storage backed by `tokens.json`, encrypted when a [`Cipher`] is configured.
+///
+/// Replaces yup-oauth2's `persist_tokens_to_disk` so every write goes through
+/// [`crypto::write_private`] (mode 0600) and, optionally, encryption.
+pub struct FileTokenStorage {
+    path: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    tokens: Mutex<Vec<StoredToken>>,
+}
+
+impl FileTokenStorage {
+    pub fn open(path: PathBuf, cipher: Option<Arc<Cipher>>) -> Result<Self, AppError> {
+        let tokens = match read_stored_tokens(&path, cipher.as_deref()) {
+            Ok(tokens) => {
+                crypto::restrict_permissions(&path)?;
+                tokens
+            }
+            Err(AppError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            cipher,
+            tokens: Mutex::new(tokens),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenStorage for FileTokenStorage {
+    async fn set(&self, scopes: &[&str], token: TokenInfo) -> Result<(), TokenStorageError> {
+        let mut tokens = self.tokens.lock().await;
+        match tokens.iter_mut().find(|t| same_scopes(&t.scopes, scopes)) {
+            Some(entry) => entry.token = token,
+            None => {
+                // A token for more scopes (after `auth --add-scope`) supersedes
+                // tokens for any subset of them.
+                tokens.retain(|t| !t.scopes.iter().all(|g| scopes.contains(&g.as_str())));
+                tokens.push(StoredToken {
+                    scopes: scopes.iter().map(|s| s.to_string()).collect(),
+                    token,
+                });
+            }
+        }
+
+        let json = serde_json::to_vec(&*tokens)
+            .map_err(|e| TokenStorageError::Other(e.to_string().into()))?;
+        let data = crypto::seal(self.cipher.as_deref(), &json)
+            .map_err(|e| TokenStorageError::Other(e.to_string().into()))?;
+        crypto::write_private(&self.path, &data)?;
+        Ok(())
+    }
+
+    async fn get(&self, scopes: &[&str]) -> Option<TokenInfo> {
+        let tokens = self.tokens.lock().await;
+        // Prefer an exact match, then any token granted for a superset of the scopes.
+        tokens
+            .iter()
+            .find(|t| same_scopes(&t.scopes, scopes))
+            .or_else(|| {
+                tokens
+                    .iter()
+                    .find(|t| scopes.iter().all(|s| t.scopes.iter().any(|g| g == s)))
+            })
+            .map(|t| t.token.clone())
+    }
+}
+
+fn same_scopes(stored: &[String], requested: &[&str]) -> bool {
+    stored.len() == requested.len() && requested.iter().all(|s| stored.iter().any(|g| g == s))
+}
@@ -1,36 +1,241 @@
 use std::sync::Arc;
 
+use chrono::Utc;
+use chrono_tz::Tz;
 use rmcp::handler::server::router::tool::ToolRouter;
 use rmcp::handler::server::wrapper::Parameters;
 use rmcp::model::*;
-use rmcp::{tool, tool_handler, tool_router, ServerHandler};
+use rmcp::service::RequestContext;
+use rmcp::{tool, tool_handler, tool_router, RoleServer, ServerHandler};
 use schemars::JsonSchema;
 use serde::Deserialize;
 
-use crate::calendar::CalendarClient;
-use crate::classroom::ClassroomClient;
-use crate::drive::DriveClient;
+use serde_json::{json, Value};
+
+use crate::accounts::{Account, AccountRegistry};
+use crate::cache::CacheScope;
+use crate::calendar::{EventFields, EventQuery};
+use crate::dates::parse_time;
+use crate::drive::parse_file_id;
+use crate::due_dates;
+use crate::error::AppError;
+use crate::export::{self, Destination};
+use crate::free_time;
+use crate::scopes::{ToolGroup, TOOL_GROUPS};
+use crate::session;
+use crate::whats_new;
+
+const ACCOUNT_DESCRIPTION: &str =
+    "Account to use: a profile name as listed by the `accounts` tool \
+     (or a user's email address when a delegating service account is configured). \
+     Defaults to the default account.";
+
+const REFRESH_DESCRIPTION: &str =
+    "Skip the cache and fetch fresh data from Google (default: false)";
 
 #[derive(Debug, Clone)]
 pub struct GoogleService {
-    client: Arc<ClassroomClient>,
-    drive_client: Arc<DriveClient>,
-    calendar_client: Arc<CalendarClient>,
+    accounts: Arc<AccountRegistry>,
     tool_router: ToolRouter<Self>,
 }
 
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AccountParam {
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct CourseIdParam {
     #[schemars(description = "The ID of the course")]
     pub course_id: String,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CourseExportParam {
+    #[schemars(description = "The ID of the course")]
+    pub course_id: String,
+    #[schemars(
+        description = "Directory to write the export to, or a path ending in .zip for a zip file, \
+                       relative to exports/ in the account's config directory. Defaults to a \
+                       directory named after the course."
+    )]
+    pub path: Option<String>,
+    #[schemars(
+        description = "Write over an existing file or a directory that isn't empty (default: false)"
+    )]
+    pub overwrite: Option<bool>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct WhatsNewParam {
+    #[schemars(description = "The ID of the course to check. Omit to check every active course.")]
+    pub course_id: Option<String>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SearchParam {
+    #[schemars(
+        description = "Words to search for. Supports \"exact phrases\", +required and -excluded words, \
+                       and title: to search titles only."
+    )]
+    pub query: String,
+    #[schemars(description = "Only search this course")]
+    pub course_id: Option<String>,
+    #[schemars(description = "Number of results to return (default: 10, max: 50)")]
+    pub limit: Option<usize>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+}
+
+// The time window and filters shared by `calendar_events` and `agenda`.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct EventWindowParam {
+    #[schemars(
+        description = "Start of the time window: an RFC 3339 timestamp, a date (YYYY-MM-DD, \
+                       read in the calendar's time zone) or words like 'today', 'last tuesday' or '2 weeks ago'. \
+                       Default: now."
+    )]
+    pub time_min: Option<String>,
+    #[schemars(
+        description = "End of the time window, in the same forms as time_min; a date includes \
+                       the whole day. Default: days_ahead days after time_min."
+    )]
+    pub time_max: Option<String>,
+    #[schemars(
+        description = "Number of days ahead to fetch events when time_max is not given (default: 7)"
+    )]
+    pub days_ahead: Option<u32>,
+    #[schemars(
+        description = "Free text to search for in the events' summary, description, location \
+                       and attendees"
+    )]
+    pub q: Option<String>,
+    #[schemars(
+        description = "Most events to return from a calendar per call (default: 100, max: 2500)"
+    )]
+    pub max_results: Option<u32>,
+    #[schemars(description = "Include cancelled events (default: false)")]
+    pub show_deleted: Option<bool>,
+}
+
+impl EventWindowParam {
+    /// The query, with times read relative to now in `tz`.
+    fn event_query(&self, tz: Tz, page_token: Option<String>) -> Result<EventQuery, AppError> {
+        let now = Utc::now().with_timezone(&tz);
+        let defaults = EventQuery::default();
+        let query = EventQuery {
+            time_min: self
+                .time_min
+                .as_deref()
+                .map(|t| parse_time(t, &now, false))
+                .transpose()?,
+            time_max: self
+                .time_max
+                .as_deref()
+                .map(|t| parse_time(t, &now, true))
+                .transpose()?,
+            days_ahead: self.days_ahead.unwrap_or(defaults.days_ahead),
+            q: self.q.clone().filter(|q| !q.trim().is_empty()),
+            max_results: self
+                .max_results
+                .unwrap_or(defaults.max_results)
+                .clamp(1, EventQuery::MAX_RESULTS),
+            page_token,
+            show_deleted: self.show_deleted.unwrap_or(false),
+        };
+        if let (Some(start), Some(end)) = (query.time_min, query.time_max) {
+            if end <= start {
+                return Err(AppError::InvalidInput(
+                    "time_max must be after time_min".into(),
+                ));
+            }
+        }
+        Ok(query)
+    }
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct CalendarEventsParam {
     #[schemars(description = "Calendar ID (use 'primary' for the user's main calendar)")]
     pub calendar_id: String,
-    #[schemars(description = "Number of days ahead to fetch events (default: 7)")]
+    #[serde(flatten)]
+    pub window: EventWindowParam,
+    #[schemars(description = "The nextPageToken of a previous call, to fetch the next page")]
+    pub page_token: Option<String>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AgendaParam {
+    #[schemars(
+        description = "IDs of the calendars to include, as listed by the calendars tool. \
+                       Default: every calendar."
+    )]
+    pub calendar_ids: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub window: EventWindowParam,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct FindFreeTimeParam {
+    #[schemars(
+        description = "IDs of the calendars whose events count as busy (default: ['primary'])"
+    )]
+    pub calendar_ids: Option<Vec<String>>,
+    #[schemars(
+        description = "Start of the search: an RFC 3339 timestamp, a date (YYYY-MM-DD) or words \
+                       like 'tomorrow' or 'next monday', read in the user's time zone. Default: now."
+    )]
+    pub time_min: Option<String>,
+    #[schemars(
+        description = "End of the search, in the same forms as time_min; a date includes the \
+                       whole day. Default: days_ahead days after time_min."
+    )]
+    pub time_max: Option<String>,
+    #[schemars(
+        description = "Number of days to search when time_max is not given (default: 7). \
+                       At most 62 days are searched at a time."
+    )]
     pub days_ahead: Option<u32>,
+    #[schemars(description = "Start of the working hours, HH:MM (default: 09:00)")]
+    pub work_start: Option<String>,
+    #[schemars(description = "End of the working hours, HH:MM (default: 17:00)")]
+    pub work_end: Option<String>,
+    #[schemars(description = "Also offer slots on Saturdays and Sundays (default: false)")]
+    pub include_weekends: Option<bool>,
+    #[schemars(description = "Shortest slot worth returning, in minutes (default: 30)")]
+    pub min_minutes: Option<u32>,
+    #[schemars(description = "Minutes to keep free before and after each event (default: 0)")]
+    pub buffer_minutes: Option<u32>,
+    #[schemars(
+        description = "IANA time zone for working hours and results, e.g. 'Europe/Berlin' \
+                       (default: the primary calendar's time zone)"
+    )]
+    pub time_zone: Option<String>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -39,6 +244,133 @@ pub struct CalendarEventDetailParam {
     pub calendar_id: String,
     #[schemars(description = "The event ID")]
     pub event_id: String,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
+}
+
+// The event fields shared by `calendar_create_event` and `calendar_update_event`.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct EventDetailsParam {
+    #[schemars(
+        description = "End, in the same forms as start; for an all-day event, the last day. \
+                       Default: an hour after a timed start, the same day for an all-day event, \
+                       or when updating, the event's current length."
+    )]
+    pub end: Option<String>,
+    #[schemars(description = "Whether start and end are days rather than times (default: false)")]
+    pub all_day: Option<bool>,
+    #[schemars(description = "Event description")]
+    pub description: Option<String>,
+    #[schemars(description = "Event location")]
+    pub location: Option<String>,
+    #[schemars(
+        description = "IANA time zone to read start and end in, e.g. 'Europe/Berlin' \
+                       (default: the calendar's time zone)"
+    )]
+    pub time_zone: Option<String>,
+    #[schemars(description = "Email addresses of the guests (replaces the current guest list)")]
+    pub attendees: Option<Vec<String>>,
+    #[schemars(
+        description = "Minutes before the start of each popup reminder, e.g. [10, 60]; \
+                       [] for no reminders. Default: the calendar's default reminders."
+    )]
+    pub reminders: Option<Vec<u32>>,
+    #[schemars(
+        description = "Recurrence rules as RFC 5545 lines, e.g. ['RRULE:FREQ=WEEKLY;BYDAY=MO,WE;COUNT=10']"
+    )]
+    pub recurrence: Option<Vec<String>>,
+    #[schemars(description = "Add a Google Meet link (default: false)")]
+    pub add_meet_link: Option<bool>,
+    #[schemars(description = "Email the guests about the change (default: false)")]
+    pub notify_attendees: Option<bool>,
+}
+
+impl EventDetailsParam {
+    fn fields(&self, summary: Option<String>, start: Option<String>) -> EventFields {
+        EventFields {
+            summary,
+            description: self.description.clone(),
+            location: self.location.clone(),
+            start,
+            end: self.end.clone(),
+            all_day: self.all_day.unwrap_or(false),
+            time_zone: self.time_zone.clone(),
+            attendees: self.attendees.clone(),
+            reminders: self.reminders.clone(),
+            recurrence: self.recurrence.clone(),
+            meet_link: self.add_meet_link.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CalendarCreateEventParam {
+    #[schemars(description = "Calendar ID (default: 'primary', the user's main calendar)")]
+    pub calendar_id: Option<String>,
+    #[schemars(description = "Event title")]
+    pub summary: String,
+    #[schemars(
+        description = "Start: an RFC 3339 timestamp, a date and time (2026-03-02 14:00, read in \
+                       the calendar's time zone) or, for an all-day event, a date or words like \
+                       'tomorrow' or 'next friday'"
+    )]
+    pub start: String,
+    #[serde(flatten)]
+    pub details: EventDetailsParam,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CalendarUpdateEventParam {
+    #[schemars(description = "Calendar ID (default: 'primary', the user's main calendar)")]
+    pub calendar_id: Option<String>,
+    #[schemars(description = "The event ID")]
+    pub event_id: String,
+    #[schemars(description = "New title")]
+    pub summary: Option<String>,
+    #[schemars(description = "New start, in the forms calendar_create_event accepts")]
+    pub start: Option<String>,
+    #[serde(flatten)]
+    pub details: EventDetailsParam,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CalendarDeleteEventParam {
+    #[schemars(description = "Calendar ID (default: 'primary', the user's main calendar)")]
+    pub calendar_id: Option<String>,
+    #[schemars(description = "The event ID; deleting a recurring event deletes every instance")]
+    pub event_id: String,
+    #[schemars(description = "Email the guests about the cancellation (default: false)")]
+    pub notify_attendees: Option<bool>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SyncDueDatesParam {
+    #[schemars(description = "Only sync this course's coursework (default: every active course)")]
+    pub course_id: Option<String>,
+    #[schemars(
+        description = "Calendar to put the events on. Default: a dedicated 'Classroom due dates' \
+                       calendar, created on the first run."
+    )]
+    pub calendar_id: Option<String>,
+    #[schemars(
+        description = "Minutes before the due time of each popup reminder (default: [1440, 60], \
+                       a day and an hour before)"
+    )]
+    pub reminders: Option<Vec<u32>>,
+    #[schemars(description = "Only report what would change (default: false)")]
+    pub dry_run: Option<bool>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -47,69 +379,343 @@ pub struct ReadMaterialParam {
         description = "A Google Drive file ID or full URL (e.g. https://docs.google.com/document/d/FILE_ID/edit)"
     )]
     pub file_id_or_url: String,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+    #[schemars(description = REFRESH_DESCRIPTION)]
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClearScope {
+    All,
+    Course,
+    DriveFile,
+    Calendar,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CacheClearParam {
+    #[schemars(description = "What to clear: 'all', 'course', 'drive_file' or 'calendar'")]
+    pub scope: ClearScope,
+    #[schemars(
+        description = "The course ID, Drive file ID/URL or calendar ID to clear. \
+                       Omit to clear everything of that kind."
+    )]
+    pub id: Option<String>,
+    #[schemars(description = ACCOUNT_DESCRIPTION)]
+    pub account: Option<String>,
+}
+
+/// Render a tool result as pretty JSON, or as an `Error: ...` message.
+///
+/// Missing-scope and expired-auth errors are rendered as JSON so the agent can
+/// relay exactly what the user needs to do.
+fn render(result: Result<Value, AppError>) -> String {
+    match result {
+        Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
+        Err(
+            ref e @ AppError::MissingScope {
+                ref account,
+                ref group,
+                ref remedy,
+            },
+        ) => {
+            let body = json!({
+                "error": "missing_scope",
+                "message": e.to_string(),
+                "account": account,
+                "toolGroup": group,
+                "remedy": remedy,
+            });
+            serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("Error: {e}"))
+        }
+        Err(
+            ref e @ AppError::AuthExpired {
+                ref account,
+                ref reason,
+                ref remedy,
+            },
+        ) => {
+            let body = json!({
+                "error": "not_authenticated",
+                "message": e.to_string(),
+                "account": account,
+                "reason": reason,
+                "remedy": remedy,
+            });
+            serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("Error: {e}"))
+        }
+        Err(e) => format!("Error: {e}"),
+    }
 }
 
 #[tool_router]
 impl GoogleService {
-    pub fn new(
-        client: Arc<ClassroomClient>,
-        drive_client: Arc<DriveClient>,
-        calendar_client: Arc<CalendarClient>,
-    ) -> Self {
+    /// Create the service, exposing only the tools of the `enabled` groups.
+    pub fn new(accounts: Arc<AccountRegistry>, enabled: &[ToolGroup]) -> Self {
+        let mut tool_router = Self::tool_router();
+        for (tool, group) in TOOL_GROUPS {
+            if !enabled.contains(group) {
+                tool_router.remove_route(tool);
+            }
+        }
+        if cfg!(not(feature = "semantic-search")) {
+            tool_router.remove_route("semantic_search");
+        }
         Self {
-            client,
-            drive_client,
-            calendar_client,
-            tool_router: Self::tool_router(),
+            accounts,
+            tool_router,
         }
     }
 
+    /// Look up an account and check it has granted the scopes `group` needs.
+    async fn account(
+        &self,
+        name: Option<&str>,
+        group: ToolGroup,
+    ) -> Result<Arc<Account>, AppError> {
+        let account = self.accounts.get(name).await?;
+        account.require(group)?;
+        Ok(account)
+    }
+
+    #[tool(
+        description = "List the configured Google accounts (profiles) and the email address each is signed in as"
+    )]
+    async fn accounts(&self) -> String {
+        render(Ok(self.accounts.describe().await))
+    }
+
+    #[tool(
+        description = "Report Google API request counts, retries, rate-limit throttling and cache hit ratios since the server started"
+    )]
+    async fn usage_stats(&self) -> String {
+        render(Ok(self.accounts.usage().report()))
+    }
+
+    #[tool(
+        description = "Clear cached Google data so the next call fetches it fresh. \
+                        Scope 'course' clears one course's data (or all Classroom data without an id), \
+                        'drive_file' one Drive file, 'calendar' one calendar's events, 'all' everything."
+    )]
+    async fn cache_clear(&self, Parameters(params): Parameters<CacheClearParam>) -> String {
+        render(
+            async {
+                let account = self.accounts.get(params.account.as_deref()).await?;
+                let id = params.id.filter(|id| !id.trim().is_empty());
+                let scope = match params.scope {
+                    ClearScope::All => CacheScope::All,
+                    ClearScope::Course => CacheScope::Course(id),
+                    ClearScope::DriveFile => {
+                        CacheScope::DriveFile(id.as_deref().map(parse_file_id).transpose()?)
+                    }
+                    ClearScope::Calendar => CacheScope::Calendar(id),
+                };
+                let cleared = account.clear_cache(&scope).await;
+                Ok(json!({ "account": account.profile, "cleared": cleared }))
+            }
+            .await,
+        )
+    }
+
     #[tool(description = "List all Google Classroom courses for the authenticated user")]
-    async fn courses(&self) -> String {
-        match self.client.list_courses().await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+    async fn courses(&self, Parameters(params): Parameters<AccountParam>) -> String {
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                account.classroom.list_courses(refresh).await
+            }
+            .await,
+        )
     }
 
     #[tool(
         description = "Get details for a specific course including recent announcements (up to 20)"
     )]
     async fn course_details(&self, Parameters(params): Parameters<CourseIdParam>) -> String {
-        match self.client.get_course_details(&params.course_id).await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                account
+                    .classroom
+                    .get_course_details(&params.course_id, refresh)
+                    .await
+            }
+            .await,
+        )
     }
 
     #[tool(
         description = "Get assignments (coursework) for a course with student submissions for the first 5 assignments"
     )]
     async fn assignments(&self, Parameters(params): Parameters<CourseIdParam>) -> String {
-        match self.client.get_assignments(&params.course_id).await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                account
+                    .classroom
+                    .get_assignments(&params.course_id, refresh)
+                    .await
+            }
+            .await,
+        )
     }
 
     #[tool(
         description = "Get course work materials (posted resources like documents, links, videos) for a course"
     )]
     async fn course_materials(&self, Parameters(params): Parameters<CourseIdParam>) -> String {
-        match self.client.get_course_materials(&params.course_id).await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                account
+                    .classroom
+                    .get_course_materials(&params.course_id, refresh)
+                    .await
+            }
+            .await,
+        )
     }
 
     #[tool(
         description = "Get topics (modules/sections) for a course that organize coursework and materials"
     )]
     async fn course_topics(&self, Parameters(params): Parameters<CourseIdParam>) -> String {
-        match self.client.get_course_topics(&params.course_id).await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                account
+                    .classroom
+                    .get_course_topics(&params.course_id, refresh)
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "Export a course for safekeeping: a Markdown index by topic, one file per \
+                        coursework item, material and announcement, the attached Drive files \
+                        (Docs and Slides as PDF, Sheets as Excel) and a manifest.json. \
+                        Writes a directory, or a zip file when the path ends in .zip."
+    )]
+    async fn course_export(&self, Parameters(params): Parameters<CourseExportParam>) -> String {
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                let destination = match params.path.as_deref() {
+                    Some(path) => Destination::Exports(path),
+                    None => Destination::Default,
+                };
+                let exported = export::export_course(
+                    &account,
+                    &params.course_id,
+                    destination,
+                    params.overwrite.unwrap_or(false),
+                    params.refresh.unwrap_or(false),
+                )
+                .await?;
+                Ok(serde_json::to_value(exported)?)
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "What changed since the last call: announcements, coursework and materials \
+                        added, updated or removed, and submissions whose state or grade changed. \
+                        Each call moves the checkpoint forward, per course and account. \
+                        Always reads Classroom itself, never the cache, so it takes no refresh flag."
+    )]
+    async fn whats_new(&self, Parameters(params): Parameters<WhatsNewParam>) -> String {
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                whats_new::whats_new(&account, params.course_id.as_deref()).await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "Full-text search of announcements, coursework, materials and the text of \
+                        attached Drive files, across every course fetched so far (run `sync` to \
+                        cover everything). Returns ranked matches with a snippet, a link and the \
+                        IDs to pass to other tools. Searches only the local store, so it takes no \
+                        refresh flag; other tools' refresh or `sync` bring the store up to date."
+    )]
+    async fn search_classroom(&self, Parameters(params): Parameters<SearchParam>) -> String {
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Classroom)
+                    .await?;
+                account
+                    .search
+                    .search(
+                        &params.query,
+                        params.course_id.as_deref(),
+                        params.limit.unwrap_or(10),
+                    )
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "Find passages by meaning rather than exact words, across announcements, \
+                        coursework, materials and the text of attached Drive files fetched so far. \
+                        Returns the best passages with their source, and the passage's byte offset \
+                        and length in the source text for citation. Searches only the local store, \
+                        so it takes no refresh flag; other tools' refresh or `sync` bring the store \
+                        up to date."
+    )]
+    async fn semantic_search(&self, Parameters(params): Parameters<SearchParam>) -> String {
+        // Not routed without the feature (see `new`).
+        #[cfg(not(feature = "semantic-search"))]
+        let result = {
+            let _ = params;
+            Err(AppError::InvalidInput(
+                "semantic search needs the `semantic-search` feature".into(),
+            ))
+        };
+        #[cfg(feature = "semantic-search")]
+        let result = async {
+            let account = self
+                .account(params.account.as_deref(), ToolGroup::Classroom)
+                .await?;
+            account
+                .semantic
+                .search(
+                    &params.query,
+                    params.course_id.as_deref(),
+                    params.limit.unwrap_or(10),
+                )
+                .await
         }
+        .await;
+        render(result)
     }
 
     #[tool(
@@ -117,37 +723,119 @@ impl GoogleService {
                         Accepts a file ID or full Google Drive/Docs URL. \
                         Google Workspace documents are exported to text; binary files return metadata only."
     )]
-    async fn read_material(
-        &self,
-        Parameters(params): Parameters<ReadMaterialParam>,
-    ) -> String {
-        match self.drive_client.read_material(&params.file_id_or_url).await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+    async fn read_material(&self, Parameters(params): Parameters<ReadMaterialParam>) -> String {
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Drive)
+                    .await?;
+                account
+                    .drive
+                    .read_material(&params.file_id_or_url, refresh)
+                    .await
+            }
+            .await,
+        )
     }
 
     #[tool(description = "List all Google Calendars the authenticated user has access to")]
-    async fn calendars(&self) -> String {
-        match self.calendar_client.list_calendars().await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+    async fn calendars(&self, Parameters(params): Parameters<AccountParam>) -> String {
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Calendar)
+                    .await?;
+                account.calendar.list_calendars(refresh).await
+            }
+            .await,
+        )
     }
 
     #[tool(
-        description = "List upcoming events on a Google Calendar. \
-                        Use calendar_id 'primary' for the user's main calendar."
+        description = "List events on a Google Calendar in a time window (by default the next \
+                        7 days), optionally matching a search text. \
+                        Use calendar_id 'primary' for the user's main calendar. \
+                        Returns the window, the events and a nextPageToken when there are more."
     )]
-    async fn calendar_events(
-        &self,
-        Parameters(params): Parameters<CalendarEventsParam>,
-    ) -> String {
-        let days = params.days_ahead.unwrap_or(7);
-        match self.calendar_client.list_events(&params.calendar_id, days).await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+    async fn calendar_events(&self, Parameters(params): Parameters<CalendarEventsParam>) -> String {
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Calendar)
+                    .await?;
+                let tz = account.calendar.zone(&params.calendar_id, refresh).await?;
+                let query = params.window.event_query(tz, params.page_token.clone())?;
+                account
+                    .calendar
+                    .list_events(&params.calendar_id, &query, refresh)
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "One agenda merged from several Google Calendars (by default all of them), \
+                        ordered by start time, for a time window (by default the next 7 days). \
+                        Each event names its calendar and color; an event on several calendars \
+                        is listed once, with the others under alsoOn."
+    )]
+    async fn agenda(&self, Parameters(params): Parameters<AgendaParam>) -> String {
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Calendar)
+                    .await?;
+                let tz = account.calendar.zone("primary", refresh).await?;
+                let query = params.window.event_query(tz, None)?;
+                account
+                    .calendar
+                    .agenda(params.calendar_ids.as_deref(), &query, refresh)
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "Find open time slots across Google Calendars, e.g. for study sessions. \
+                        Uses the calendars' free/busy information and returns the slots within \
+                        working hours, in the user's time zone, that are long enough and keep \
+                        the requested buffer around events."
+    )]
+    async fn find_free_time(&self, Parameters(params): Parameters<FindFreeTimeParam>) -> String {
+        render(
+            async {
+                let request = free_time::Request {
+                    calendar_ids: params
+                        .calendar_ids
+                        .clone()
+                        .filter(|ids| !ids.is_empty())
+                        .unwrap_or_else(|| vec!["primary".into()]),
+                    time_min: params.time_min.clone(),
+                    time_max: params.time_max.clone(),
+                    days_ahead: params.days_ahead.unwrap_or(7),
+                    hours: free_time::Hours::parse(
+                        params.work_start.as_deref().unwrap_or("09:00"),
+                        params.work_end.as_deref().unwrap_or("17:00"),
+                        params.include_weekends.unwrap_or(false),
+                    )?,
+                    min_minutes: params.min_minutes.unwrap_or(30),
+                    buffer_minutes: params.buffer_minutes.unwrap_or(0),
+                    time_zone: params.time_zone.clone(),
+                    refresh: params.refresh.unwrap_or(false),
+                };
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Calendar)
+                    .await?;
+                free_time::find_free_time(&account, &request).await
+            }
+            .await,
+        )
     }
 
     #[tool(description = "Get full details for a specific Google Calendar event")]
@@ -155,10 +843,127 @@ impl GoogleService {
         &self,
         Parameters(params): Parameters<CalendarEventDetailParam>,
     ) -> String {
-        match self.calendar_client.get_event(&params.calendar_id, &params.event_id).await {
-            Ok(val) => serde_json::to_string_pretty(&val).unwrap_or_else(|e| e.to_string()),
-            Err(e) => format!("Error: {e}"),
-        }
+        let refresh = params.refresh.unwrap_or(false);
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::Calendar)
+                    .await?;
+                account
+                    .calendar
+                    .get_event(&params.calendar_id, &params.event_id, refresh)
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "Create a Google Calendar event: timed or all-day, with optional guests, \
+                        reminders, recurrence rules and a Google Meet link. Returns the event."
+    )]
+    async fn calendar_create_event(
+        &self,
+        Parameters(params): Parameters<CalendarCreateEventParam>,
+    ) -> String {
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::CalendarWrite)
+                    .await?;
+                let calendar_id = params.calendar_id.as_deref().unwrap_or("primary");
+                let fields = params
+                    .details
+                    .fields(Some(params.summary.clone()), Some(params.start.clone()));
+                let notify = params.details.notify_attendees.unwrap_or(false);
+                account
+                    .calendar
+                    .create_event(calendar_id, &fields, notify)
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "Change a Google Calendar event. Only the fields given are changed; \
+                        moving the start keeps the event's length unless end is given. \
+                        For a recurring event, pass the recurring event's ID to change every \
+                        instance or an instance's ID to change just that one. Returns the event."
+    )]
+    async fn calendar_update_event(
+        &self,
+        Parameters(params): Parameters<CalendarUpdateEventParam>,
+    ) -> String {
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::CalendarWrite)
+                    .await?;
+                let calendar_id = params.calendar_id.as_deref().unwrap_or("primary");
+                let fields = params
+                    .details
+                    .fields(params.summary.clone(), params.start.clone());
+                let notify = params.details.notify_attendees.unwrap_or(false);
+                account
+                    .calendar
+                    .update_event(calendar_id, &params.event_id, &fields, notify)
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(description = "Delete a Google Calendar event")]
+    async fn calendar_delete_event(
+        &self,
+        Parameters(params): Parameters<CalendarDeleteEventParam>,
+    ) -> String {
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::CalendarWrite)
+                    .await?;
+                let calendar_id = params.calendar_id.as_deref().unwrap_or("primary");
+                let notify = params.notify_attendees.unwrap_or(false);
+                account
+                    .calendar
+                    .delete_event(calendar_id, &params.event_id, notify)
+                    .await
+            }
+            .await,
+        )
+    }
+
+    #[tool(
+        description = "Put Classroom due dates on Google Calendar: one event per coursework due \
+                        date, with popup reminders, in a dedicated calendar. Reruns update \
+                        changed events and remove those of deleted or turned-in work. \
+                        Use dry_run to preview the changes."
+    )]
+    async fn sync_due_dates_to_calendar(
+        &self,
+        Parameters(params): Parameters<SyncDueDatesParam>,
+    ) -> String {
+        render(
+            async {
+                let account = self
+                    .account(params.account.as_deref(), ToolGroup::CalendarWrite)
+                    .await?;
+                let request = due_dates::Request {
+                    course_id: params.course_id.clone(),
+                    calendar_id: params.calendar_id.clone(),
+                    reminders: params
+                        .reminders
+                        .clone()
+                        .unwrap_or_else(|| due_dates::DEFAULT_REMINDERS.to_vec()),
+                    dry_run: params.dry_run.unwrap_or(false),
+                    refresh: params.refresh.unwrap_or(false),
+                };
+                due_dates::sync_due_dates(&account, &request).await
+            }
+            .await,
+        )
     }
 }
 
@@ -169,12 +974,47 @@ impl ServerHandler for GoogleService {
             instructions: Some(
                 "Personal Google MCP server — provides access to Google services including \
                  Classroom (courses, announcements, assignments, materials), \
-                 Calendar (list calendars, upcoming events, event details), \
-                 Drive (file reading), and more services coming soon (Gmail, etc.)."
+                 Calendar (list calendars, events, a merged agenda, event details, free time; \
+                 when the opt-in calendar-write group is enabled, also creating, updating and \
+                 deleting events and putting Classroom due dates on a calendar), \
+                 Drive (file reading), and more services coming soon (Gmail, etc.). \
+                 Several Google accounts can be configured; every tool takes an optional \
+                 `account` parameter (see the `accounts` tool)."
                     .into(),
             ),
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities: ServerCapabilities::builder()
+                .enable_logging()
+                .enable_tools()
+                .build(),
             ..Default::default()
         }
     }
+
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), ErrorData> {
+        session::set_log_level(request.level);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tools that work regardless of which groups are enabled.
+    const UNGROUPED_TOOLS: &[&str] = &["accounts", "usage_stats", "cache_clear"];
+
+    #[test]
+    fn every_tool_declares_a_group() {
+        for tool in GoogleService::tool_router().list_all() {
+            let name = tool.name.as_ref();
+            assert!(
+                UNGROUPED_TOOLS.contains(&name) || TOOL_GROUPS.iter().any(|(t, _)| *t == name),
+                "tool `{name}` is missing from scopes::TOOL_GROUPS"
+            );
+        }
+    }
 }
@@ -0,0 +1,207 @@
+//! Client-side rate limiting and request accounting per Google API.
+//!
+//! Each API has one token bucket shared by every account's client, so
+//! fan-out tools (per-course loops, submission fetching) stay under the
+//! per-user and per-project quotas instead of tripping 429s.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use crate::config::{RateLimit, RateLimits};
+
+/// A token bucket that hands out reservations: callers that find it empty
+/// are told how long to wait for their token, so waiters are served in order.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        let capacity = f64::from(limit.burst.max(1));
+        Self {
+            rate: limit.requests_per_second,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// Take a token, returning how long to wait before using it.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Rate limiter and counters for one Google API.
+#[derive(Debug)]
+pub struct ApiUsage {
+    name: &'static str,
+    bucket: Option<Mutex<TokenBucket>>,
+    limit: RateLimit,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    retries: AtomicU64,
+    throttled: AtomicU64,
+    throttled_ms: AtomicU64,
+    rate_limited: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl ApiUsage {
+    pub fn new(name: &'static str, limit: RateLimit) -> Self {
+        let bucket = (limit.requests_per_second > 0.0)
+            .then(|| Mutex::new(TokenBucket::new(limit, Instant::now())));
+        Self {
+            name,
+            bucket,
+            limit,
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            throttled_ms: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait for the rate limiter, then count a request.
+    pub async fn acquire(&self) {
+        if let Some(bucket) = &self.bucket {
+            let wait = bucket
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .reserve(Instant::now());
+            if !wait.is_zero() {
+                self.throttled.fetch_add(1, Ordering::Relaxed);
+                self.throttled_ms
+                    .fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+                tracing::debug!("{} rate limit: waiting {wait:?}", self.name);
+                tokio::time::sleep(wait).await;
+            }
+        }
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a call that failed after all retries.
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a retried attempt, and whether Google rejected it for quota.
+    pub fn record_retry(&self, rate_limited: bool) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+        if rate_limited {
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn report(&self) -> Value {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let (hits, misses) = (get(&self.cache_hits), get(&self.cache_misses));
+        let hit_ratio = (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64);
+        json!({
+            "requests": get(&self.requests),
+            "failures": get(&self.failures),
+            "retries": get(&self.retries),
+            "throttling": {
+                "clientWaits": get(&self.throttled),
+                "clientWaitMs": get(&self.throttled_ms),
+                "serverRateLimited": get(&self.rate_limited),
+            },
+            "cache": {
+                "hits": hits,
+                "misses": misses,
+                "hitRatio": hit_ratio,
+            },
+            "rateLimit": {
+                "requestsPerSecond": self.limit.requests_per_second,
+                "burst": self.limit.burst,
+            },
+        })
+    }
+}
+
+/// Usage of every Google API since startup.
+#[derive(Debug)]
+pub struct Usage {
+    started: DateTime<Utc>,
+    pub classroom: Arc<ApiUsage>,
+    pub drive: Arc<ApiUsage>,
+    pub calendar: Arc<ApiUsage>,
+}
+
+impl Usage {
+    pub fn new(limits: &RateLimits) -> Self {
+        Self {
+            started: Utc::now(),
+            classroom: Arc::new(ApiUsage::new("classroom", limits.classroom)),
+            drive: Arc::new(ApiUsage::new("drive", limits.drive)),
+            calendar: Arc::new(ApiUsage::new("calendar", limits.calendar)),
+        }
+    }
+
+    /// Counters for the `usage_stats` tool.
+    pub fn report(&self) -> Value {
+        json!({
+            "since": self.started,
+            "classroom": self.classroom.report(),
+            "drive": self.drive.report(),
+            "calendar": self.calendar.report(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_burst_then_spaces_requests() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(
+            RateLimit {
+                requests_per_second: 2.0,
+                burst: 3,
+            },
+            start,
+        );
+        for _ in 0..3 {
+            assert_eq!(bucket.reserve(start), Duration::ZERO);
+        }
+        assert_eq!(bucket.reserve(start), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(start), Duration::from_millis(1000));
+
+        // After two seconds the backlog is paid off and a token has refilled.
+        assert_eq!(
+            bucket.reserve(start + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! The `whats_new` tool: what changed in Classroom since the last check.
+//!
+//! Each check lists a course's announcements, coursework, materials and
+//! submissions and reduces them to a snapshot: per object, Google's
+//! `updateTime`, a title, a link and, for submissions, the state and grade.
+//! The snapshot is compared with the course's checkpoint in the store and
+//! then becomes the new checkpoint. A course checked for the first time is
+//! compared with whatever the store already holds of it instead.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::accounts::Account;
+use crate::classroom::announcement_title;
+use crate::error::AppError;
+use crate::scopes::ToolGroup;
+use crate::store::{Checkpoint, RecordKind};
+
+/// The kinds of object tracked, in the order they are reported.
+const KINDS: &[RecordKind] = &[
+    RecordKind::Announcement,
+    RecordKind::CourseWork,
+    RecordKind::Material,
+    RecordKind::Submission,
+];
+
+/// What a check remembers of one object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grade: Option<f64>,
+}
+
+/// Entries by kind, then by ID.
+type Snapshot = BTreeMap<String, BTreeMap<String, Entry>>;
+
+/// An object that was added, updated or removed.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Change {
+    kind: String,
+    id: String,
+    #[serde(flatten)]
+    entry: Entry,
+    /// For updates, what the last check saw.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<Entry>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Diff {
+    added: Vec<Change>,
+    updated: Vec<Change>,
+    removed: Vec<Change>,
+}
+
+impl Diff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Report what changed in one course, or in every active course, since the
+/// last check, and move the checkpoint forward.
+pub async fn whats_new(account: &Account, course_id: Option<&str>) -> Result<Value, AppError> {
+    if account.is_offline() {
+        return Err(AppError::FailedPrecondition(
+            "whats_new compares against Google, which isn't possible offline".into(),
+        ));
+    }
+    account.require(ToolGroup::Classroom)?;
+    let courses = match course_id {
+        Some(id) => vec![(id.to_string(), None)],
+        None => account
+            .classroom
+            .all_courses()
+            .await?
+            .into_iter()
+            .filter(|c| c["courseState"] == "ACTIVE")
+            .filter_map(|c| {
+                Some((
+                    c["id"].as_str()?.to_string(),
+                    c["name"].as_str().map(String::from),
+                ))
+            })
+            .collect(),
+    };
+
+    let checked_at = Utc::now();
+    let mut changed = Vec::new();
+    let mut unchanged = Vec::new();
+    let mut first_checks = Vec::new();
+    let mut failures = Vec::new();
+    for (id, name) in courses {
+        let label = name.clone().unwrap_or_else(|| id.clone());
+        let snapshot = match snapshot(account, &id).await {
+            Ok(snapshot) => snapshot,
+            // Asked about one course: fail rather than report nothing.
+            Err(e) if course_id.is_some() => return Err(e),
+            Err(e) => {
+                failures.push(format!("{label}: {e}"));
+                continue;
+            }
+        };
+        let (since, previous) = match account.store.checkpoint(&id)? {
+            Some(checkpoint) => (
+                Some(checkpoint.checked_at.to_rfc3339()),
+                serde_json::from_value(checkpoint.snapshot)?,
+            ),
+            None => (None, stored_snapshot(account, &id)?),
+        };
+        account.store.save_checkpoint(
+            &id,
+            &Checkpoint {
+                checked_at,
+                snapshot: serde_json::to_value(&snapshot)?,
+            },
+        )?;
+        if since.is_none() && previous.values().all(BTreeMap::is_empty) {
+            first_checks.push(label);
+            continue;
+        }
+        let diff = diff(&previous, &snapshot);
+        if diff.is_empty() {
+            unchanged.push(label);
+            continue;
+        }
+        changed.push(json!({
+            "courseId": id,
+            "name": name,
+            "since": since,
+            "added": diff.added,
+            "updated": diff.updated,
+            "removed": diff.removed,
+        }));
+    }
+
+    Ok(json!({
+        "checkedAt": checked_at.to_rfc3339(),
+        "courses": changed,
+        "unchanged": unchanged,
+        // Nothing to compare with yet; changes are reported from the next check.
+        "firstCheck": first_checks,
+        "failures": failures,
+    }))
+}
+
+/// The current state of a course at Google.
+async fn snapshot(account: &Account, course_id: &str) -> Result<Snapshot, AppError> {
+    let classroom = &account.classroom;
+    let course_work = classroom.all_course_work(course_id).await?;
+    let listings = [
+        (
+            RecordKind::Announcement,
+            classroom.all_announcements(course_id).await?,
+        ),
+        (
+            RecordKind::Material,
+            classroom.all_materials(course_id).await?,
+        ),
+        (
+            RecordKind::Submission,
+            classroom.all_submissions(course_id).await?,
+        ),
+        (RecordKind::CourseWork, course_work.clone()),
+    ];
+    Ok(build_snapshot(&listings, &course_work))
+}
+
+/// What the store held of a course before its first check.
+fn stored_snapshot(account: &Account, course_id: &str) -> Result<Snapshot, AppError> {
+    let mut listings: Vec<(RecordKind, Vec<Value>)> = Vec::new();
+    for kind in KINDS {
+        let items = account
+            .store
+            .records(*kind, Some(course_id))?
+            .into_iter()
+            .filter(|record| record.removed_at.is_none())
+            .map(|record| record.value)
+            .collect();
+        listings.push((*kind, items));
+    }
+    let course_work = listings
+        .iter()
+        .find(|(kind, _)| *kind == RecordKind::CourseWork)
+        .map(|(_, items)| items.clone())
+        .unwrap_or_default();
+    Ok(build_snapshot(&listings, &course_work))
+}
+
+fn build_snapshot(listings: &[(RecordKind, Vec<Value>)], course_work: &[Value]) -> Snapshot {
+    let titles: HashMap<&str, &str> = course_work
+        .iter()
+        .filter_map(|w| Some((w["id"].as_str()?, w["title"].as_str()?)))
+        .collect();
+    let mut snapshot = Snapshot::new();
+    for kind in KINDS {
+        snapshot.insert(kind.as_str().to_string(), BTreeMap::new());
+    }
+    for (kind, items) in listings {
+        let entries = snapshot.entry(kind.as_str().to_string()).or_default();
+        for item in items {
+            let Some(id) = item["id"].as_str() else {
+                continue;
+            };
+            entries.insert(id.to_string(), entry(*kind, item, &titles));
+        }
+    }
+    snapshot
+}
+
+fn entry(kind: RecordKind, item: &Value, course_work_titles: &HashMap<&str, &str>) -> Entry {
+    let title = match kind {
+        RecordKind::Announcement => announcement_title(item),
+        RecordKind::Submission => {
+            let work = item["courseWorkId"].as_str().unwrap_or_default();
+            let title = course_work_titles.get(work).copied().unwrap_or(work);
+            format!("Submission for {title}")
+        }
+        _ => item["title"].as_str().unwrap_or_default().to_string(),
+    };
+    Entry {
+        title,
+        update_time: item["updateTime"].as_str().map(String::from),
+        link: item["alternateLink"].as_str().map(String::from),
+        state: item["state"].as_str().map(String::from),
+        grade: item["assignedGrade"].as_f64(),
+    }
+}
+
+fn diff(previous: &Snapshot, current: &Snapshot) -> Diff {
+    let empty = BTreeMap::new();
+    let mut diff = Diff::default();
+    for kind in KINDS {
+        let kind = kind.as_str();
+        let before = previous.get(kind).unwrap_or(&empty);
+        let after = current.get(kind).unwrap_or(&empty);
+        let change = |id: &String, entry: &Entry, previous: Option<&Entry>| Change {
+            kind: kind.to_string(),
+            id: id.clone(),
+            entry: entry.clone(),
+            previous: previous.cloned(),
+        };
+        for (id, entry) in after {
+            match before.get(id) {
+                None => diff.added.push(change(id, entry, None)),
+                Some(old)
+                    if old.update_time != entry.update_time
+                        || old.state != entry.state
+                        || old.grade != entry.grade =>
+                {
+                    diff.updated.push(change(id, entry, Some(old)))
+                }
+                Some(_) => {}
+            }
+        }
+        for (id, entry) in before {
+            if !after.contains_key(id) {
+                diff.removed.push(change(id, entry, None));
+            }
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_changes_between_snapshots() {
+        let work =
+            vec![json!({"id": "w1", "title": "Essay", "updateTime": "2026-09-01T00:00:00Z"})];
+        let before = build_snapshot(
+            &[
+                (RecordKind::CourseWork, work.clone()),
+                (
+                    RecordKind::Submission,
+                    vec![json!({"id": "s1", "courseWorkId": "w1", "state": "CREATED",
+                                "updateTime": "2026-09-01T00:00:00Z"})],
+                ),
+                (
+                    RecordKind::Announcement,
+                    vec![
+                        json!({"id": "a1", "text": "\nNo class Friday", "updateTime": "2026-09-01T00:00:00Z"}),
+                    ],
+                ),
+            ],
+            &work,
+        );
+        let after = build_snapshot(
+            &[
+                (RecordKind::CourseWork, work.clone()),
+                (
+                    RecordKind::Submission,
+                    vec![
+                        json!({"id": "s1", "courseWorkId": "w1", "state": "RETURNED",
+                                "assignedGrade": 9.0, "updateTime": "2026-09-03T00:00:00Z"}),
+                    ],
+                ),
+                (
+                    RecordKind::Material,
+                    vec![
+                        json!({"id": "m1", "title": "Slides", "updateTime": "2026-09-02T00:00:00Z"}),
+                    ],
+                ),
+            ],
+            &work,
+        );
+
+        let diff = diff(&before, &after);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].id, "m1");
+        assert_eq!(diff.updated.len(), 1);
+        let submission = &diff.updated[0];
+        assert_eq!(submission.entry.title, "Submission for Essay");
+        assert_eq!(submission.entry.grade, Some(9.0));
+        assert_eq!(
+            submission.previous.as_ref().unwrap().state.as_deref(),
+            Some("CREATED")
+        );
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].entry.title, "No class Friday");
+
+        let round_trip: Snapshot =
+            serde_json::from_value(serde_json::to_value(&after).unwrap()).unwrap();
+        assert!(super::diff(&after, &round_trip).is_empty());
+    }
+}